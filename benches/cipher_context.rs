@@ -0,0 +1,36 @@
+//! Compares one-shot `AESCiphertext` encryption, which sets up the key schedule on
+//! every call, with a reused `CipherContext`.
+//!
+//! Run with `cargo bench --bench cipher_context`.
+
+use secure_channel::aes::{AESCiphertext, CipherContext, AES_KEY_SIZE};
+use secure_channel::capabilities::AeadAlgorithm;
+use std::time::Instant;
+
+const ITERATIONS: usize = 100_000;
+
+fn measure(name: &str, encrypt: impl Fn() -> AESCiphertext) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        std::hint::black_box(encrypt());
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<16} {:>10.2?} per call",
+        name,
+        elapsed / ITERATIONS as u32
+    );
+}
+
+fn main() {
+    let key = [0x42u8; AES_KEY_SIZE];
+    let message = b"Short session message";
+    let context = CipherContext::new(AeadAlgorithm::Aes256Gcm, &key);
+
+    println!("{} iterations", ITERATIONS);
+    measure("one-shot", || {
+        AESCiphertext::encrypt_with_key(&key, message).unwrap()
+    });
+    measure("cipher context", || context.encrypt(message).unwrap());
+}
@@ -0,0 +1,45 @@
+//! Compares the throughput of SHA-512 and BLAKE2b-512 when hashing to a scalar, and the
+//! cost of signing with each.
+//!
+//! Run with `cargo bench --features blake2 --bench hash_throughput`.
+
+use secure_channel::hash::HashAlgorithm;
+use secure_channel::keys::KeyPair;
+use secure_channel::schnorr::SchnorrSignature;
+use std::time::Instant;
+
+const ITERATIONS: usize = 10_000;
+const INPUT_SIZE: usize = 64 * 1024;
+
+fn measure(hash: HashAlgorithm, input: &[u8], keypair: &KeyPair) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        std::hint::black_box(hash.hash_to_scalar(&[input]));
+    }
+    let elapsed = start.elapsed();
+    let throughput = (ITERATIONS * input.len()) as f64 / elapsed.as_secs_f64() / 1e6;
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        std::hint::black_box(
+            SchnorrSignature::sign_with_hash(hash, b"Short message", &keypair.private_key).unwrap(),
+        );
+    }
+    let signing = start.elapsed() / ITERATIONS as u32;
+
+    println!(
+        "{:<8} {:>10.1} MB/s {:>10.2?} per signature",
+        format!("{:?}", hash),
+        throughput,
+        signing
+    );
+}
+
+fn main() {
+    let input = vec![0x42u8; INPUT_SIZE];
+    let keypair = KeyPair::generate().unwrap();
+
+    println!("{} iterations over {} bytes", ITERATIONS, INPUT_SIZE);
+    measure(HashAlgorithm::Sha512, &input, &keypair);
+    measure(HashAlgorithm::Blake2b, &input, &keypair);
+}
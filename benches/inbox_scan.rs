@@ -0,0 +1,57 @@
+//! Compares trial-decrypting every ciphertext of an inbox with `InboxScanner`, which skips
+//! ciphertexts hinted for other keys without parsing them.
+//!
+//! Run with `cargo bench --bench inbox_scan`.
+
+use secure_channel::hybrid_enc::{HybridCiphertext, InboxScanner};
+use secure_channel::keys::KeyPair;
+use std::time::Instant;
+
+const INBOX_SIZE: usize = 1000;
+const ROUNDS: u32 = 10;
+
+/// Index and plaintext of each decryptable ciphertext
+type Found = Vec<(usize, Vec<u8>)>;
+
+fn measure(name: &str, scan: impl Fn() -> Found) -> Found {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        std::hint::black_box(scan());
+    }
+    println!("{:<16} {:>10.2?} per inbox", name, start.elapsed() / ROUNDS);
+    scan()
+}
+
+fn main() {
+    let own = KeyPair::generate().unwrap();
+    let others: Vec<KeyPair> = (0..9).map(|_| KeyPair::generate().unwrap()).collect();
+    // One message in ten is for us, all carry a recipient hint
+    let inbox: Vec<Vec<u8>> = (0..INBOX_SIZE)
+        .map(|index| {
+            let recipient = if index % 10 == 0 {
+                &own
+            } else {
+                &others[index % others.len()]
+            };
+            HybridCiphertext::encrypt(b"Inbox message", &recipient.public_key)
+                .unwrap()
+                .with_recipient_hint(&recipient.public_key)
+                .serialize()
+        })
+        .collect();
+
+    println!("{} messages, {} rounds", INBOX_SIZE, ROUNDS);
+    let naive = measure("trial decryption", || {
+        inbox
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bytes)| {
+                let ciphertext = HybridCiphertext::deserialize(bytes).ok()?;
+                Some((index, ciphertext.decrypt(&own.private_key).ok()?))
+            })
+            .collect()
+    });
+    let scanner = InboxScanner::new(&own.private_key);
+    let hinted = measure("hinted scan", || scanner.scan(&inbox));
+    assert_eq!(naive, hinted);
+}
@@ -0,0 +1,62 @@
+//! Compares heap allocations and running time of `SchnorrSignature::verify`
+//! and `SchnorrSignature::verify_no_alloc`.
+//!
+//! Run with `cargo bench --bench verify_alloc`.
+
+use secure_channel::keys::KeyPair;
+use secure_channel::schnorr::SchnorrSignature;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Allocator wrapper counting every allocation made through it
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: usize = 1_000;
+
+fn measure(name: &str, verify: impl Fn() -> bool) {
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert!(verify(), "{} rejected a valid signature", name);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+
+    println!(
+        "{:<16} {:>8} allocations {:>10.2?} per call",
+        name,
+        allocations,
+        elapsed / ITERATIONS as u32
+    );
+}
+
+fn main() {
+    let keypair = KeyPair::generate().unwrap();
+    let message = b"Benchmark message for Schnorr verification";
+    let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
+
+    println!("{} iterations", ITERATIONS);
+    measure("verify", || {
+        SchnorrSignature::verify(&signature, message, &keypair.public_key)
+    });
+    measure("verify_no_alloc", || {
+        SchnorrSignature::verify_no_alloc(&signature, message, &keypair.public_key)
+    });
+}
@@ -2,79 +2,353 @@ extern crate aes_gcm;
 extern crate curve25519_dalek;
 extern crate rand;
 
-use aead::generic_array::GenericArray;
-use aes_gcm::aead::{Aead, KeyInit}; // Use KeyInit for the `new` method
+use crate::capabilities::AeadAlgorithm;
+use crate::error::{AeadError, CryptoError};
+use crate::keys::secure_rng;
+use crate::serializers::{to_hex, to_hex_truncated};
+use aes_gcm::aead::{Aead, KeyInit, Payload}; // Use KeyInit for the `new` method
 use aes_gcm::{Aes256Gcm, Nonce}; // AES-GCM with 256-bit key
+#[cfg(feature = "siv")]
+use aes_gcm_siv::Aes256GcmSiv;
 use curve25519_dalek::scalar::Scalar;
-use rand::{rngs::OsRng, Rng};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
+use std::fmt;
+use zeroize::Zeroize;
 
-const AES_KEY_SIZE: usize = 32; // AES-256 requires a 256-bit key (32 bytes)
+pub const AES_KEY_SIZE: usize = 32; // AES-256 requires a 256-bit key (32 bytes)
 pub const AES_NONCE_SIZE: usize = 12; // Recommended nonce size for AES-GCM is 12 bytes
+pub const AES_TAG_SIZE: usize = 16; // Authentication tag appended to every ciphertext
+
+/// Number of ciphertext bytes `Display` shows before truncating
+const DISPLAY_BYTES: usize = 32;
 
 /// Struct to hold the AES ciphertext and nonce
+#[derive(Debug, Clone)]
 pub struct AESCiphertext {
+    pub algorithm: AeadAlgorithm, // The AEAD the ciphertext was produced with
     pub nonce: [u8; AES_NONCE_SIZE], // The nonce used for encryption
-    pub ciphertext: Vec<u8>,         // The encrypted message
+    pub ciphertext: Vec<u8>,      // The encrypted message
 }
 
 impl AESCiphertext {
-    /// Display nonce and ciphertext as hex for readability
-    #[allow(dead_code)]
-    pub fn display(&self) {
-        println!("Nonce: {:?}", self.nonce);
-        println!("Ciphertext: {:?}", self.ciphertext);
-    }
-
     /// Generates a random scalar to be used as an AES key
-    pub fn keygen() -> Scalar {
-        Scalar::random(&mut OsRng)
+    pub fn keygen() -> Result<Scalar, CryptoError> {
+        Ok(Scalar::random(&mut secure_rng()?))
     }
 
-    /// Converts a Scalar into a 32-byte array to be used as an AES key
-    fn scalar_to_aes_key(scalar: &Scalar) -> [u8; AES_KEY_SIZE] {
-        scalar.to_bytes() // Scalar provides a 32-byte output
+    /// Converts a Scalar into a 32-byte array to be used as an AES key.
+    /// Rejects zero: `keygen` practically never returns it, but decapsulating a crafted
+    /// ElGamal ciphertext can, which would mean an all-zero AES key.
+    fn scalar_to_aes_key(scalar: &Scalar) -> Result<[u8; AES_KEY_SIZE], AeadError> {
+        if *scalar == Scalar::ZERO {
+            return Err(AeadError::WeakSymmetricKey);
+        }
+        Ok(scalar.to_bytes()) // Scalar provides a 32-byte output
     }
 
     /// Encrypts a plaintext message using AES-256-GCM with a Scalar as the AES key
     pub fn encrypt(scalar_key: &Scalar, message: &[u8]) -> Result<AESCiphertext, String> {
-          // Derive a 32-byte AES key from the scalar
-        let key_bytes = Self::scalar_to_aes_key(scalar_key);
+        // Derive a 32-byte AES key from the scalar
+        let key_bytes = Self::scalar_to_aes_key(scalar_key).map_err(|e| e.to_string())?;
+
+        Self::encrypt_with_key(&key_bytes, message)
+    }
 
-        // Initialize AES-GCM cipher
-        let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-            .expect("Failed to initialize AES-GCM cipher");
+    /// Encrypts a plaintext message using AES-256-GCM with raw key bytes (e.g. a pre-shared key)
+    pub fn encrypt_with_key(
+        key_bytes: &[u8; AES_KEY_SIZE],
+        message: &[u8],
+    ) -> Result<AESCiphertext, String> {
+        Self::encrypt_with_algorithm(AeadAlgorithm::Aes256Gcm, key_bytes, message)
+    }
 
+    /// Encrypts a plaintext message with the given AEAD and a random nonce
+    pub fn encrypt_with_algorithm(
+        algorithm: AeadAlgorithm,
+        key_bytes: &[u8; AES_KEY_SIZE],
+        message: &[u8],
+    ) -> Result<AESCiphertext, String> {
         // Generate a random nonce
-        let mut rng = OsRng;
+        let mut rng = secure_rng().map_err(|e| e.to_string())?;
         let mut nonce = [0u8; AES_NONCE_SIZE];
         rng.fill(&mut nonce);
 
-        // Encrypt the message
-        let nonce_instance = Nonce::from_slice(&nonce);
-        match cipher.encrypt(nonce_instance, message) {
+        Self::encrypt_with_nonce(algorithm, key_bytes, &nonce, message)
+    }
+
+    /// Encrypts a plaintext message with the given AEAD and a caller-chosen nonce.
+    /// Reusing a nonce under AES-256-GCM leaks the XOR of the plaintexts and allows forgeries,
+    /// so only use a fixed or counter nonce with GCM-SIV, which only reveals whether two
+    /// plaintexts were equal.
+    pub fn encrypt_with_nonce(
+        algorithm: AeadAlgorithm,
+        key_bytes: &[u8; AES_KEY_SIZE],
+        nonce: &[u8; AES_NONCE_SIZE],
+        message: &[u8],
+    ) -> Result<AESCiphertext, String> {
+        CipherContext::new(algorithm, key_bytes).encrypt_with_nonce(nonce, message)
+    }
+
+    /// Decrypts a ciphertext using AES-256-GCM with a Scalar as the AES key
+    pub fn decrypt(
+        scalar_key: &Scalar,
+        aes_ciphertext: &AESCiphertext,
+    ) -> Result<Vec<u8>, AeadError> {
+        // Derive a 32-byte AES key from the scalar
+        let key_bytes = Self::scalar_to_aes_key(scalar_key)?;
+
+        Self::decrypt_with_key(&key_bytes, aes_ciphertext)
+    }
+
+    /// Decrypts a ciphertext with raw key bytes (e.g. a pre-shared key), using the AEAD
+    /// recorded in the ciphertext.
+    /// GCM can't tell a wrong key from tampered data, both are `AuthenticationFailed`.
+    pub fn decrypt_with_key(
+        key_bytes: &[u8; AES_KEY_SIZE],
+        aes_ciphertext: &AESCiphertext,
+    ) -> Result<Vec<u8>, AeadError> {
+        CipherContext::new(aes_ciphertext.algorithm, key_bytes).decrypt(aes_ciphertext)
+    }
+
+    /// Serializes the AESCiphertext into a Vec<u8> (nonce followed by ciphertext).
+    /// The layout doesn't record the algorithm, `HybridCiphertext::serialize` does.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(AES_NONCE_SIZE + self.ciphertext.len());
+        buffer.extend_from_slice(&self.nonce);
+        buffer.extend_from_slice(&self.ciphertext);
+        buffer
+    }
+
+    /// Deserializes a &[u8] back into an AES-256-GCM AESCiphertext
+    pub fn deserialize(bytes: &[u8]) -> Result<AESCiphertext, AeadError> {
+        let nonce: [u8; AES_NONCE_SIZE] = bytes
+            .get(..AES_NONCE_SIZE)
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or(AeadError::InvalidNonceLength)?;
+        let ciphertext = bytes[AES_NONCE_SIZE..].to_vec();
+
+        Ok(AESCiphertext {
+            algorithm: AeadAlgorithm::Aes256Gcm,
+            nonce,
+            ciphertext,
+        })
+    }
+}
+
+/// Initialized AEAD for one key, so many messages under the same key (e.g. a session) don't
+/// repeat the key schedule setup of the one-shot `AESCiphertext` functions
+#[derive(Clone)]
+pub struct CipherContext {
+    algorithm: AeadAlgorithm,
+    cipher: Cipher,
+}
+
+#[derive(Clone)]
+enum Cipher {
+    Aes256Gcm(Aes256Gcm),
+    #[cfg(feature = "siv")]
+    Aes256GcmSiv(Aes256GcmSiv),
+}
+
+impl CipherContext {
+    pub fn new(algorithm: AeadAlgorithm, key_bytes: &[u8; AES_KEY_SIZE]) -> CipherContext {
+        let cipher = match algorithm {
+            AeadAlgorithm::Aes256Gcm => Cipher::Aes256Gcm(Aes256Gcm::new(key_bytes.into())),
+            #[cfg(feature = "siv")]
+            AeadAlgorithm::Aes256GcmSiv => {
+                Cipher::Aes256GcmSiv(Aes256GcmSiv::new(key_bytes.into()))
+            }
+        };
+        CipherContext { algorithm, cipher }
+    }
+
+    pub fn algorithm(&self) -> AeadAlgorithm {
+        self.algorithm
+    }
+
+    /// Encrypts with a random nonce, like `AESCiphertext::encrypt_with_algorithm`
+    pub fn encrypt(&self, message: &[u8]) -> Result<AESCiphertext, String> {
+        let mut nonce = [0u8; AES_NONCE_SIZE];
+        secure_rng().map_err(|e| e.to_string())?.fill(&mut nonce);
+        self.encrypt_with_nonce(&nonce, message)
+    }
+
+    /// Encrypts with a caller-chosen nonce, see `AESCiphertext::encrypt_with_nonce`
+    pub fn encrypt_with_nonce(
+        &self,
+        nonce: &[u8; AES_NONCE_SIZE],
+        message: &[u8],
+    ) -> Result<AESCiphertext, String> {
+        self.encrypt_with_nonce_and_aad(nonce, message, &[])
+    }
+
+    /// Encrypts with a random nonce, authenticating `aad` along with the message.
+    /// `aad` isn't part of the ciphertext, the decryptor has to supply the same bytes.
+    pub fn encrypt_with_aad(&self, message: &[u8], aad: &[u8]) -> Result<AESCiphertext, String> {
+        let mut nonce = [0u8; AES_NONCE_SIZE];
+        secure_rng().map_err(|e| e.to_string())?.fill(&mut nonce);
+        self.encrypt_with_nonce_and_aad(&nonce, message, aad)
+    }
+
+    /// Encrypts with a caller-chosen nonce, authenticating `aad` along with the message
+    pub(crate) fn encrypt_with_nonce_and_aad(
+        &self,
+        nonce: &[u8; AES_NONCE_SIZE],
+        message: &[u8],
+        aad: &[u8],
+    ) -> Result<AESCiphertext, String> {
+        let nonce_instance = Nonce::from_slice(nonce);
+        let payload = Payload { msg: message, aad };
+        let result = match &self.cipher {
+            Cipher::Aes256Gcm(cipher) => cipher.encrypt(nonce_instance, payload),
+            #[cfg(feature = "siv")]
+            Cipher::Aes256GcmSiv(cipher) => cipher.encrypt(nonce_instance, payload),
+        };
+
+        match result {
             Ok(ciphertext) => Ok(AESCiphertext {
-                nonce,
+                algorithm: self.algorithm,
+                nonce: *nonce,
                 ciphertext,
             }),
             Err(_) => Err("Encryption failed".to_string()),
         }
     }
 
-    /// Decrypts a ciphertext using AES-256-GCM with a Scalar as the AES key
-    pub fn decrypt(scalar_key: &Scalar, aes_ciphertext: &AESCiphertext) -> Result<Vec<u8>, String> {
-        // Derive a 32-byte AES key from the scalar
-        let key_bytes = Self::scalar_to_aes_key(scalar_key);
-
-        // Initialize AES-GCM cipher
-        let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-            .expect("Failed to initialize AES-GCM cipher");
+    /// Decrypts a ciphertext produced with the same key. A ciphertext recorded with another
+    /// AEAD can't have been produced under this context and fails authentication.
+    pub fn decrypt(&self, aes_ciphertext: &AESCiphertext) -> Result<Vec<u8>, AeadError> {
+        self.decrypt_with_aad(aes_ciphertext, &[])
+    }
 
-        // Decrypt the ciphertext
+    /// Decrypts a ciphertext from `encrypt_with_aad`, failing authentication unless `aad`
+    /// matches the bytes used for encryption
+    pub fn decrypt_with_aad(
+        &self,
+        aes_ciphertext: &AESCiphertext,
+        aad: &[u8],
+    ) -> Result<Vec<u8>, AeadError> {
+        if aes_ciphertext.algorithm != self.algorithm {
+            return Err(AeadError::AuthenticationFailed);
+        }
         let nonce_instance = Nonce::from_slice(&aes_ciphertext.nonce);
-        match cipher.decrypt(nonce_instance, aes_ciphertext.ciphertext.as_ref()) {
-            Ok(plaintext) => Ok(plaintext),
-            Err(_) => Err("Decryption failed".to_string()),
+        let payload = Payload {
+            msg: &aes_ciphertext.ciphertext,
+            aad,
+        };
+        let result = match &self.cipher {
+            Cipher::Aes256Gcm(cipher) => cipher.decrypt(nonce_instance, payload),
+            #[cfg(feature = "siv")]
+            Cipher::Aes256GcmSiv(cipher) => cipher.decrypt(nonce_instance, payload),
+        };
+
+        result.map_err(|_| AeadError::AuthenticationFailed)
+    }
+}
+
+/// HKDF info strings splitting a `NonceHidingContext` key into the AEAD key and the mask key
+const NONCE_HIDING_AEAD_INFO: &[u8] = b"secure-channel/nonce-hiding/aead-key";
+const NONCE_HIDING_MASK_INFO: &[u8] = b"secure-channel/nonce-hiding/mask-key";
+
+/// AEAD whose nonces don't appear in the clear, so observers can't read message counts or
+/// order from them. The nonce is a per-context counter, stored XORed with a mask derived
+/// from the authentication tag under a separate key, as in QUIC header protection. The
+/// receiver recomputes the mask from the tag and unmasks the nonce before decrypting.
+///
+/// Tradeoffs versus `CipherContext` with random nonces:
+/// - the sender has to keep the counter: two contexts with the same key, or one restored
+///   from an old state, repeat nonces, which breaks AES-256-GCM
+/// - a tampered masked nonce unmasks to a wrong nonce and fails authentication, so the
+///   mask needs no integrity of its own
+/// - each message costs an extra HMAC, the size is unchanged
+pub struct NonceHidingContext {
+    context: CipherContext,
+    mask_key: Hkdf<Sha256>,
+    counter: u64, // Nonce of the next message
+}
+
+impl NonceHidingContext {
+    /// Derives the AEAD and mask keys from `key_bytes`, starting the nonce counter at 0
+    pub fn new(algorithm: AeadAlgorithm, key_bytes: &[u8; AES_KEY_SIZE]) -> NonceHidingContext {
+        let hkdf = Hkdf::<Sha256>::new(None, key_bytes);
+        let mut aead_key = [0u8; AES_KEY_SIZE];
+        let mut mask_key = [0u8; AES_KEY_SIZE];
+        hkdf.expand(NONCE_HIDING_AEAD_INFO, &mut aead_key)
+            .and_then(|_| hkdf.expand(NONCE_HIDING_MASK_INFO, &mut mask_key))
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        let context = NonceHidingContext {
+            context: CipherContext::new(algorithm, &aead_key),
+            mask_key: Hkdf::<Sha256>::from_prk(&mask_key).expect("PRK is a full SHA-256 block"),
+            counter: 0,
+        };
+        aead_key.zeroize();
+        mask_key.zeroize();
+        context
+    }
+
+    /// Encrypts under the next counter nonce, returning masked nonce || ciphertext
+    pub fn encrypt(&mut self, message: &[u8]) -> Result<Vec<u8>, String> {
+        let next = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| "Nonce counter exhausted".to_string())?;
+        let mut nonce = [0u8; AES_NONCE_SIZE];
+        nonce[AES_NONCE_SIZE - 8..].copy_from_slice(&self.counter.to_be_bytes());
+        let aes_ciphertext = self.context.encrypt_with_nonce(&nonce, message)?;
+        self.counter = next;
+
+        let mask = self.nonce_mask(&aes_ciphertext.ciphertext);
+        let mut bytes = Vec::with_capacity(AES_NONCE_SIZE + aes_ciphertext.ciphertext.len());
+        bytes.extend(
+            nonce
+                .iter()
+                .zip(mask)
+                .map(|(byte, mask_byte)| byte ^ mask_byte),
+        );
+        bytes.extend_from_slice(&aes_ciphertext.ciphertext);
+        Ok(bytes)
+    }
+
+    /// Unmasks the nonce of a ciphertext from `encrypt` and decrypts it
+    pub fn decrypt(&self, bytes: &[u8]) -> Result<Vec<u8>, AeadError> {
+        if bytes.len() < AES_NONCE_SIZE + AES_TAG_SIZE {
+            return Err(AeadError::InvalidNonceLength);
+        }
+        let (masked_nonce, ciphertext) = bytes.split_at(AES_NONCE_SIZE);
+        let mask = self.nonce_mask(ciphertext);
+        let mut nonce = [0u8; AES_NONCE_SIZE];
+        for ((byte, masked), mask_byte) in nonce.iter_mut().zip(masked_nonce).zip(mask) {
+            *byte = masked ^ mask_byte;
         }
+        self.context.decrypt(&AESCiphertext {
+            algorithm: self.context.algorithm,
+            nonce,
+            ciphertext: ciphertext.to_vec(),
+        })
+    }
+
+    /// Mask for the nonce, a PRF of the tag at the end of the ciphertext
+    fn nonce_mask(&self, ciphertext: &[u8]) -> [u8; AES_NONCE_SIZE] {
+        let tag = &ciphertext[ciphertext.len() - AES_TAG_SIZE..];
+        let mut mask = [0u8; AES_NONCE_SIZE];
+        self.mask_key
+            .expand(tag, &mut mask)
+            .expect("12 bytes is a valid HKDF-SHA256 output length");
+        mask
+    }
+}
+
+impl fmt::Display for AESCiphertext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Algorithm: {:?}", self.algorithm)?;
+        writeln!(f, "Nonce: {}", to_hex(&self.nonce))?;
+        write!(
+            f,
+            "Ciphertext: {}",
+            to_hex_truncated(&self.ciphertext, DISPLAY_BYTES)
+        )
     }
 }
 
@@ -84,7 +358,7 @@ mod tests {
 
     #[test]
     fn test_aes_correctness() {
-        let key = AESCiphertext::keygen();
+        let key = AESCiphertext::keygen().unwrap();
         // Message to encrypt
         let message = b"Hello, AES-GCM encryption using Scalar as the key!";
 
@@ -101,4 +375,140 @@ mod tests {
             "Decrypted message should match the original plaintext"
         );
     }
+
+    #[test]
+    fn test_aes_raw_key_serialization_roundtrip() {
+        let key = [7u8; AES_KEY_SIZE];
+        let message = b"Hello, AES-GCM with a pre-shared key!";
+
+        let aes_ciphertext =
+            AESCiphertext::encrypt_with_key(&key, message).expect("Encryption failed");
+        let deserialized = AESCiphertext::deserialize(&aes_ciphertext.serialize())
+            .expect("Deserialization failed");
+        let decrypted_message =
+            AESCiphertext::decrypt_with_key(&key, &deserialized).expect("Decryption failed");
+
+        assert_eq!(decrypted_message, message);
+        assert!(AESCiphertext::deserialize(&[0u8; AES_NONCE_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_nonce_hiding_roundtrip() {
+        let key = [7u8; AES_KEY_SIZE];
+        let mut sender = NonceHidingContext::new(AeadAlgorithm::Aes256Gcm, &key);
+        let receiver = NonceHidingContext::new(AeadAlgorithm::Aes256Gcm, &key);
+
+        let messages: Vec<Vec<u8>> = (0..3)
+            .map(|_| sender.encrypt(b"Hidden nonce").expect("Encryption failed"))
+            .collect();
+        for (counter, bytes) in messages.iter().enumerate() {
+            // The counter nonce (zeros and a small big-endian counter) isn't stored as is
+            let mut nonce = [0u8; AES_NONCE_SIZE];
+            nonce[AES_NONCE_SIZE - 1] = counter as u8;
+            assert!(!bytes.windows(AES_NONCE_SIZE).any(|window| window == nonce));
+            assert_eq!(receiver.decrypt(bytes).unwrap(), b"Hidden nonce");
+        }
+
+        let mut tampered = messages[1].clone();
+        tampered[0] ^= 0x01;
+        assert_eq!(
+            receiver.decrypt(&tampered),
+            Err(AeadError::AuthenticationFailed)
+        );
+        assert_eq!(
+            receiver.decrypt(&messages[1][..AES_NONCE_SIZE + AES_TAG_SIZE - 1]),
+            Err(AeadError::InvalidNonceLength)
+        );
+    }
+
+    #[cfg(feature = "siv")]
+    #[test]
+    fn test_siv_tolerates_nonce_reuse() {
+        let key = [7u8; AES_KEY_SIZE];
+        let nonce = [1u8; AES_NONCE_SIZE];
+        let first = b"Attack at dawn!!";
+        let second = b"Attack at dusk!!";
+        let xor = |a: &[u8], b: &[u8]| -> Vec<u8> { a.iter().zip(b).map(|(x, y)| x ^ y).collect() };
+
+        let encrypt = |algorithm, message: &[u8]| {
+            AESCiphertext::encrypt_with_nonce(algorithm, &key, &nonce, message)
+                .expect("Encryption failed")
+        };
+
+        // Under GCM a reused nonce reveals the XOR of the plaintexts
+        let gcm_first = encrypt(AeadAlgorithm::Aes256Gcm, first);
+        let gcm_second = encrypt(AeadAlgorithm::Aes256Gcm, second);
+        assert_eq!(
+            xor(
+                &gcm_first.ciphertext[..first.len()],
+                &gcm_second.ciphertext[..second.len()]
+            ),
+            xor(first, second)
+        );
+
+        // Under SIV it only reveals whether the plaintexts are equal
+        let siv_first = encrypt(AeadAlgorithm::Aes256GcmSiv, first);
+        let siv_repeat = encrypt(AeadAlgorithm::Aes256GcmSiv, first);
+        let siv_second = encrypt(AeadAlgorithm::Aes256GcmSiv, second);
+        assert_eq!(siv_first.ciphertext, siv_repeat.ciphertext);
+        assert_ne!(
+            xor(
+                &siv_first.ciphertext[..first.len()],
+                &siv_second.ciphertext[..second.len()]
+            ),
+            xor(first, second)
+        );
+
+        for (ciphertext, plaintext) in [(siv_first, first), (siv_second, second)] {
+            let decrypted =
+                AESCiphertext::decrypt_with_key(&key, &ciphertext).expect("Decryption failed");
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_aead_errors_distinguish_format_from_authentication() {
+        let key = [7u8; AES_KEY_SIZE];
+        assert_eq!(
+            AESCiphertext::deserialize(&[0u8; AES_NONCE_SIZE - 1]).unwrap_err(),
+            AeadError::InvalidNonceLength
+        );
+
+        let mut aes_ciphertext =
+            AESCiphertext::encrypt_with_key(&key, b"Tag test").expect("Encryption failed");
+        let last = aes_ciphertext.ciphertext.len() - 1;
+        aes_ciphertext.ciphertext[last] ^= 0x01; // The tag is at the end
+        assert_eq!(
+            AESCiphertext::decrypt_with_key(&key, &aes_ciphertext),
+            Err(AeadError::AuthenticationFailed)
+        );
+    }
+
+    #[test]
+    fn test_cipher_context_matches_one_shot_functions() {
+        let key = [7u8; AES_KEY_SIZE];
+        let nonce = [3u8; AES_NONCE_SIZE];
+        let message = b"Session message";
+        let context = CipherContext::new(AeadAlgorithm::Aes256Gcm, &key);
+
+        let one_shot =
+            AESCiphertext::encrypt_with_nonce(AeadAlgorithm::Aes256Gcm, &key, &nonce, message)
+                .unwrap();
+        let reused = context.encrypt_with_nonce(&nonce, message).unwrap();
+        assert_eq!(reused.ciphertext, one_shot.ciphertext);
+
+        // Ciphertexts from either path open with the other
+        for _ in 0..3 {
+            let ciphertext = context.encrypt(message).unwrap();
+            assert_eq!(
+                AESCiphertext::decrypt_with_key(&key, &ciphertext).unwrap(),
+                message
+            );
+        }
+        assert_eq!(context.decrypt(&one_shot).unwrap(), message);
+        assert_eq!(
+            CipherContext::new(AeadAlgorithm::Aes256Gcm, &[8u8; AES_KEY_SIZE]).decrypt(&one_shot),
+            Err(AeadError::AuthenticationFailed)
+        );
+    }
 }
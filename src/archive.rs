@@ -0,0 +1,255 @@
+//! Container format of `Message::to_archive`: a multi-recipient ciphertext together with
+//! a manifest of its recipients, so it can be stored and restored on its own.
+//!
+//! Layout: a header (`ARCHIVE_MAGIC`, archive version, format version of the archived
+//! message, slot count as u16 LE, length of the AES part as u32 LE), a manifest of one slot
+//! per recipient (fingerprint, c1, c2) and the shared AES-256-GCM nonce and ciphertext.
+//! The header and the manifest are the AAD of the AES part.
+
+use crate::aes::{AESCiphertext, AES_NONCE_SIZE, AES_TAG_SIZE};
+use crate::elgamal::ElGamalCiphertext;
+use crate::error::CryptoError;
+use crate::hybrid_enc::{KeyDerivation, MultiRecipientCiphertext, RecipientSlot};
+use crate::keys::fingerprint;
+use crate::schnorr::SchnorrSignature;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::scalar::Scalar;
+
+/// Magic bytes at the start of an archive
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"SCAR";
+/// Layout version written after the magic
+const ARCHIVE_VERSION: u8 = 3;
+/// Magic, archive version, format version, slot count (u16) and AES part length (u32)
+const HEADER_SIZE: usize = 4 + 1 + 1 + 2 + 4;
+/// Recipient fingerprint, c1 and c2
+const SLOT_SIZE: usize = 8 + 32 + 32;
+
+/// A parsed archive, see the module docs
+pub struct Archive {
+    pub format_version: u8, // `Message::format_version` of the archived message
+    pub ciphertext: MultiRecipientCiphertext,
+}
+
+impl Archive {
+    /// Encrypts `message` to `recipients`, authenticating the header and the manifest with
+    /// the AES part so neither can be rewritten without `open` failing.
+    pub fn seal(
+        message: &[u8],
+        format_version: u8,
+        recipients: &[RistrettoPoint],
+    ) -> Result<Archive, String> {
+        let slot_count = u16::try_from(recipients.len()).map_err(|_| {
+            CryptoError::TooManyRecipients {
+                limit: u16::MAX as usize,
+                actual: recipients.len(),
+            }
+            .to_string()
+        })?;
+        let aes_length = u32::try_from(AES_NONCE_SIZE + message.len() + AES_TAG_SIZE)
+            .map_err(|_| CryptoError::InvalidArchive.to_string())?;
+        let ciphertext =
+            MultiRecipientCiphertext::encrypt_with_aad(message, recipients, |slots| {
+                header_and_manifest(format_version, slot_count, aes_length, slots)
+            })?;
+        Ok(Archive {
+            format_version,
+            ciphertext,
+        })
+    }
+
+    /// Decrypts the archive with the slot matching `private_key`, checking the header and
+    /// the manifest against the AES part
+    pub fn open(&self, private_key: &Scalar) -> Result<Vec<u8>, String> {
+        let public_key = private_key * RISTRETTO_BASEPOINT_POINT;
+        let aad = self.authenticated_bytes().map_err(|e| e.to_string())?;
+        self.ciphertext
+            .extract_for(&fingerprint(&public_key))
+            .ok_or("No slot for this key")?
+            .decrypt_with_aad(private_key, &aad)
+    }
+
+    /// Serializes the archive. Fails if the slots or the AES part don't fit their length fields.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CryptoError> {
+        let mut bytes = self.authenticated_bytes()?;
+        bytes.extend_from_slice(&self.ciphertext.aes_ciphertext.serialize());
+        Ok(bytes)
+    }
+
+    /// The header and the manifest, which `seal` passes as AAD for the AES part
+    fn authenticated_bytes(&self) -> Result<Vec<u8>, CryptoError> {
+        let slot_count = u16::try_from(self.ciphertext.slots.len()).map_err(|_| {
+            CryptoError::TooManyRecipients {
+                limit: u16::MAX as usize,
+                actual: self.ciphertext.slots.len(),
+            }
+        })?;
+        let aes_length = u32::try_from(self.ciphertext.aes_ciphertext.serialize().len())
+            .map_err(|_| CryptoError::InvalidArchive)?;
+        Ok(header_and_manifest(
+            self.format_version,
+            slot_count,
+            aes_length,
+            &self.ciphertext.slots,
+        ))
+    }
+
+    /// Parses an archive. The slot count and the AES part length have to add up to the exact
+    /// size of `bytes`, so a changed count can't shift slots into the AES part or back.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Archive, CryptoError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(CryptoError::Truncated {
+                minimum: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        if &bytes[..4] != ARCHIVE_MAGIC {
+            return Err(CryptoError::InvalidArchive);
+        }
+        if bytes[4] != ARCHIVE_VERSION {
+            return Err(CryptoError::UnsupportedArchiveVersion(bytes[4]));
+        }
+        let slot_count = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+        let aes_length = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+        let manifest_end = HEADER_SIZE + slot_count * SLOT_SIZE;
+        if aes_length < AES_NONCE_SIZE + AES_TAG_SIZE
+            || bytes.len().checked_sub(manifest_end) != Some(aes_length)
+        {
+            return Err(CryptoError::InvalidArchive);
+        }
+
+        let slots = bytes[HEADER_SIZE..manifest_end]
+            .chunks_exact(SLOT_SIZE)
+            .map(|slot| {
+                let c1 = SchnorrSignature::bytes_to_point(&slot[8..40])?;
+                let c2 = SchnorrSignature::bytes_to_scalar(&slot[40..])?;
+                Ok(RecipientSlot {
+                    fingerprint: slot[..8].try_into().expect("Slot size is fixed"),
+                    elgamal_ciphertext: ElGamalCiphertext { c1, c2 },
+                })
+            })
+            .collect::<Result<Vec<_>, CryptoError>>()?;
+        let aes_ciphertext = AESCiphertext::deserialize(&bytes[manifest_end..])
+            .map_err(|_| CryptoError::InvalidArchive)?;
+        Ok(Archive {
+            format_version: bytes[5],
+            ciphertext: MultiRecipientCiphertext {
+                slots,
+                aes_ciphertext,
+                key_derivation: KeyDerivation::Hkdf,
+            },
+        })
+    }
+
+    /// Lists the `keys::fingerprint`s of the recipients without decrypting anything, so a
+    /// user can check they can read the archive. Fails like `from_bytes` on archives whose
+    /// slot count doesn't match the manifest or that hold invalid keys.
+    pub fn list_recipients(bytes: &[u8]) -> Result<Vec<[u8; 8]>, CryptoError> {
+        let archive = Archive::from_bytes(bytes)?;
+        Ok(archive
+            .ciphertext
+            .slots
+            .iter()
+            .map(|slot| slot.fingerprint)
+            .collect())
+    }
+}
+
+fn header_and_manifest(
+    format_version: u8,
+    slot_count: u16,
+    aes_length: u32,
+    slots: &[RecipientSlot],
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + SLOT_SIZE * slots.len() + aes_length as usize);
+    bytes.extend_from_slice(ARCHIVE_MAGIC);
+    bytes.push(ARCHIVE_VERSION);
+    bytes.push(format_version);
+    bytes.extend_from_slice(&slot_count.to_le_bytes());
+    bytes.extend_from_slice(&aes_length.to_le_bytes());
+    for slot in slots {
+        bytes.extend_from_slice(&slot.fingerprint);
+        bytes.extend_from_slice(slot.elgamal_ciphertext.c1.compress().as_bytes());
+        bytes.extend_from_slice(slot.elgamal_ciphertext.c2.as_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyPair;
+    use crate::message::Message;
+    use crate::schnorr::SchnorrSignature;
+    use curve25519_dalek::ristretto::CompressedRistretto;
+
+    fn archive_for(recipients: &[KeyPair]) -> Vec<u8> {
+        let public_keys: Vec<_> = recipients
+            .iter()
+            .map(|keypair| keypair.public_key)
+            .collect();
+        Message::new(
+            0,
+            b"Archived".to_vec(),
+            CompressedRistretto::default(),
+            CompressedRistretto::default(),
+            SchnorrSignature::emty_signature(),
+        )
+        .to_archive(&public_keys)
+        .unwrap()
+    }
+
+    #[test]
+    fn test_list_recipients() {
+        let recipients = [KeyPair::from_seed(1), KeyPair::from_seed(2)];
+        let archive = archive_for(&recipients);
+
+        assert_eq!(
+            Archive::list_recipients(&archive),
+            Ok(vec![
+                fingerprint(&recipients[0].public_key),
+                fingerprint(&recipients[1].public_key)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_list_recipients_rejects_mismatched_counts() {
+        let archive = archive_for(&[KeyPair::from_seed(1), KeyPair::from_seed(2)]);
+
+        // A count above and below the number of slots in the manifest
+        for count in [0xffu16, 3, 1, 0] {
+            let mut corrupted = archive.clone();
+            corrupted[6..8].copy_from_slice(&count.to_le_bytes());
+            assert_eq!(
+                Archive::list_recipients(&corrupted),
+                Err(CryptoError::InvalidArchive),
+                "Slot count {count}"
+            );
+        }
+
+        // A dropped or an extra byte shifts the AES part
+        assert_eq!(
+            Archive::list_recipients(&archive[..archive.len() - 1]),
+            Err(CryptoError::InvalidArchive)
+        );
+        assert_eq!(
+            Archive::list_recipients(&[archive.as_slice(), &[0]].concat()),
+            Err(CryptoError::InvalidArchive)
+        );
+        assert_eq!(
+            Archive::list_recipients(&archive[..4]),
+            Err(CryptoError::Truncated {
+                minimum: HEADER_SIZE,
+                actual: 4
+            })
+        );
+
+        let mut other_version = archive;
+        other_version[4] = 1;
+        assert_eq!(
+            Archive::list_recipients(&other_version),
+            Err(CryptoError::UnsupportedArchiveVersion(1))
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::hash::HashAlgorithm;
+use crate::message::FORMAT_VERSION;
+use serde::{Deserialize, Serialize};
+
+/// AEAD algorithms used for the symmetric part of hybrid encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AeadAlgorithm {
+    Aes256Gcm,
+    /// Nonce-misuse-resistant: reusing a nonce only reveals whether two plaintexts are equal
+    #[cfg(feature = "siv")]
+    Aes256GcmSiv,
+}
+
+impl AeadAlgorithm {
+    /// Identifier of the algorithm in tagged wire formats
+    pub fn id(self) -> u8 {
+        match self {
+            AeadAlgorithm::Aes256Gcm => 0,
+            #[cfg(feature = "siv")]
+            AeadAlgorithm::Aes256GcmSiv => 1,
+        }
+    }
+
+    /// Looks up an algorithm by its wire identifier, None if it is unknown or not compiled in
+    pub fn from_id(id: u8) -> Option<AeadAlgorithm> {
+        match id {
+            0 => Some(AeadAlgorithm::Aes256Gcm),
+            #[cfg(feature = "siv")]
+            1 => Some(AeadAlgorithm::Aes256GcmSiv),
+            _ => None,
+        }
+    }
+}
+
+/// Key encapsulation mechanisms used to wrap the symmetric key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum KemAlgorithm {
+    ElGamal,
+}
+
+/// Signature schemes used to authenticate messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SignatureAlgorithm {
+    Schnorr,
+}
+
+/// Struct describing what this build can produce and understand.
+/// Peers exchange it during a handshake and pick a common subset with `intersect`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub aead: Vec<AeadAlgorithm>,
+    pub kem: Vec<KemAlgorithm>,
+    pub signature: Vec<SignatureAlgorithm>,
+    pub hash: Vec<HashAlgorithm>, // Hashes for Schnorr challenges and ElGamal masks
+    pub read_versions: Vec<u8>,   // Wire-format versions that can be parsed
+    pub write_versions: Vec<u8>,  // Wire-format versions that can be produced
+}
+
+impl Capabilities {
+    /// Returns the capabilities supported by both `self` and `other`, preserving the order of `self`
+    pub fn intersect(&self, other: &Capabilities) -> Capabilities {
+        fn common<T: PartialEq + Copy>(ours: &[T], theirs: &[T]) -> Vec<T> {
+            ours.iter()
+                .filter(|x| theirs.contains(x))
+                .copied()
+                .collect()
+        }
+
+        Capabilities {
+            aead: common(&self.aead, &other.aead),
+            kem: common(&self.kem, &other.kem),
+            signature: common(&self.signature, &other.signature),
+            hash: common(&self.hash, &other.hash),
+            read_versions: common(&self.read_versions, &other.read_versions),
+            write_versions: common(&self.write_versions, &other.write_versions),
+        }
+    }
+}
+
+/// Reports the algorithms and wire-format versions compiled into this build
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        aead: vec![
+            AeadAlgorithm::Aes256Gcm,
+            #[cfg(feature = "siv")]
+            AeadAlgorithm::Aes256GcmSiv,
+        ],
+        kem: vec![KemAlgorithm::ElGamal],
+        signature: vec![SignatureAlgorithm::Schnorr],
+        hash: vec![
+            HashAlgorithm::Sha512,
+            #[cfg(feature = "blake2")]
+            HashAlgorithm::Blake2b,
+        ],
+        read_versions: vec![FORMAT_VERSION],
+        write_versions: vec![FORMAT_VERSION],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_include_defaults() {
+        let caps = capabilities();
+
+        assert!(caps.aead.contains(&AeadAlgorithm::Aes256Gcm));
+        assert!(caps.kem.contains(&KemAlgorithm::ElGamal));
+        assert!(caps.signature.contains(&SignatureAlgorithm::Schnorr));
+        assert!(caps.read_versions.contains(&FORMAT_VERSION));
+        assert!(caps.write_versions.contains(&FORMAT_VERSION));
+    }
+
+    #[test]
+    fn test_capabilities_reflect_feature_flags() {
+        let caps = capabilities();
+
+        assert_eq!(caps.aead.len(), 1 + cfg!(feature = "siv") as usize);
+        #[cfg(feature = "siv")]
+        assert!(caps.aead.contains(&AeadAlgorithm::Aes256GcmSiv));
+        assert_eq!(caps.kem, vec![KemAlgorithm::ElGamal]);
+        assert_eq!(caps.signature, vec![SignatureAlgorithm::Schnorr]);
+        assert_eq!(caps.hash.len(), 1 + cfg!(feature = "blake2") as usize);
+    }
+
+    #[test]
+    fn test_capabilities_intersect() {
+        let ours = capabilities();
+        let theirs = Capabilities {
+            aead: vec![AeadAlgorithm::Aes256Gcm],
+            kem: vec![],
+            signature: vec![SignatureAlgorithm::Schnorr],
+            hash: vec![HashAlgorithm::Sha512],
+            read_versions: vec![FORMAT_VERSION, FORMAT_VERSION + 1],
+            write_versions: vec![FORMAT_VERSION + 1],
+        };
+
+        let common = ours.intersect(&theirs);
+        assert_eq!(common.aead, vec![AeadAlgorithm::Aes256Gcm]);
+        assert!(common.kem.is_empty(), "No common KEM should be reported");
+        assert_eq!(common.read_versions, vec![FORMAT_VERSION]);
+        assert!(common.write_versions.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::error::{CryptoError, MessageError};
+use crate::keys::public_keys_equal;
+use crate::schnorr::SchnorrSignature;
+use crate::serializers::*;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+
+/// Domain separator prepended to the bytes covered by a certificate signature
+const CERTIFICATE_DOMAIN: &[u8] = b"secure-channel/certificate";
+
+/// Statement by an issuer key authorizing a subject key to sign until `not_after`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Certificate {
+    #[serde(
+        serialize_with = "serialize_fixed_base64",
+        deserialize_with = "deserialize_fixed_base64"
+    )]
+    pub subject: [u8; 32], // The (short-term) key being authorized
+    #[serde(
+        serialize_with = "serialize_fixed_base64",
+        deserialize_with = "deserialize_fixed_base64"
+    )]
+    pub issuer: [u8; 32], // The (long-term) key vouching for the subject
+    pub not_after: u64, // Unix time in seconds after which the certificate is expired
+    #[serde(
+        serialize_with = "serialize_schnorr_signature",
+        deserialize_with = "deserialize_schnorr_signature"
+    )]
+    pub signature: SchnorrSignature,
+}
+
+impl Certificate {
+    /// Issues a certificate for `subject`, signed with the issuer's private key
+    pub fn issue(
+        issuer_key: &Scalar,
+        subject: &RistrettoPoint,
+        not_after: u64,
+    ) -> Result<Certificate, CryptoError> {
+        let issuer = issuer_key * RISTRETTO_BASEPOINT_POINT;
+        let mut certificate = Certificate {
+            subject: subject.compress().to_bytes(),
+            issuer: issuer.compress().to_bytes(),
+            not_after,
+            signature: SchnorrSignature::emty_signature(),
+        };
+        certificate.signature = SchnorrSignature::sign(&certificate.signed_bytes(), issuer_key)?;
+        Ok(certificate)
+    }
+
+    /// Bytes covered by the issuer's signature
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(CERTIFICATE_DOMAIN.len() + 72);
+        buffer.extend_from_slice(CERTIFICATE_DOMAIN);
+        buffer.extend_from_slice(&self.subject);
+        buffer.extend_from_slice(&self.issuer);
+        buffer.extend_from_slice(&self.not_after.to_le_bytes());
+        buffer
+    }
+
+    /// Checks the issuer's signature and that the certificate is not expired at `now`
+    pub fn verify(&self, now: u64) -> Result<(), MessageError> {
+        self.verify_signature()?;
+        if now > self.not_after {
+            return Err(MessageError::CertificateExpired);
+        }
+        Ok(())
+    }
+
+    /// Checks the issuer's signature only, whatever the time
+    fn verify_signature(&self) -> Result<(), MessageError> {
+        let issuer = CompressedRistretto(self.issuer)
+            .decompress()
+            .ok_or(MessageError::InvalidCertificate)?;
+        if !SchnorrSignature::verify(&self.signature, &self.signed_bytes(), &issuer) {
+            return Err(MessageError::InvalidCertificate);
+        }
+        Ok(())
+    }
+}
+
+/// Verifies that `chain` authorizes `signer` and leads to one of `trusted_roots`.
+/// `chain[0]` certifies the signer, and every following certificate certifies
+/// the issuer of the previous one.
+pub fn verify_chain(
+    chain: &[Certificate],
+    signer: &[u8; 32],
+    trusted_roots: &[RistrettoPoint],
+    now: u64,
+) -> Result<(), MessageError> {
+    let expected_subject = verify_chain_signatures(chain, signer)?;
+    for certificate in chain {
+        if now > certificate.not_after {
+            return Err(MessageError::CertificateExpired);
+        }
+    }
+
+    // A signer without certificates has to be a trusted root itself
+    let trusted = trusted_roots
+        .iter()
+        .any(|root| public_keys_equal(&root.compress().to_bytes(), &expected_subject));
+    if trusted {
+        Ok(())
+    } else {
+        Err(MessageError::UntrustedRoot)
+    }
+}
+
+/// Checks that `chain` links up from `signer` and that every certificate carries its
+/// issuer's signature, and returns the key at the top of the chain. Expiry and trust in
+/// that key are left to `verify_chain`, which knows the time and the trusted roots.
+pub(crate) fn verify_chain_signatures(
+    chain: &[Certificate],
+    signer: &[u8; 32],
+) -> Result<[u8; 32], MessageError> {
+    let mut expected_subject = *signer;
+    for certificate in chain {
+        if !public_keys_equal(&certificate.subject, &expected_subject) {
+            return Err(MessageError::BrokenCertificateChain);
+        }
+        certificate.verify_signature()?;
+        expected_subject = certificate.issuer;
+    }
+    Ok(expected_subject)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyPair;
+
+    #[test]
+    fn test_certificate_verification() {
+        let issuer = KeyPair::from_seed(1);
+        let subject = KeyPair::from_seed(2);
+
+        let mut certificate =
+            Certificate::issue(&issuer.private_key, &subject.public_key, 100).unwrap();
+        assert_eq!(certificate.verify(50), Ok(()));
+        assert_eq!(
+            certificate.verify(101),
+            Err(MessageError::CertificateExpired)
+        );
+
+        // Extending the validity invalidates the issuer's signature
+        certificate.not_after = 1_000;
+        assert_eq!(
+            certificate.verify(50),
+            Err(MessageError::InvalidCertificate)
+        );
+    }
+
+    #[test]
+    fn test_chain_must_link_subjects_to_issuers() {
+        let root = KeyPair::from_seed(1);
+        let intermediate = KeyPair::from_seed(2);
+        let signer = KeyPair::from_seed(3);
+
+        let chain = vec![
+            Certificate::issue(&intermediate.private_key, &signer.public_key, 100).unwrap(),
+            Certificate::issue(&root.private_key, &intermediate.public_key, 100).unwrap(),
+        ];
+        let signer_bytes = signer.public_key.compress().to_bytes();
+
+        assert_eq!(
+            verify_chain(&chain, &signer_bytes, &[root.public_key], 10),
+            Ok(())
+        );
+
+        let reordered = vec![chain[1].clone(), chain[0].clone()];
+        assert_eq!(
+            verify_chain(&reordered, &signer_bytes, &[root.public_key], 10),
+            Err(MessageError::BrokenCertificateChain)
+        );
+    }
+}
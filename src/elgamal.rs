@@ -4,12 +4,26 @@ extern crate rand;
 use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
-use rand::rngs::OsRng;
-use sha2::{Digest, Sha512};
-
-use crate::keys::KeyPair;
+use curve25519_dalek::traits::Identity;
+
+use crate::error::CryptoError;
+use crate::hash::HashAlgorithm;
+use crate::keys::{secure_rng, KeyPair};
+
+/// Domain separator of the blinding factor d in proxy re-encryption keys
+const REKEY_DOMAIN: &[u8] = b"secure-channel/proxy-re-encryption";
+
+/// Diagnoses key mismatches: true if `sk` is the private key of `pk`. Encryption derives
+/// the shared secret pk^r and decryption c1^sk, and with c1 = g^r the two agree for every
+/// ciphertext exactly when pk = g^sk. r isn't kept after encryption, so this checks the keys
+/// rather than a particular ciphertext.
+#[cfg(any(test, feature = "debug-tools"))]
+pub fn key_pair_matches(sk: &Scalar, pk: &RistrettoPoint) -> bool {
+    sk * RISTRETTO_BASEPOINT_POINT == *pk
+}
 
 /// Struct to hold the ElGamal ciphertext
+#[derive(Clone)]
 pub struct ElGamalCiphertext {
     pub c1: RistrettoPoint, // C1 = r * G
     pub c2: Scalar, // C2 = M + Hash(r * public_key) as a Scalar: we want to encrypt AES keys as scalars
@@ -17,23 +31,45 @@ pub struct ElGamalCiphertext {
 
 impl ElGamalCiphertext {
     /// Generates a new KeyPair for encryption
-    pub fn keygen() -> KeyPair {
+    pub fn keygen() -> Result<KeyPair, CryptoError> {
         KeyPair::generate()
     }
 
     /// Encrypts a message (represented as a scalar) using the recipient's public key
     /// Returns an `ElGamalCiphertext` struct containing the encrypted message
-    pub fn encrypt(message: &Scalar, public_key: &RistrettoPoint) -> ElGamalCiphertext {
-        let mut rng = OsRng;
-        let r = Scalar::random(&mut rng); // Generate random scalar r
+    pub fn encrypt(
+        message: &Scalar,
+        public_key: &RistrettoPoint,
+    ) -> Result<ElGamalCiphertext, CryptoError> {
+        ElGamalCiphertext::encrypt_with_hash(HashAlgorithm::Sha512, message, public_key)
+    }
+
+    /// Like `encrypt`, with the mask H(pk^r) computed by `hash`. Decrypt with
+    /// `decrypt_with_hash` and the same hash.
+    pub fn encrypt_with_hash(
+        hash: HashAlgorithm,
+        message: &Scalar,
+        public_key: &RistrettoPoint,
+    ) -> Result<ElGamalCiphertext, CryptoError> {
+        let r = Scalar::random(&mut secure_rng()?); // Generate random scalar r
+        Ok(ElGamalCiphertext::encrypt_with_randomness(
+            hash, message, public_key, &r,
+        ))
+    }
 
-        let c1 = &r * &RISTRETTO_BASEPOINT_POINT; // c1 = g^r
+    /// Encryption with caller-chosen randomness r, for senders that keep r to prove
+    /// the contents later. Reusing r for two messages to the same key leaks their difference.
+    pub(crate) fn encrypt_with_randomness(
+        hash: HashAlgorithm,
+        message: &Scalar,
+        public_key: &RistrettoPoint,
+        r: &Scalar,
+    ) -> ElGamalCiphertext {
+        let c1 = r * RISTRETTO_BASEPOINT_POINT; // c1 = g^r
         let shared_secret = public_key * r; // pk^r = g^(sk * r)
 
         // Hash the shared secret to a scalar
-        let mut hasher = Sha512::new();
-        hasher.update(shared_secret.compress().as_bytes());
-        let hashed_secret = Scalar::from_hash(hasher);
+        let hashed_secret = hash.hash_to_scalar(&[shared_secret.compress().as_bytes()]);
 
         // Compute c2 = H(pk^r) + m
         let c2 = hashed_secret + message;
@@ -44,35 +80,170 @@ impl ElGamalCiphertext {
     /// Decrypts an ElGamal ciphertext using the recipient's private key
     /// Returns the decrypted scalar (original message)
     pub fn decrypt(&self, private_key: &Scalar) -> Scalar {
-        let shared_secret = &self.c1 * private_key; // c1^sk = g^(sk * r)
+        self.decrypt_with_hash(HashAlgorithm::Sha512, private_key)
+    }
+
+    /// Decrypts a ciphertext from `encrypt_with_hash` with the same hash
+    pub fn decrypt_with_hash(&self, hash: HashAlgorithm, private_key: &Scalar) -> Scalar {
+        let shared_secret = self.c1 * private_key; // c1^sk = g^(sk * r)
 
         // Hash the shared secret to a scalar
-        let mut hasher = Sha512::new();
-        hasher.update(shared_secret.compress().as_bytes());
-        let hashed_secret = Scalar::from_hash(hasher);
+        let hashed_secret = hash.hash_to_scalar(&[shared_secret.compress().as_bytes()]);
 
         // Recover the original message: m = c2 - H(pk^r)
         self.c2 - hashed_secret
     }
+
+    /// Commitment to the ciphertext: C = c2 * G + c1.
+    /// With c2 = m + k for the mask k = H(pk^r), this is m * G + (k * G + c1), a
+    /// Pedersen-style commitment to m with blinding k * G + c1. Assumptions:
+    /// - it hides m only while k is unknown, anyone holding the private key can open it
+    /// - commitments add up, ciphertexts don't: the hash mask isn't linear, so summing c1
+    ///   and c2 of two ciphertexts doesn't encrypt the sum of their messages. Tally votes
+    ///   with `ExponentialCiphertext` instead.
+    pub fn commitment(&self) -> RistrettoPoint {
+        self.c2 * RISTRETTO_BASEPOINT_POINT + self.c1
+    }
+
+    /// Derives the key a proxy needs to rewrap ciphertexts for the delegator's key to
+    /// `delegatee_pk`. With an ephemeral key x, d = H(g^x || pk_B || pk_B^x) and
+    /// rk = sk_A / d: only the delegatee can recompute d, as X^sk_B. A proxy colluding with
+    /// the delegatee can compute sk_A = rk * d, so only delegate to parties trusted that far.
+    pub fn re_encryption_key(
+        delegator_sk: &Scalar,
+        delegatee_pk: &RistrettoPoint,
+    ) -> Result<ReEncryptionKey, CryptoError> {
+        let x = Scalar::random(&mut secure_rng()?);
+        let ephemeral = x * RISTRETTO_BASEPOINT_POINT;
+        let d = rekey_blinding(&ephemeral, delegatee_pk, &(delegatee_pk * x));
+        Ok(ReEncryptionKey {
+            rk: delegator_sk * d.invert(),
+            ephemeral,
+        })
+    }
+
+    /// Proxy transform: turns c1 = g^r into c1^rk, so the delegatee's c1'^d is the
+    /// delegator's shared secret pk_A^r. Needs no private key and doesn't reveal the message.
+    pub fn rewrap_key(&self, re_encryption_key: &ReEncryptionKey) -> RewrappedCiphertext {
+        RewrappedCiphertext {
+            c1: self.c1 * re_encryption_key.rk,
+            c2: self.c2,
+            ephemeral: re_encryption_key.ephemeral,
+        }
+    }
+}
+
+/// Re-encryption key from `ElGamalCiphertext::re_encryption_key`, held by the proxy
+#[derive(Clone)]
+pub struct ReEncryptionKey {
+    rk: Scalar,                    // sk_A / d
+    pub ephemeral: RistrettoPoint, // X = g^x, lets the delegatee recompute d
+}
+
+/// Exponential ElGamal ciphertext of a small count m: c1 = r * G, c2 = m * G + r * pk.
+/// Unlike `ElGamalCiphertext` it is additively homomorphic, so ballots can be summed into
+/// a tally that decrypts to the vote count without decrypting any single ballot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExponentialCiphertext {
+    pub c1: RistrettoPoint, // r * G
+    pub c2: RistrettoPoint, // m * G + r * pk
+}
+
+impl ExponentialCiphertext {
+    /// Encrypts `count` to `public_key`
+    pub fn encrypt(
+        count: u64,
+        public_key: &RistrettoPoint,
+    ) -> Result<ExponentialCiphertext, CryptoError> {
+        let r = Scalar::random(&mut secure_rng()?);
+        Ok(ExponentialCiphertext {
+            c1: r * RISTRETTO_BASEPOINT_POINT,
+            c2: Scalar::from(count) * RISTRETTO_BASEPOINT_POINT + r * public_key,
+        })
+    }
+
+    /// Component-wise sum, an encryption of the sum of both counts
+    pub fn aggregate(&self, other: &ExponentialCiphertext) -> ExponentialCiphertext {
+        ExponentialCiphertext {
+            c1: self.c1 + other.c1,
+            c2: self.c2 + other.c2,
+        }
+    }
+
+    /// Pedersen commitment c2 = m * G + r * pk to the count. The commitment of an
+    /// `aggregate` is the sum of the commitments of its parts.
+    pub fn commitment(&self) -> RistrettoPoint {
+        self.c2
+    }
+
+    /// Recovers the count by trying every value up to `max_count`, None if it is larger.
+    /// Solving the discrete log is linear in the count, so keep counts small.
+    pub fn decrypt(&self, private_key: &Scalar, max_count: u64) -> Option<u64> {
+        let target = self.c2 - private_key * self.c1;
+        let mut candidate = RistrettoPoint::identity();
+        for count in 0..=max_count {
+            if candidate == target {
+                return Some(count);
+            }
+            candidate += RISTRETTO_BASEPOINT_POINT;
+        }
+        None
+    }
+}
+
+/// Ciphertext rewrapped by a proxy for the delegatee
+#[derive(Clone)]
+pub struct RewrappedCiphertext {
+    pub c1: RistrettoPoint,        // c1^rk = g^(r * sk_A / d)
+    pub c2: Scalar,                // Unchanged C2 = M + Hash(pk_A^r)
+    pub ephemeral: RistrettoPoint, // X of the re-encryption key
+}
+
+impl RewrappedCiphertext {
+    /// Decrypts with the delegatee's private key
+    pub fn decrypt(&self, delegatee_sk: &Scalar) -> Scalar {
+        let delegatee_pk = delegatee_sk * RISTRETTO_BASEPOINT_POINT;
+        let d = rekey_blinding(
+            &self.ephemeral,
+            &delegatee_pk,
+            &(self.ephemeral * delegatee_sk),
+        );
+        let shared_secret = (self.c1 * d).compress(); // g^(r * sk_A) = pk_A^r
+
+        let hashed_secret = HashAlgorithm::Sha512.hash_to_scalar(&[shared_secret.as_bytes()]);
+        self.c2 - hashed_secret
+    }
+}
+
+/// d = H(X || pk_B || pk_B^x), computed by the delegator with x and by the delegatee with sk_B
+fn rekey_blinding(
+    ephemeral: &RistrettoPoint,
+    delegatee_pk: &RistrettoPoint,
+    shared: &RistrettoPoint,
+) -> Scalar {
+    HashAlgorithm::Sha512.hash_to_scalar(&[
+        REKEY_DOMAIN,
+        ephemeral.compress().as_bytes(),
+        delegatee_pk.compress().as_bytes(),
+        shared.compress().as_bytes(),
+    ])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use curve25519_dalek::scalar::Scalar; // Ensure KeyPair is imported for testing
-use crate::keys::KeyPair;
-// use crate::keys::generate_keys;
+    use rand::rngs::OsRng;
 
     #[test]
     fn test_elgamal_correctness() {
         // Generate key pair for encryption and decryption
-        let keypair = ElGamalCiphertext::keygen();
+        let keypair = ElGamalCiphertext::keygen().unwrap();
 
         // Message to encrypt (as a scalar)
         let message = Scalar::random(&mut OsRng);
 
         // Encrypt the message
-        let ciphertext = ElGamalCiphertext::encrypt(&message, &keypair.public_key);
+        let ciphertext = ElGamalCiphertext::encrypt(&message, &keypair.public_key).unwrap();
 
         // Decrypt the message
         let decrypted_message = ciphertext.decrypt(&keypair.private_key);
@@ -87,14 +258,14 @@ use crate::keys::KeyPair;
     #[test]
     fn test_elgamal_different_keys() {
         // Generate two different key pairs
-        let keypair1 = ElGamalCiphertext::keygen();
-        let keypair2 = ElGamalCiphertext::keygen();
+        let keypair1 = ElGamalCiphertext::keygen().unwrap();
+        let keypair2 = ElGamalCiphertext::keygen().unwrap();
 
         // Message to encrypt
         let message = Scalar::random(&mut OsRng);
 
         // Encrypt with the first keypair
-        let ciphertext = ElGamalCiphertext::encrypt(&message, &keypair1.public_key);
+        let ciphertext = ElGamalCiphertext::encrypt(&message, &keypair1.public_key).unwrap();
 
         // Attempt to decrypt with the wrong keypair
         let decrypted_message_wrong_key = ciphertext.decrypt(&keypair2.private_key);
@@ -109,14 +280,14 @@ use crate::keys::KeyPair;
     #[test]
     fn test_elgamal_repeated_encryption_produces_different_ciphertexts() {
         // Generate key pair
-        let keypair = ElGamalCiphertext::keygen();
+        let keypair = ElGamalCiphertext::keygen().unwrap();
 
         // Message to encrypt
         let message = Scalar::random(&mut OsRng);
 
         // Encrypt the same message twice
-        let ciphertext1 = ElGamalCiphertext::encrypt(&message, &keypair.public_key);
-        let ciphertext2 = ElGamalCiphertext::encrypt(&message, &keypair.public_key);
+        let ciphertext1 = ElGamalCiphertext::encrypt(&message, &keypair.public_key).unwrap();
+        let ciphertext2 = ElGamalCiphertext::encrypt(&message, &keypair.public_key).unwrap();
 
         // Ensure that the two ciphertexts are different due to randomness in encryption
         assert_ne!(
@@ -132,13 +303,13 @@ use crate::keys::KeyPair;
     #[test]
     fn test_elgamal_encrypt_small_scalar() {
         // Generate key pair
-        let keypair = ElGamalCiphertext::keygen();
+        let keypair = ElGamalCiphertext::keygen().unwrap();
 
         // Small message to encrypt (scalar of 1)
         let small_message = Scalar::ONE;
 
         // Encrypt the small scalar
-        let ciphertext = ElGamalCiphertext::encrypt(&small_message, &keypair.public_key);
+        let ciphertext = ElGamalCiphertext::encrypt(&small_message, &keypair.public_key).unwrap();
 
         // Decrypt the message
         let decrypted_message = ciphertext.decrypt(&keypair.private_key);
@@ -153,13 +324,13 @@ use crate::keys::KeyPair;
     #[test]
     fn test_elgamal_encrypt_zero_scalar() {
         // Generate key pair
-        let keypair = ElGamalCiphertext::keygen();
+        let keypair = ElGamalCiphertext::keygen().unwrap();
 
         // Message to encrypt (scalar of 0)
         let zero_message = Scalar::ZERO;
 
         // Encrypt the zero scalar
-        let ciphertext = ElGamalCiphertext::encrypt(&zero_message, &keypair.public_key);
+        let ciphertext = ElGamalCiphertext::encrypt(&zero_message, &keypair.public_key).unwrap();
 
         // Decrypt the message
         let decrypted_message = ciphertext.decrypt(&keypair.private_key);
@@ -170,4 +341,138 @@ use crate::keys::KeyPair;
             "Decrypted zero scalar message should match the original zero scalar"
         );
     }
+
+    #[test]
+    fn test_key_pair_matches() {
+        let keypair = ElGamalCiphertext::keygen().unwrap();
+        let other = ElGamalCiphertext::keygen().unwrap();
+        let r = Scalar::random(&mut OsRng);
+        let ciphertext = ElGamalCiphertext::encrypt_with_randomness(
+            HashAlgorithm::Sha512,
+            &Scalar::from(42u64),
+            &keypair.public_key,
+            &r,
+        );
+
+        // The check agrees with comparing both sides' shared secrets when r is known
+        for sk in [keypair.private_key, other.private_key] {
+            let secrets_agree = keypair.public_key * r == ciphertext.c1 * sk;
+            assert_eq!(key_pair_matches(&sk, &keypair.public_key), secrets_agree);
+        }
+        assert!(key_pair_matches(&keypair.private_key, &keypair.public_key));
+        assert!(!key_pair_matches(&other.private_key, &keypair.public_key));
+    }
+
+    #[test]
+    fn test_commitment_is_deterministic() {
+        let tallier = ElGamalCiphertext::keygen().unwrap();
+        let yes = ElGamalCiphertext::encrypt(&Scalar::ONE, &tallier.public_key).unwrap();
+        let no = ElGamalCiphertext::encrypt(&Scalar::ZERO, &tallier.public_key).unwrap();
+        assert_eq!(
+            yes.commitment(),
+            yes.commitment(),
+            "Commitments should be deterministic"
+        );
+
+        assert_eq!(
+            yes.commitment(),
+            yes.c2 * RISTRETTO_BASEPOINT_POINT + yes.c1
+        );
+        assert_ne!(yes.commitment(), no.commitment());
+    }
+
+    #[test]
+    fn test_exponential_tally_decrypts_to_the_vote_count() {
+        let tallier = ElGamalCiphertext::keygen().unwrap();
+        let ballots: Vec<ExponentialCiphertext> = [1, 0, 1, 1, 0]
+            .iter()
+            .map(|&vote| ExponentialCiphertext::encrypt(vote, &tallier.public_key).unwrap())
+            .collect();
+
+        let tally = ballots[1..]
+            .iter()
+            .fold(ballots[0].clone(), |tally, ballot| tally.aggregate(ballot));
+        assert_eq!(tally.decrypt(&tallier.private_key, 5), Some(3));
+        assert_eq!(tally.decrypt(&tallier.private_key, 2), None);
+        assert_eq!(
+            tally.decrypt(&ElGamalCiphertext::keygen().unwrap().private_key, 5),
+            None
+        );
+
+        // Adding the commitments gives the commitment of the tally
+        let summed = ballots
+            .iter()
+            .map(ExponentialCiphertext::commitment)
+            .fold(RistrettoPoint::identity(), |sum, commitment| {
+                sum + commitment
+            });
+        assert_eq!(summed, tally.commitment());
+    }
+
+    #[cfg(feature = "blake2")]
+    #[test]
+    fn test_blake2b_encryption_roundtrip() {
+        let keypair = KeyPair::generate().unwrap();
+        let message = Scalar::random(&mut OsRng);
+
+        let ciphertext = ElGamalCiphertext::encrypt_with_hash(
+            HashAlgorithm::Blake2b,
+            &message,
+            &keypair.public_key,
+        )
+        .unwrap();
+        assert_eq!(
+            ciphertext.decrypt_with_hash(HashAlgorithm::Blake2b, &keypair.private_key),
+            message
+        );
+        assert_ne!(ciphertext.decrypt(&keypair.private_key), message);
+    }
+
+    #[test]
+    fn test_rewrapped_key_decrypts_for_delegatee() {
+        let delegator = KeyPair::generate().unwrap();
+        let delegatee = KeyPair::generate().unwrap();
+        let aes_key = Scalar::random(&mut OsRng);
+
+        let ciphertext = ElGamalCiphertext::encrypt(&aes_key, &delegator.public_key).unwrap();
+        let re_encryption_key =
+            ElGamalCiphertext::re_encryption_key(&delegator.private_key, &delegatee.public_key)
+                .unwrap();
+        let rewrapped = ciphertext.rewrap_key(&re_encryption_key);
+
+        assert_eq!(rewrapped.decrypt(&delegatee.private_key), aes_key);
+        assert_ne!(
+            rewrapped.decrypt(&KeyPair::generate().unwrap().private_key),
+            aes_key
+        );
+        assert_eq!(ciphertext.decrypt(&delegator.private_key), aes_key);
+    }
+
+    #[test]
+    fn test_proxy_cannot_unwrap_key() {
+        let delegator = KeyPair::generate().unwrap();
+        let delegatee = KeyPair::generate().unwrap();
+        let aes_key = Scalar::random(&mut OsRng);
+
+        let ciphertext = ElGamalCiphertext::encrypt(&aes_key, &delegator.public_key).unwrap();
+        let re_encryption_key =
+            ElGamalCiphertext::re_encryption_key(&delegator.private_key, &delegatee.public_key)
+                .unwrap();
+        let rewrapped = ciphertext.rewrap_key(&re_encryption_key);
+
+        // Everything the proxy holds, used as if it were a decryption key or shared secret
+        let unmask = |secret: &RistrettoPoint| {
+            rewrapped.c2 - HashAlgorithm::Sha512.hash_to_scalar(&[secret.compress().as_bytes()])
+        };
+        for guess in [
+            unmask(&rewrapped.c1),
+            unmask(&ciphertext.c1),
+            unmask(&(ciphertext.c1 * re_encryption_key.rk)),
+            unmask(&(re_encryption_key.ephemeral * re_encryption_key.rk)),
+            ciphertext.decrypt(&re_encryption_key.rk),
+            rewrapped.decrypt(&re_encryption_key.rk),
+        ] {
+            assert_ne!(guess, aes_key);
+        }
+    }
 }
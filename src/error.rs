@@ -0,0 +1,272 @@
+use crate::message::EncryptionMode;
+use crate::selftest::SelfTestStep;
+use crate::serializers::to_hex;
+use std::fmt;
+
+/// Errors returned by `Message` operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MessageError {
+    /// The signature does not verify against the embedded sender key
+    InvalidSignature,
+    /// The message still carries the unset sender or the placeholder signature
+    Unsigned,
+    /// The message is not addressed to the key used for decryption
+    RecipientMismatch,
+    /// The message is signed by a different key than the expected sender
+    SenderMismatch,
+    /// The encrypted payload could not be parsed
+    MalformedCiphertext(String),
+    /// Encrypting the payload failed
+    Encryption(String),
+    /// The ciphertext failed authentication (tampered data or wrong key)
+    Decryption(String),
+    /// The message could not be serialized or deserialized
+    Serialization(String),
+    /// The message uses fields or a format this build doesn't understand
+    UnsupportedFormat(String),
+    /// The message was written in a newer format version than this build understands
+    UnsupportedFormatVersion { found: u8, max_supported: u8 },
+    /// The operation does not match the mode the message was encrypted with
+    WrongMode(EncryptionMode),
+    /// Broadcast messages have no recipient and can only be signed
+    BroadcastNotEncryptable,
+    /// A token or field is not valid Base64
+    InvalidEncoding(String),
+    /// The serialized envelope does not fit in the requested fixed size
+    EnvelopeTooLarge { size: usize, limit: usize },
+    /// The padding of a fixed-size token is malformed
+    InvalidPadding,
+    /// The ASCII armor is missing its header, footer or checksum line
+    InvalidArmor,
+    /// The armored data does not match its CRC24 checksum
+    ChecksumMismatch,
+    /// A certificate's signature does not verify against its issuer
+    InvalidCertificate,
+    /// A certificate in the chain is past its expiry time
+    CertificateExpired,
+    /// A certificate does not certify the issuer of the previous one
+    BrokenCertificateChain,
+    /// The certificate chain does not end at a trusted root key
+    UntrustedRoot,
+    /// `Message::split` was asked for chunks of 0 bytes
+    InvalidChunkSize,
+    /// A chunk of a split payload is missing
+    MissingChunk(usize),
+    /// The index is past the end of the batch
+    IndexOutOfRange { index: usize, len: usize },
+    /// Chunks are duplicated, from different payloads or have malformed chunk headers
+    InconsistentChunks,
+    /// The message already carries a signature by a different key
+    AlreadySigned,
+    /// The message is already encrypted, encrypting it again would nest envelopes
+    AlreadyEncrypted,
+    /// The message is signed and encrypting would clear the signature, see `encrypt_strict`
+    SignatureWouldBeCleared,
+    /// The public key is the identity or otherwise unsafe to encrypt to
+    WeakKey,
+    /// An envelope field fails the structural checks of `Message::is_well_formed`
+    MalformedEnvelope(String),
+    /// The message is signed under a domain tag version this build doesn't know
+    UnsupportedDomainVersion(u8),
+    /// Reading or writing a framed message failed
+    Io(std::io::ErrorKind),
+    /// Reading or writing the file at `path` failed
+    FileIo {
+        path: String,
+        kind: std::io::ErrorKind,
+    },
+    /// A session message doesn't follow the last one accepted, it was dropped or reordered
+    SequenceBroken,
+    /// No key with this fingerprint is known
+    UnknownRecipient([u8; 8]),
+    /// The message carries no timestamp token
+    MissingTimestamp,
+    /// The timestamp token isn't signed by the authority or is for a different message
+    InvalidTimestamp,
+    /// The OS randomness source kept failing, see `keys::secure_rng`
+    RngUnavailable,
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::InvalidSignature => write!(f, "Invalid message signature"),
+            MessageError::Unsigned => write!(f, "Message is not signed"),
+            MessageError::RecipientMismatch => {
+                write!(f, "Message is not addressed to this key")
+            }
+            MessageError::SenderMismatch => write!(f, "Message is not from the expected sender"),
+            MessageError::MalformedCiphertext(e) => write!(f, "Malformed ciphertext: {}", e),
+            MessageError::Encryption(e) => write!(f, "Encryption failed: {}", e),
+            MessageError::Decryption(e) => write!(f, "Decryption failed: {}", e),
+            MessageError::Serialization(e) => write!(f, "Serialization failed: {}", e),
+            MessageError::UnsupportedFormat(e) => write!(f, "Unsupported message format: {}", e),
+            MessageError::UnsupportedFormatVersion {
+                found,
+                max_supported,
+            } => write!(
+                f,
+                "Format version {} is newer than the supported version {}",
+                found, max_supported
+            ),
+            MessageError::WrongMode(mode) => {
+                write!(f, "Operation not supported for {:?}-mode messages", mode)
+            }
+            MessageError::BroadcastNotEncryptable => {
+                write!(f, "Broadcast messages cannot be encrypted")
+            }
+            MessageError::InvalidEncoding(e) => write!(f, "Invalid encoding: {}", e),
+            MessageError::EnvelopeTooLarge { size, limit } => write!(
+                f,
+                "Envelope of {} bytes does not fit in {} bytes",
+                size, limit
+            ),
+            MessageError::InvalidPadding => write!(f, "Invalid token padding"),
+            MessageError::InvalidArmor => write!(f, "Invalid ASCII armor"),
+            MessageError::ChecksumMismatch => write!(f, "Armor checksum mismatch"),
+            MessageError::InvalidCertificate => write!(f, "Invalid certificate signature"),
+            MessageError::CertificateExpired => write!(f, "Certificate has expired"),
+            MessageError::BrokenCertificateChain => write!(f, "Certificate chain is broken"),
+            MessageError::UntrustedRoot => {
+                write!(f, "Certificate chain does not lead to a trusted root")
+            }
+            MessageError::InvalidChunkSize => write!(f, "Chunk size must be non-zero"),
+            MessageError::MissingChunk(index) => write!(f, "Chunk {} is missing", index),
+            MessageError::IndexOutOfRange { index, len } => {
+                write!(f, "Index {} is out of range for {} messages", index, len)
+            }
+            MessageError::InconsistentChunks => write!(f, "Chunks do not belong together"),
+            MessageError::AlreadySigned => {
+                write!(f, "Message is already signed by a different key")
+            }
+            MessageError::AlreadyEncrypted => write!(f, "Message is already encrypted"),
+            MessageError::SignatureWouldBeCleared => {
+                write!(f, "Encrypting would clear the message signature")
+            }
+            MessageError::WeakKey => write!(f, "Refusing to encrypt to a weak public key"),
+            MessageError::MalformedEnvelope(e) => write!(f, "Malformed envelope: {}", e),
+            MessageError::UnsupportedDomainVersion(version) => {
+                write!(f, "Unsupported signing domain version {}", version)
+            }
+            MessageError::Io(kind) => write!(f, "I/O error: {}", kind),
+            MessageError::FileIo { path, kind } => write!(f, "I/O error on {}: {}", path, kind),
+            MessageError::SequenceBroken => {
+                write!(f, "Message does not follow the previous one in the session")
+            }
+            MessageError::UnknownRecipient(fingerprint) => {
+                write!(f, "No key with fingerprint {}", to_hex(fingerprint))
+            }
+            MessageError::MissingTimestamp => write!(f, "Message has no timestamp"),
+            MessageError::InvalidTimestamp => write!(f, "Invalid timestamp token"),
+            MessageError::RngUnavailable => write!(f, "OS randomness source is unavailable"),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+/// Errors returned by AEAD decryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadError {
+    /// The input is too short to hold the nonce
+    InvalidNonceLength,
+    /// The tag doesn't match. GCM can't tell a wrong key from tampered data,
+    /// so both end up here
+    AuthenticationFailed,
+    /// The key derived from a scalar is all zeros, e.g. from a crafted ElGamal ciphertext
+    WeakSymmetricKey,
+}
+
+impl fmt::Display for AeadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AeadError::InvalidNonceLength => write!(f, "Invalid nonce length"),
+            AeadError::AuthenticationFailed => write!(f, "Authentication failed"),
+            AeadError::WeakSymmetricKey => write!(f, "Refusing an all-zero symmetric key"),
+        }
+    }
+}
+
+impl std::error::Error for AeadError {}
+
+/// Errors returned when parsing keys, scalars and ciphertexts from bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CryptoError {
+    /// The input doesn't have the exact length of the encoded value
+    WrongLength { expected: usize, actual: usize },
+    /// The input is shorter than the fixed-size part of the encoding
+    Truncated { minimum: usize, actual: usize },
+    /// The 32 bytes encode an integer at or above the group order
+    NonCanonicalScalar,
+    /// The 32 bytes are not the encoding of a Ristretto point
+    InvalidPoint,
+    /// The algorithm id is unknown or not compiled into this build
+    UnsupportedAlgorithm(u8),
+    /// The header sets flags this build doesn't understand
+    UnsupportedFlags(u8),
+    /// The public key is the identity or otherwise unsafe for key agreement
+    WeakPoint,
+    /// The private key doesn't belong to the stored public key
+    KeyPairMismatch,
+    /// A key is stored under a fingerprint that isn't its own
+    FingerprintMismatch,
+    /// The randomness source failed the sanity check of `keys::check_entropy`
+    WeakEntropy,
+    /// The OS randomness source kept failing, see `keys::secure_rng`
+    RngUnavailable,
+    /// Not a `scch1...` recipient string, or its checksum doesn't match
+    InvalidRecipientString,
+    /// The time-lock puzzle's modulus or base is out of range
+    InvalidPuzzle,
+    /// A step of `self_test` gave a wrong result, the build or platform is broken
+    SelfTestFailed(SelfTestStep),
+    /// More recipients than the multi-recipient limit
+    TooManyRecipients { limit: usize, actual: usize },
+    /// Not an archive, or its slot count and AES part length don't add up to its size
+    InvalidArchive,
+    /// The archive layout version is unknown to this build
+    UnsupportedArchiveVersion(u8),
+    /// Reading keys from disk failed
+    Io(std::io::ErrorKind),
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CryptoError::WrongLength { expected, actual } => {
+                write!(f, "Expected {} bytes, got {}", expected, actual)
+            }
+            CryptoError::Truncated { minimum, actual } => {
+                write!(f, "Expected at least {} bytes, got {}", minimum, actual)
+            }
+            CryptoError::NonCanonicalScalar => {
+                write!(f, "Scalar is not reduced modulo the group order")
+            }
+            CryptoError::InvalidPoint => write!(f, "Invalid Ristretto point encoding"),
+            CryptoError::UnsupportedAlgorithm(id) => write!(f, "Unsupported algorithm id {}", id),
+            CryptoError::UnsupportedFlags(flags) => write!(f, "Unsupported flags {:#04x}", flags),
+            CryptoError::WeakPoint => write!(f, "Weak public key"),
+            CryptoError::KeyPairMismatch => {
+                write!(f, "Private key does not match the stored public key")
+            }
+            CryptoError::FingerprintMismatch => {
+                write!(f, "Key is stored under the wrong fingerprint")
+            }
+            CryptoError::WeakEntropy => write!(f, "Randomness source looks broken"),
+            CryptoError::RngUnavailable => write!(f, "OS randomness source is unavailable"),
+            CryptoError::InvalidRecipientString => write!(f, "Invalid recipient string"),
+            CryptoError::InvalidPuzzle => write!(f, "Invalid time-lock puzzle"),
+            CryptoError::SelfTestFailed(step) => write!(f, "Self-test failed at {:?}", step),
+            CryptoError::TooManyRecipients { limit, actual } => {
+                write!(f, "{} recipients exceed the limit of {}", actual, limit)
+            }
+            CryptoError::InvalidArchive => write!(f, "Invalid archive"),
+            CryptoError::UnsupportedArchiveVersion(version) => {
+                write!(f, "Unsupported archive version {}", version)
+            }
+            CryptoError::Io(kind) => write!(f, "I/O error: {}", kind),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
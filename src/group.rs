@@ -0,0 +1,172 @@
+use crate::error::CryptoError;
+use crate::hybrid_enc::HybridCiphertext;
+use crate::keys::{fingerprint, is_weak_point, KeyPair};
+use crate::schnorr::SchnorrSignature;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::scalar::Scalar;
+use std::collections::BTreeMap;
+
+/// Key of a managed group. Messages are encrypted once to the group public key, and every
+/// member gets the group private key wrapped to their own key with `member_key`.
+/// Revoking a member rekeys the group, so the member can't read later messages. Messages
+/// from earlier epochs stay readable with the member keys of their epoch.
+pub struct GroupKey {
+    epoch: u32, // Incremented on every rekey
+    keypair: KeyPair,
+    members: BTreeMap<[u8; 8], RistrettoPoint>,
+}
+
+/// The group private key of one epoch, wrapped to a single member
+pub struct MemberKey {
+    pub epoch: u32,
+    pub wrapped_key: HybridCiphertext,
+}
+
+/// A message encrypted to the group key of `epoch`
+pub struct GroupCiphertext {
+    pub epoch: u32,
+    pub ciphertext: HybridCiphertext,
+}
+
+impl GroupKey {
+    /// Creates a group with a fresh key, rejecting weak member keys
+    pub fn new(members: &[RistrettoPoint]) -> Result<GroupKey, CryptoError> {
+        if members.iter().any(is_weak_point) {
+            return Err(CryptoError::WeakPoint);
+        }
+        Ok(GroupKey {
+            epoch: 0,
+            keypair: KeyPair::generate()?,
+            members: members
+                .iter()
+                .map(|member| (fingerprint(member), *member))
+                .collect(),
+        })
+    }
+
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    pub fn public_key(&self) -> RistrettoPoint {
+        self.keypair.public_key
+    }
+
+    /// Returns true if the member with `fingerprint` can read messages of the current epoch
+    pub fn is_member(&self, fingerprint: &[u8; 8]) -> bool {
+        self.members.contains_key(fingerprint)
+    }
+
+    /// Removes the member and rekeys the group, returns false if there is no such member.
+    /// Member keys have to be handed out again with `member_key` after a revocation.
+    /// Fails without removing the member if no new key can be generated.
+    pub fn revoke_member(&mut self, fingerprint: &[u8; 8]) -> Result<bool, CryptoError> {
+        if !self.members.contains_key(fingerprint) {
+            return Ok(false);
+        }
+        self.keypair = KeyPair::generate()?;
+        self.members.remove(fingerprint);
+        self.epoch += 1;
+        Ok(true)
+    }
+
+    /// Wraps the current group private key for the member with `fingerprint`,
+    /// None if it is not a member
+    pub fn member_key(&self, fingerprint: &[u8; 8]) -> Option<MemberKey> {
+        let member = self.members.get(fingerprint)?;
+        let wrapped_key =
+            HybridCiphertext::encrypt(&self.keypair.private_key.to_bytes(), member).ok()?;
+        Some(MemberKey {
+            epoch: self.epoch,
+            wrapped_key,
+        })
+    }
+
+    /// Encrypts the message to the group key of the current epoch
+    pub fn encrypt_to_group(&self, message: &[u8]) -> Result<GroupCiphertext, String> {
+        Ok(GroupCiphertext {
+            epoch: self.epoch,
+            ciphertext: HybridCiphertext::encrypt(message, &self.keypair.public_key)?,
+        })
+    }
+}
+
+impl GroupCiphertext {
+    /// Unwraps the group key with the member's private key and decrypts the message.
+    /// Fails if the member key is from a different epoch than the message.
+    pub fn decrypt_as_member(
+        &self,
+        member_key: &MemberKey,
+        private_key: &Scalar,
+    ) -> Result<Vec<u8>, String> {
+        if member_key.epoch != self.epoch {
+            return Err(format!(
+                "Member key of epoch {} can't decrypt a message of epoch {}",
+                member_key.epoch, self.epoch
+            ));
+        }
+        let group_key_bytes = member_key.wrapped_key.decrypt(private_key)?;
+        let group_key =
+            SchnorrSignature::bytes_to_scalar(&group_key_bytes).map_err(|e| e.to_string())?;
+        self.ciphertext.decrypt(&group_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revoked_member_cannot_read_later_messages() {
+        let alice = KeyPair::from_seed(1);
+        let bob = KeyPair::from_seed(2);
+        let (alice_id, bob_id) = (fingerprint(&alice.public_key), fingerprint(&bob.public_key));
+        let mut group = GroupKey::new(&[alice.public_key, bob.public_key]).unwrap();
+
+        let before = group.encrypt_to_group(b"Before revocation").unwrap();
+        let bob_old_key = group.member_key(&bob_id).unwrap();
+        assert_eq!(
+            before
+                .decrypt_as_member(&bob_old_key, &bob.private_key)
+                .unwrap(),
+            b"Before revocation"
+        );
+
+        let old_public_key = group.public_key();
+        assert!(group.revoke_member(&bob_id).unwrap());
+        assert!(
+            !group.revoke_member(&bob_id).unwrap(),
+            "Bob is no longer a member"
+        );
+        assert_ne!(
+            group.public_key(),
+            old_public_key,
+            "Revocation should rekey the group"
+        );
+        assert_eq!(group.epoch(), 1);
+        assert!(group.member_key(&bob_id).is_none());
+
+        let after = group.encrypt_to_group(b"After revocation").unwrap();
+        let alice_key = group.member_key(&alice_id).unwrap();
+        assert_eq!(
+            after
+                .decrypt_as_member(&alice_key, &alice.private_key)
+                .unwrap(),
+            b"After revocation"
+        );
+        assert!(after
+            .decrypt_as_member(&bob_old_key, &bob.private_key)
+            .is_err());
+        // Past messages stay readable
+        assert!(before
+            .decrypt_as_member(&bob_old_key, &bob.private_key)
+            .is_ok());
+
+        // Even ignoring the epoch, Bob's old group key doesn't open the new message
+        let stale = MemberKey {
+            epoch: after.epoch,
+            wrapped_key: bob_old_key.wrapped_key,
+        };
+        assert!(after.decrypt_as_member(&stale, &bob.private_key).is_err());
+    }
+}
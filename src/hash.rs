@@ -0,0 +1,37 @@
+//! 512-bit hash functions for the Schnorr challenge e = H(R || message) and the ElGamal
+//! mask H(pk^r). SHA-512 is the default and what every `sign`/`encrypt` without a hash
+//! argument uses. BLAKE2b-512 (feature `blake2`) is faster on some platforms; signatures and
+//! ciphertexts made with it only verify or decrypt with it, so both sides have to agree.
+
+use curve25519_dalek::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+use sha2::digest::consts::U64;
+use sha2::{Digest, Sha512};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha512,
+    #[cfg(feature = "blake2")]
+    Blake2b,
+}
+
+impl HashAlgorithm {
+    /// Hashes the concatenation of `parts` to a scalar, reducing the 64-byte digest
+    /// modulo the group order
+    pub fn hash_to_scalar(self, parts: &[&[u8]]) -> Scalar {
+        match self {
+            HashAlgorithm::Sha512 => hash_parts::<Sha512>(parts),
+            #[cfg(feature = "blake2")]
+            HashAlgorithm::Blake2b => hash_parts::<blake2::Blake2b512>(parts),
+        }
+    }
+}
+
+fn hash_parts<D: Digest<OutputSize = U64>>(parts: &[&[u8]]) -> Scalar {
+    let mut hasher = D::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    Scalar::from_hash(hasher)
+}
@@ -1,17 +1,105 @@
 use crate::aes::*;
+use crate::capabilities::AeadAlgorithm;
 use crate::elgamal::ElGamalCiphertext;
-use crate::keys::KeyPair;
-use curve25519_dalek::ristretto::CompressedRistretto;
+use crate::error::{AeadError, CryptoError};
+use crate::hash::HashAlgorithm;
+use crate::keys::{fingerprint, is_weak_point, secure_rng, KeyPair};
+use crate::schnorr::SchnorrSignature;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::RistrettoPoint;
 use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::{Sha256, Sha512};
+use zeroize::Zeroize;
+
+/// First byte of the tagged serialization, followed by the AEAD id and a flags byte.
+/// Legacy blobs start with the encoding of c1, whose lowest bit is always 0 for a valid
+/// Ristretto point, so an odd first byte can't be mistaken for one.
+const TAGGED_FORMAT_MARKER: u8 = 0x53;
+const TAGGED_HEADER_SIZE: usize = 3;
+/// Flag of the tagged header: the recipient's 8-byte fingerprint follows the header
+const FLAG_RECIPIENT_HINT: u8 = 0x01;
+/// Flag of the tagged header: the AES key is derived from the ElGamal scalar with HKDF
+const FLAG_HKDF_KEY: u8 = 0x02;
+
+/// HKDF info string of `KeyDerivation::Hkdf`
+const AES_KEY_INFO: &[u8] = b"secure-channel/hybrid/aes-key";
+
+/// Recipient limit of `MultiRecipientCiphertext::encrypt`. Every recipient adds a 72-byte
+/// slot, so unbounded lists would let a sender blow up stored ciphertexts.
+pub const DEFAULT_MAX_RECIPIENTS: usize = 256;
+
+/// Domain separator of the challenge in plaintext proofs
+const PLAINTEXT_PROOF_DOMAIN: &[u8] = b"secure-channel/plaintext-proof";
+
+/// Domain separator of the challenge in encryption proofs
+const ENCRYPTION_PROOF_DOMAIN: &[u8] = b"secure-channel/encryption-proof";
+
+/// Input hashed to the second Pedersen generator H
+const PEDERSEN_GENERATOR_DOMAIN: &[u8] = b"secure-channel/pedersen-generator";
+
+/// HKDF info string turning the key point of a `VerifiableCiphertext` into the AES key
+const VERIFIABLE_AES_KEY_INFO: &[u8] = b"secure-channel/verifiable/aes-key";
+
+/// HKDF info prefix of the values derived by `HybridCiphertext::encrypt_deterministic`
+const DETERMINISTIC_INFO: &[u8] = b"secure-channel/hybrid/deterministic/";
+
+/// The per-encryption secrets of a hybrid ciphertext
+struct EncryptionSecrets {
+    r: Scalar,       // ElGamal randomness
+    aes_key: Scalar, // Scalar the AES key is derived from
+    nonce: [u8; AES_NONCE_SIZE],
+}
+
+impl Drop for EncryptionSecrets {
+    fn drop(&mut self) {
+        self.r.zeroize();
+        self.aes_key.zeroize();
+    }
+}
+
+/// How the AES key is derived from the ElGamal-encrypted scalar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyDerivation {
+    /// The scalar's 32 bytes are the key. Its top bits are always zero, so the key has
+    /// only about 252 bits of entropy. Kept for reading legacy blobs, nothing encrypts
+    /// with it anymore.
+    Raw,
+    /// HKDF-SHA256 over the scalar bytes, giving a uniform 256-bit key
+    Hkdf,
+}
+
+impl KeyDerivation {
+    /// Turns the decrypted ElGamal scalar into AES key bytes, refusing a zero scalar
+    fn aes_key(self, scalar: &Scalar) -> Result<[u8; AES_KEY_SIZE], AeadError> {
+        // A crafted c2 = H(pk^r) unmasks to a zero scalar, reject it before it reaches the AEAD
+        if *scalar == Scalar::ZERO {
+            return Err(AeadError::WeakSymmetricKey);
+        }
+        match self {
+            KeyDerivation::Raw => Ok(scalar.to_bytes()),
+            KeyDerivation::Hkdf => {
+                let mut key = [0u8; AES_KEY_SIZE];
+                Hkdf::<Sha256>::new(None, scalar.as_bytes())
+                    .expand(AES_KEY_INFO, &mut key)
+                    .expect("32 bytes is a valid HKDF-SHA256 output length");
+                Ok(key)
+            }
+        }
+    }
+}
 
 pub struct HybridCiphertext {
     pub elgamal_ciphertext: ElGamalCiphertext,
     pub aes_ciphertext: AESCiphertext,
+    recipient_hint: Option<[u8; 8]>, // Fingerprint of the recipient, written in the tagged layout
+    key_derivation: KeyDerivation,   // Recorded as a flag in the tagged layout
 }
 
 impl HybridCiphertext {
-    pub fn keygen() -> KeyPair {
+    pub fn keygen() -> Result<KeyPair, CryptoError> {
         ElGamalCiphertext::keygen()
     }
 
@@ -19,35 +107,275 @@ impl HybridCiphertext {
         message: &[u8],
         public_key: &RistrettoPoint,
     ) -> Result<HybridCiphertext, String> {
+        Self::encrypt_with_algorithm(message, public_key, AeadAlgorithm::Aes256Gcm)
+    }
 
-        // Generate a random AES key
-        let aes_key = AESCiphertext::keygen();
+    /// Hybrid encryption with the given AEAD for the payload
+    pub fn encrypt_with_algorithm(
+        message: &[u8],
+        public_key: &RistrettoPoint,
+        algorithm: AeadAlgorithm,
+    ) -> Result<HybridCiphertext, String> {
+        let r = Scalar::random(&mut secure_rng().map_err(|e| e.to_string())?);
+        Self::encrypt_with_randomness(message, &[], public_key, algorithm, KeyDerivation::Hkdf, &r)
+    }
+
+    /// Hybrid encryption authenticating `aad` along with the payload, with an HKDF-derived
+    /// AES key. `aad` isn't encrypted or stored, decrypt with `decrypt_with_aad` and the
+    /// same bytes.
+    pub fn encrypt_with_aad(
+        message: &[u8],
+        aad: &[u8],
+        public_key: &RistrettoPoint,
+    ) -> Result<HybridCiphertext, String> {
+        let r = Scalar::random(&mut secure_rng().map_err(|e| e.to_string())?);
+        Self::encrypt_with_randomness(
+            message,
+            aad,
+            public_key,
+            AeadAlgorithm::Aes256Gcm,
+            KeyDerivation::Hkdf,
+            &r,
+        )
+    }
+
+    /// Like `encrypt`, also returning the ElGamal randomness so the sender can later prove
+    /// the contents with `prove_plaintext`. Whoever holds the randomness can decrypt the
+    /// ciphertext, so keep it as secret as the plaintext.
+    pub fn encrypt_retaining_randomness(
+        message: &[u8],
+        public_key: &RistrettoPoint,
+    ) -> Result<(HybridCiphertext, SenderRandomness), String> {
+        let r = Scalar::random(&mut secure_rng().map_err(|e| e.to_string())?);
+        let ciphertext = Self::encrypt_with_randomness(
+            message,
+            &[],
+            public_key,
+            AeadAlgorithm::Aes256Gcm,
+            KeyDerivation::Hkdf,
+            &r,
+        )?;
+        let randomness = SenderRandomness {
+            r,
+            recipient: *public_key,
+        };
+        Ok((ciphertext, randomness))
+    }
+
+    /// Hybrid encryption with a raw scalar AES key, to produce legacy blobs in tests
+    #[cfg(test)]
+    pub(crate) fn encrypt_legacy(
+        message: &[u8],
+        public_key: &RistrettoPoint,
+    ) -> Result<HybridCiphertext, String> {
+        let r = Scalar::random(&mut secure_rng().map_err(|e| e.to_string())?);
+        let algorithm = AeadAlgorithm::Aes256Gcm;
+        Self::encrypt_with_randomness(message, &[], public_key, algorithm, KeyDerivation::Raw, &r)
+    }
+
+    fn encrypt_with_randomness(
+        message: &[u8],
+        aad: &[u8],
+        public_key: &RistrettoPoint,
+        algorithm: AeadAlgorithm,
+        key_derivation: KeyDerivation,
+        r: &Scalar,
+    ) -> Result<HybridCiphertext, String> {
+        // Generate a random AES key and nonce
+        let mut nonce = [0u8; AES_NONCE_SIZE];
+        secure_rng()
+            .map_err(|e| e.to_string())?
+            .fill_bytes(&mut nonce);
+        let secrets = EncryptionSecrets {
+            r: *r,
+            aes_key: AESCiphertext::keygen().map_err(|e| e.to_string())?,
+            nonce,
+        };
+        Self::encrypt_with_secrets(
+            message,
+            aad,
+            public_key,
+            algorithm,
+            key_derivation,
+            &secrets,
+        )
+    }
+
+    /// Encrypts `message` like `encrypt_with_aad`, but derives the ElGamal randomness, the
+    /// AES key and the nonce from HKDF-SHA256 over `message`, salted with `ikm`, instead of
+    /// drawing them at random, so the same inputs always give byte-identical ciphertexts.
+    /// This reveals which ciphertexts hold the same plaintext, and anyone who knows `ikm` can
+    /// confirm a guessed plaintext. Only use it for test vectors and deduplication, with an
+    /// `ikm` kept as secret as the plaintexts.
+    pub fn encrypt_deterministic(
+        message: &[u8],
+        aad: &[u8],
+        public_key: &RistrettoPoint,
+        ikm: &[u8],
+    ) -> Result<HybridCiphertext, String> {
+        // As the salt, `ikm` can't run into the message the way a concatenation would
+        let hkdf = Hkdf::<Sha256>::new(Some(ikm), message);
+
+        let mut r_bytes = [0u8; 64];
+        let mut key_bytes = [0u8; 64];
+        let mut nonce = [0u8; AES_NONCE_SIZE];
+        for (info, output) in [
+            (&b"r"[..], &mut r_bytes[..]),
+            (b"aes-key", &mut key_bytes[..]),
+            (b"nonce", &mut nonce[..]),
+        ] {
+            hkdf.expand_multi_info(&[DETERMINISTIC_INFO, info], output)
+                .expect("Output lengths are valid for HKDF-SHA256");
+        }
+        let secrets = EncryptionSecrets {
+            r: Scalar::from_bytes_mod_order_wide(&r_bytes),
+            aes_key: Scalar::from_bytes_mod_order_wide(&key_bytes),
+            nonce,
+        };
+        r_bytes.zeroize();
+        key_bytes.zeroize();
+
+        Self::encrypt_with_secrets(
+            message,
+            aad,
+            public_key,
+            AeadAlgorithm::Aes256Gcm,
+            KeyDerivation::Hkdf,
+            &secrets,
+        )
+    }
+
+    fn encrypt_with_secrets(
+        message: &[u8],
+        aad: &[u8],
+        public_key: &RistrettoPoint,
+        algorithm: AeadAlgorithm,
+        key_derivation: KeyDerivation,
+        secrets: &EncryptionSecrets,
+    ) -> Result<HybridCiphertext, String> {
+        if is_weak_point(public_key) {
+            return Err(CryptoError::WeakPoint.to_string());
+        }
 
         // Encrypt the message using AES
-        let aes_ciphertext = AESCiphertext::encrypt(&aes_key, message)?;
+        let key_bytes = key_derivation
+            .aes_key(&secrets.aes_key)
+            .map_err(|e| e.to_string())?;
+        let aes_ciphertext = CipherContext::new(algorithm, &key_bytes).encrypt_with_nonce_and_aad(
+            &secrets.nonce,
+            message,
+            aad,
+        )?;
 
         // Encrypt the AES key using ElGamal
-        let elgamal_ciphertext = ElGamalCiphertext::encrypt(&aes_key, public_key);
+        let elgamal_ciphertext = ElGamalCiphertext::encrypt_with_randomness(
+            HashAlgorithm::Sha512,
+            &secrets.aes_key,
+            public_key,
+            &secrets.r,
+        );
 
         Ok(HybridCiphertext {
             elgamal_ciphertext,
             aes_ciphertext,
+            recipient_hint: None,
+            key_derivation,
         })
     }
 
     /// Hybrid decryption: Decrypts the AES key using the ElGamal private key, then decrypts the AES ciphertext
     pub fn decrypt(&self, private_key: &Scalar) -> Result<Vec<u8>, String> {
+        self.decrypt_with_aad(private_key, &[])
+    }
+
+    /// Decrypts a ciphertext from `encrypt_with_aad`, failing authentication unless `aad`
+    /// matches the bytes used for encryption
+    pub fn decrypt_with_aad(&self, private_key: &Scalar, aad: &[u8]) -> Result<Vec<u8>, String> {
         // Decrypt the AES key using ElGamal
         let aes_key = self.elgamal_ciphertext.decrypt(private_key);
+        let key_bytes = self
+            .key_derivation
+            .aes_key(&aes_key)
+            .map_err(|e| e.to_string())?;
 
         // Decrypt the AES ciphertext using the AES key
-        AESCiphertext::decrypt(&aes_key, &self.aes_ciphertext)
+        CipherContext::new(self.aes_ciphertext.algorithm, &key_bytes)
+            .decrypt_with_aad(&self.aes_ciphertext, aad)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Proves to anyone holding the recipient's public key that this ciphertext decrypts
+    /// to `claimed_plaintext`. The proof discloses the shared secret pk^r and with it the
+    /// AES key of this one ciphertext, but neither private key.
+    pub fn prove_plaintext(
+        &self,
+        sender_randomness: &SenderRandomness,
+        claimed_plaintext: &[u8],
+    ) -> Result<PlaintextProof, CryptoError> {
+        let SenderRandomness { r, recipient } = sender_randomness;
+        let shared_secret = r * recipient;
+
+        // Chaum-Pedersen proof that log_G(c1) = log_pk(pk^r)
+        let w = Scalar::random(&mut secure_rng()?);
+        let commitment_base = w * RISTRETTO_BASEPOINT_POINT;
+        let commitment_recipient = w * recipient;
+        let challenge = plaintext_proof_challenge(
+            self,
+            recipient,
+            &shared_secret,
+            &commitment_base,
+            &commitment_recipient,
+            claimed_plaintext,
+        );
+
+        Ok(PlaintextProof {
+            shared_secret,
+            commitment_base,
+            commitment_recipient,
+            response: w + challenge * r,
+        })
+    }
+
+    /// Embeds the fingerprint of `recipient` in the serialized form, so a decryptor holding
+    /// many keys can pick the right one with `recipient_hint` instead of trying them all.
+    /// The fingerprint is sent in the clear and links the ciphertext to the recipient.
+    pub fn with_recipient_hint(mut self, recipient: &RistrettoPoint) -> HybridCiphertext {
+        self.recipient_hint = Some(fingerprint(recipient));
+        self
+    }
+
+    /// The `keys::fingerprint` of the intended recipient, None for blobs without a hint
+    pub fn recipient_hint(&self) -> Option<[u8; 8]> {
+        self.recipient_hint
+    }
+
+    /// How the AES key is derived from the ElGamal-encrypted scalar
+    pub fn key_derivation(&self) -> KeyDerivation {
+        self.key_derivation
     }
 
-    /// Serializes the HybridCiphertext into a Vec<u8>
+    /// Serializes the HybridCiphertext into a Vec<u8>.
+    /// AES-256-GCM ciphertexts with a raw key and without a recipient hint use the legacy
+    /// untagged layout, other AEADs, the hint and HKDF keys are recorded in a tagged header.
     pub fn serialize(&self) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::new();
 
+        let algorithm = self.aes_ciphertext.algorithm;
+        let hkdf = self.key_derivation == KeyDerivation::Hkdf;
+        if algorithm != AeadAlgorithm::Aes256Gcm || self.recipient_hint.is_some() || hkdf {
+            let mut flags = 0;
+            if self.recipient_hint.is_some() {
+                flags |= FLAG_RECIPIENT_HINT;
+            }
+            if hkdf {
+                flags |= FLAG_HKDF_KEY;
+            }
+            buffer.extend_from_slice(&[TAGGED_FORMAT_MARKER, algorithm.id(), flags]);
+            if let Some(hint) = &self.recipient_hint {
+                buffer.extend_from_slice(hint);
+            }
+        }
+
         // Serialize ElGamalCiphertext (RistrettoPoint and Scalar)
         let c1_bytes = self.elgamal_ciphertext.c1.compress().to_bytes(); // 32 bytes
         let c2_bytes = self.elgamal_ciphertext.c2.to_bytes(); // 32 bytes
@@ -64,59 +392,499 @@ impl HybridCiphertext {
     }
 
     /// Deserializes a &[u8] back into a HybridCiphertext
-    pub fn deserialize(bytes: &[u8]) -> Result<HybridCiphertext, String> {
-        let mut offset = 0;
+    pub fn deserialize(bytes: &[u8]) -> Result<HybridCiphertext, CryptoError> {
+        let (algorithm, recipient_hint, key_derivation, bytes) = match bytes.first() {
+            Some(&first) if first & 1 == 1 => {
+                // An odd first byte other than the marker can't be a valid c1 either
+                if first != TAGGED_FORMAT_MARKER || bytes.len() < TAGGED_HEADER_SIZE {
+                    return Err(CryptoError::InvalidPoint);
+                }
+                let algorithm = AeadAlgorithm::from_id(bytes[1])
+                    .ok_or(CryptoError::UnsupportedAlgorithm(bytes[1]))?;
+                let flags = bytes[2];
+                if flags & !(FLAG_RECIPIENT_HINT | FLAG_HKDF_KEY) != 0 {
+                    return Err(CryptoError::UnsupportedFlags(flags));
+                }
+                let key_derivation = if flags & FLAG_HKDF_KEY == 0 {
+                    KeyDerivation::Raw
+                } else {
+                    KeyDerivation::Hkdf
+                };
+                let bytes = &bytes[TAGGED_HEADER_SIZE..];
+                if flags & FLAG_RECIPIENT_HINT == 0 {
+                    (algorithm, None, key_derivation, bytes)
+                } else {
+                    let hint: [u8; 8] = bytes
+                        .get(..8)
+                        .and_then(|hint| hint.try_into().ok())
+                        .ok_or(CryptoError::Truncated {
+                            minimum: 8,
+                            actual: bytes.len(),
+                        })?;
+                    (algorithm, Some(hint), key_derivation, &bytes[8..])
+                }
+            }
+            _ => (AeadAlgorithm::Aes256Gcm, None, KeyDerivation::Raw, bytes),
+        };
 
-        // Deserialize ElGamalCiphertext
-        if bytes.len() < 64 {
-            return Err("Not enough bytes to deserialize ElGamalCiphertext".to_string());
+        // c1 and c2 (32 bytes each) followed by the AES nonce and ciphertext
+        let minimum = 64 + AES_NONCE_SIZE;
+        if bytes.len() < minimum {
+            return Err(CryptoError::Truncated {
+                minimum,
+                actual: bytes.len(),
+            });
         }
 
-        // Deserialize c1 (RistrettoPoint)
-        let c1_bytes: [u8; 32] = bytes[offset..offset + 32]
-            .try_into()
-            .map_err(|_| "Invalid byte slice for c1".to_string())?;
-        offset += 32;
-
-        // Correctly handle the result from `CompressedRistretto::from_slice`
-        let c1_compressed = CompressedRistretto(c1_bytes);
-        let c1 = c1_compressed
-            .decompress()
-            .ok_or("Failed to decompress c1 RistrettoPoint")?;
-
-        // Deserialize c2 (Scalar)
-        let c2_bytes: [u8; 32] = bytes[offset..offset + 32]
-            .try_into()
-            .map_err(|_| "Invalid byte slice for c2".to_string())?;
-        offset += 32;
-        let c2 = Scalar::from_bytes_mod_order(c2_bytes);
-
+        let c1 = SchnorrSignature::bytes_to_point(&bytes[0..32])?;
+        let c2 = SchnorrSignature::bytes_to_scalar(&bytes[32..64])?;
         let elgamal_ciphertext = ElGamalCiphertext { c1, c2 };
 
-        // Deserialize AESCiphertext
-        if bytes.len() < offset + AES_NONCE_SIZE {
-            return Err("Not enough bytes to deserialize AESCiphertext".to_string());
+        let nonce: [u8; AES_NONCE_SIZE] =
+            bytes[64..minimum].try_into().expect("Length checked above");
+        let ciphertext = bytes[minimum..].to_vec();
+
+        let aes_ciphertext = AESCiphertext {
+            algorithm,
+            nonce,
+            ciphertext,
+        };
+
+        Ok(HybridCiphertext {
+            elgamal_ciphertext,
+            aes_ciphertext,
+            recipient_hint,
+            key_derivation,
+        })
+    }
+
+    /// Reads the recipient hint from the header of a serialized ciphertext without parsing
+    /// the rest. None for blobs without a hint and for malformed headers.
+    pub fn peek_recipient_hint(bytes: &[u8]) -> Option<[u8; 8]> {
+        if bytes.first() != Some(&TAGGED_FORMAT_MARKER)
+            || bytes
+                .get(2)
+                .is_none_or(|flags| flags & FLAG_RECIPIENT_HINT == 0)
+        {
+            return None;
         }
+        bytes
+            .get(TAGGED_HEADER_SIZE..TAGGED_HEADER_SIZE + 8)
+            .and_then(|hint| hint.try_into().ok())
+    }
+}
 
-        let nonce: [u8; AES_NONCE_SIZE] = bytes[offset..offset + AES_NONCE_SIZE]
-            .try_into()
-            .map_err(|_| "Invalid byte slice for nonce".to_string())?;
-        offset += AES_NONCE_SIZE;
+/// Finds the ciphertexts in an inbox that one key can decrypt. Blobs whose recipient hint
+/// names a different key are skipped from the header alone, only blobs hinted for this key
+/// and blobs without a hint are parsed and trial-decrypted.
+pub struct InboxScanner {
+    private_key: Scalar,
+    fingerprint: [u8; 8], // Fingerprint of the matching public key
+}
 
-        let ciphertext = bytes[offset..].to_vec(); 
+impl InboxScanner {
+    pub fn new(private_key: &Scalar) -> InboxScanner {
+        InboxScanner {
+            private_key: *private_key,
+            fingerprint: fingerprint(&(private_key * RISTRETTO_BASEPOINT_POINT)),
+        }
+    }
 
-        let aes_ciphertext = AESCiphertext { nonce, ciphertext };
+    /// Returns the index and plaintext of every serialized ciphertext in `inbox` that
+    /// decrypts under the key
+    pub fn scan<T: AsRef<[u8]>>(&self, inbox: &[T]) -> Vec<(usize, Vec<u8>)> {
+        inbox
+            .iter()
+            .enumerate()
+            .filter(|(_, bytes)| {
+                HybridCiphertext::peek_recipient_hint(bytes.as_ref())
+                    .is_none_or(|hint| hint == self.fingerprint)
+            })
+            .filter_map(|(index, bytes)| {
+                let plaintext = HybridCiphertext::deserialize(bytes.as_ref())
+                    .ok()?
+                    .decrypt(&self.private_key)
+                    .ok()?;
+                Some((index, plaintext))
+            })
+            .collect()
+    }
+}
 
-        Ok(HybridCiphertext {
-            elgamal_ciphertext,
+impl Drop for InboxScanner {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+    }
+}
+
+/// ElGamal randomness r of a ciphertext from `encrypt_retaining_randomness`
+pub struct SenderRandomness {
+    r: Scalar,
+    recipient: RistrettoPoint, // The public key the ciphertext was encrypted to
+}
+
+impl Drop for SenderRandomness {
+    fn drop(&mut self) {
+        self.r.zeroize();
+    }
+}
+
+/// Proof from `HybridCiphertext::prove_plaintext`: the shared secret pk^r and a
+/// Chaum-Pedersen proof that it belongs to the ciphertext's c1 = g^r
+#[derive(Debug, Clone)]
+pub struct PlaintextProof {
+    pub shared_secret: RistrettoPoint,        // pk^r
+    pub commitment_base: RistrettoPoint,      // g^w
+    pub commitment_recipient: RistrettoPoint, // pk^w
+    pub response: Scalar,                     // w + e * r
+}
+
+/// Checks that `ciphertext` decrypts to `claimed_plaintext` for the holder of
+/// `recipient_public_key`, using a proof from `HybridCiphertext::prove_plaintext`
+pub fn verify_plaintext_proof(
+    ciphertext: &HybridCiphertext,
+    claimed_plaintext: &[u8],
+    recipient_public_key: &RistrettoPoint,
+    proof: &PlaintextProof,
+) -> bool {
+    let challenge = plaintext_proof_challenge(
+        ciphertext,
+        recipient_public_key,
+        &proof.shared_secret,
+        &proof.commitment_base,
+        &proof.commitment_recipient,
+        claimed_plaintext,
+    );
+    let c1 = ciphertext.elgamal_ciphertext.c1;
+    if proof.response * RISTRETTO_BASEPOINT_POINT != proof.commitment_base + challenge * c1
+        || proof.response * recipient_public_key
+            != proof.commitment_recipient + challenge * proof.shared_secret
+    {
+        return false;
+    }
+
+    // With the shared secret proven, unmask the AES key the way the recipient would
+    let mask = HashAlgorithm::Sha512.hash_to_scalar(&[proof.shared_secret.compress().as_bytes()]);
+    let aes_key = ciphertext.elgamal_ciphertext.c2 - mask;
+    let Ok(key_bytes) = ciphertext.key_derivation.aes_key(&aes_key) else {
+        return false;
+    };
+    AESCiphertext::decrypt_with_key(&key_bytes, &ciphertext.aes_ciphertext)
+        .is_ok_and(|plaintext| plaintext == claimed_plaintext)
+}
+
+/// Fiat-Shamir challenge of a plaintext proof, binding the ciphertext and the claim
+fn plaintext_proof_challenge(
+    ciphertext: &HybridCiphertext,
+    recipient_public_key: &RistrettoPoint,
+    shared_secret: &RistrettoPoint,
+    commitment_base: &RistrettoPoint,
+    commitment_recipient: &RistrettoPoint,
+    claimed_plaintext: &[u8],
+) -> Scalar {
+    HashAlgorithm::Sha512.hash_to_scalar(&[
+        PLAINTEXT_PROOF_DOMAIN,
+        ciphertext.elgamal_ciphertext.c1.compress().as_bytes(),
+        &ciphertext.elgamal_ciphertext.c2.to_bytes(),
+        &ciphertext.aes_ciphertext.nonce,
+        &ciphertext.aes_ciphertext.ciphertext,
+        recipient_public_key.compress().as_bytes(),
+        shared_secret.compress().as_bytes(),
+        commitment_base.compress().as_bytes(),
+        commitment_recipient.compress().as_bytes(),
+        claimed_plaintext,
+    ])
+}
+
+/// ElGamal-wrapped copy of the AES key for one recipient
+#[derive(Clone)]
+pub struct RecipientSlot {
+    pub fingerprint: [u8; 8], // `keys::fingerprint` of the recipient's public key
+    pub elgamal_ciphertext: ElGamalCiphertext,
+}
+
+/// Hybrid ciphertext for several recipients: one AES body, with the AES key
+/// ElGamal-encrypted separately to each recipient
+pub struct MultiRecipientCiphertext {
+    pub slots: Vec<RecipientSlot>,
+    pub aes_ciphertext: AESCiphertext,
+    pub key_derivation: KeyDerivation, // Shared by all slots
+}
+
+impl MultiRecipientCiphertext {
+    /// Encrypts the message once and wraps the AES key for every recipient, at most
+    /// `DEFAULT_MAX_RECIPIENTS` of them
+    pub fn encrypt(
+        message: &[u8],
+        recipients: &[RistrettoPoint],
+    ) -> Result<MultiRecipientCiphertext, String> {
+        Self::encrypt_with_limit(message, recipients, DEFAULT_MAX_RECIPIENTS)
+    }
+
+    /// Like `encrypt`, with a custom limit on the number of recipients
+    pub fn encrypt_with_limit(
+        message: &[u8],
+        recipients: &[RistrettoPoint],
+        max_recipients: usize,
+    ) -> Result<MultiRecipientCiphertext, String> {
+        Self::encrypt_with_limit_and_aad(message, recipients, max_recipients, |_| Vec::new())
+    }
+
+    /// Like `encrypt`, also authenticating the bytes `aad` builds from the recipient slots,
+    /// e.g. a manifest listing them. The ciphertext `extract_for` returns then has to be
+    /// decrypted with `HybridCiphertext::decrypt_with_aad` and the same bytes.
+    pub fn encrypt_with_aad(
+        message: &[u8],
+        recipients: &[RistrettoPoint],
+        aad: impl FnOnce(&[RecipientSlot]) -> Vec<u8>,
+    ) -> Result<MultiRecipientCiphertext, String> {
+        Self::encrypt_with_limit_and_aad(message, recipients, DEFAULT_MAX_RECIPIENTS, aad)
+    }
+
+    fn encrypt_with_limit_and_aad(
+        message: &[u8],
+        recipients: &[RistrettoPoint],
+        max_recipients: usize,
+        aad: impl FnOnce(&[RecipientSlot]) -> Vec<u8>,
+    ) -> Result<MultiRecipientCiphertext, String> {
+        if recipients.len() > max_recipients {
+            return Err(CryptoError::TooManyRecipients {
+                limit: max_recipients,
+                actual: recipients.len(),
+            }
+            .to_string());
+        }
+        if recipients.iter().any(is_weak_point) {
+            return Err(CryptoError::WeakPoint.to_string());
+        }
+
+        let aes_key = AESCiphertext::keygen().map_err(|e| e.to_string())?;
+        let key_bytes = KeyDerivation::Hkdf
+            .aes_key(&aes_key)
+            .map_err(|e| e.to_string())?;
+
+        let slots = recipients
+            .iter()
+            .map(|public_key| {
+                Ok(RecipientSlot {
+                    fingerprint: fingerprint(public_key),
+                    elgamal_ciphertext: ElGamalCiphertext::encrypt(&aes_key, public_key)?,
+                })
+            })
+            .collect::<Result<Vec<_>, CryptoError>>()
+            .map_err(|e| e.to_string())?;
+        let aes_ciphertext = CipherContext::new(AeadAlgorithm::Aes256Gcm, &key_bytes)
+            .encrypt_with_aad(message, &aad(&slots))?;
+
+        Ok(MultiRecipientCiphertext {
+            slots,
             aes_ciphertext,
+            key_derivation: KeyDerivation::Hkdf,
         })
     }
+
+    /// Decrypts with the slot matching the private key's fingerprint
+    pub fn decrypt(&self, private_key: &Scalar) -> Result<Vec<u8>, String> {
+        let public_key = private_key * RISTRETTO_BASEPOINT_POINT;
+        self.extract_for(&fingerprint(&public_key))
+            .ok_or("No slot for this key")?
+            .decrypt(private_key)
+    }
+
+    /// Returns the single-recipient ciphertext for the recipient with `fingerprint`: the shared
+    /// AES body plus only that recipient's slot, so it can be stored or sent on its own
+    pub fn extract_for(&self, fingerprint: &[u8; 8]) -> Option<HybridCiphertext> {
+        self.slots
+            .iter()
+            .find(|slot| slot.fingerprint == *fingerprint)
+            .map(|slot| HybridCiphertext {
+                elgamal_ciphertext: slot.elgamal_ciphertext.clone(),
+                aes_ciphertext: self.aes_ciphertext.clone(),
+                recipient_hint: None,
+                key_derivation: self.key_derivation,
+            })
+    }
+}
+
+/// Second Pedersen generator H, hashed to the group so nobody knows its discrete log to G
+fn pedersen_generator() -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<Sha512>(PEDERSEN_GENERATOR_DOMAIN)
+}
+
+/// Opening of a Pedersen commitment value * G + blinding * H to an AES key value, published
+/// before encrypting with `VerifiableCiphertext::encrypt_with_proof`
+pub struct KeyCommitmentOpening {
+    pub value: Scalar,
+    pub blinding: Scalar,
+}
+
+impl KeyCommitmentOpening {
+    /// Random key value and blinding
+    pub fn random() -> Result<KeyCommitmentOpening, CryptoError> {
+        let mut rng = secure_rng()?;
+        Ok(KeyCommitmentOpening {
+            value: Scalar::random(&mut rng),
+            blinding: Scalar::random(&mut rng),
+        })
+    }
+
+    /// The commitment to publish, it reveals nothing about the value
+    pub fn commitment(&self) -> RistrettoPoint {
+        self.value * RISTRETTO_BASEPOINT_POINT + self.blinding * pedersen_generator()
+    }
+}
+
+impl Drop for KeyCommitmentOpening {
+    fn drop(&mut self) {
+        self.value.zeroize();
+        self.blinding.zeroize();
+    }
+}
+
+/// Hybrid ciphertext whose AES key comes from a committed value. Unlike `HybridCiphertext`,
+/// which masks the key scalar with a hash, the key point value * G is ElGamal-encrypted as
+/// a group element, so `EncryptionProof` can relate it to the commitment algebraically.
+#[derive(Debug, Clone)]
+pub struct VerifiableCiphertext {
+    pub c1: RistrettoPoint, // g^r
+    pub c2: RistrettoPoint, // value * G + r * pk
+    pub aes_ciphertext: AESCiphertext,
+}
+
+/// Zero-knowledge proof that a `VerifiableCiphertext` encrypts the key value of a
+/// commitment, from `VerifiableCiphertext::encrypt_with_proof`
+#[derive(Debug, Clone)]
+pub struct EncryptionProof {
+    pub commitment_c1: RistrettoPoint,         // a_r * G
+    pub commitment_c2: RistrettoPoint,         // a_k * G + a_r * pk
+    pub commitment_commitment: RistrettoPoint, // a_k * G + a_b * H
+    pub response_randomness: Scalar,           // a_r + e * r
+    pub response_value: Scalar,                // a_k + e * value
+    pub response_blinding: Scalar,             // a_b + e * blinding
+}
+
+impl VerifiableCiphertext {
+    /// Encrypts `message` under the key committed to by `opening` and proves that the
+    /// ElGamal part encrypts that key, without revealing it. The proof covers the key, not
+    /// the AES body: only the recipient can check that the body decrypts.
+    pub fn encrypt_with_proof(
+        message: &[u8],
+        public_key: &RistrettoPoint,
+        opening: &KeyCommitmentOpening,
+    ) -> Result<(VerifiableCiphertext, EncryptionProof), String> {
+        if is_weak_point(public_key) {
+            return Err(CryptoError::WeakPoint.to_string());
+        }
+        if opening.value == Scalar::ZERO {
+            return Err(AeadError::WeakSymmetricKey.to_string());
+        }
+        let key_point = opening.value * RISTRETTO_BASEPOINT_POINT;
+        let aes_ciphertext =
+            CipherContext::new(AeadAlgorithm::Aes256Gcm, &aes_key(&key_point)).encrypt(message)?;
+
+        let mut rng = secure_rng().map_err(|e| e.to_string())?;
+        let mut r = Scalar::random(&mut rng);
+        let ciphertext = VerifiableCiphertext {
+            c1: r * RISTRETTO_BASEPOINT_POINT,
+            c2: key_point + r * public_key,
+            aes_ciphertext,
+        };
+
+        // Sigma protocol for c1 = r * G, c2 = value * G + r * pk and
+        // commitment = value * G + blinding * H, made non-interactive with Fiat-Shamir
+        let h = pedersen_generator();
+        let mut nonces = [
+            Scalar::random(&mut rng),
+            Scalar::random(&mut rng),
+            Scalar::random(&mut rng),
+        ];
+        let [a_r, a_k, a_b] = nonces;
+        let mut proof = EncryptionProof {
+            commitment_c1: a_r * RISTRETTO_BASEPOINT_POINT,
+            commitment_c2: a_k * RISTRETTO_BASEPOINT_POINT + a_r * public_key,
+            commitment_commitment: a_k * RISTRETTO_BASEPOINT_POINT + a_b * h,
+            response_randomness: Scalar::ZERO,
+            response_value: Scalar::ZERO,
+            response_blinding: Scalar::ZERO,
+        };
+        let e = encryption_proof_challenge(&ciphertext, public_key, &opening.commitment(), &proof);
+        proof.response_randomness = a_r + e * r;
+        proof.response_value = a_k + e * opening.value;
+        proof.response_blinding = a_b + e * opening.blinding;
+        r.zeroize();
+        nonces.zeroize();
+
+        Ok((ciphertext, proof))
+    }
+
+    /// Recovers the key point with the recipient's private key and decrypts the AES body.
+    /// Refuses a weak c1, whose shared secret doesn't depend on the private key, and a key
+    /// point for a zero value, the same keys `encrypt_with_proof` refuses.
+    pub fn decrypt(&self, private_key: &Scalar) -> Result<Vec<u8>, String> {
+        if is_weak_point(&self.c1) {
+            return Err(CryptoError::WeakPoint.to_string());
+        }
+        let key_point = self.c2 - private_key * self.c1;
+        if key_point.is_identity() {
+            return Err(AeadError::WeakSymmetricKey.to_string());
+        }
+        CipherContext::new(self.aes_ciphertext.algorithm, &aes_key(&key_point))
+            .decrypt(&self.aes_ciphertext)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Checks that `ciphertext`, encrypted to `recipient_public_key`, carries the key value of
+/// `commitment`, using a proof from `VerifiableCiphertext::encrypt_with_proof`
+pub fn verify_encryption_proof(
+    ciphertext: &VerifiableCiphertext,
+    recipient_public_key: &RistrettoPoint,
+    commitment: &RistrettoPoint,
+    proof: &EncryptionProof,
+) -> bool {
+    let e = encryption_proof_challenge(ciphertext, recipient_public_key, commitment, proof);
+    let g = RISTRETTO_BASEPOINT_POINT;
+    proof.response_randomness * g == proof.commitment_c1 + e * ciphertext.c1
+        && proof.response_value * g + proof.response_randomness * recipient_public_key
+            == proof.commitment_c2 + e * ciphertext.c2
+        && proof.response_value * g + proof.response_blinding * pedersen_generator()
+            == proof.commitment_commitment + e * commitment
+}
+
+fn encryption_proof_challenge(
+    ciphertext: &VerifiableCiphertext,
+    recipient_public_key: &RistrettoPoint,
+    commitment: &RistrettoPoint,
+    proof: &EncryptionProof,
+) -> Scalar {
+    HashAlgorithm::Sha512.hash_to_scalar(&[
+        ENCRYPTION_PROOF_DOMAIN,
+        ciphertext.c1.compress().as_bytes(),
+        ciphertext.c2.compress().as_bytes(),
+        &ciphertext.aes_ciphertext.nonce,
+        &ciphertext.aes_ciphertext.ciphertext,
+        recipient_public_key.compress().as_bytes(),
+        commitment.compress().as_bytes(),
+        proof.commitment_c1.compress().as_bytes(),
+        proof.commitment_c2.compress().as_bytes(),
+        proof.commitment_commitment.compress().as_bytes(),
+    ])
+}
+
+/// AES key of a `VerifiableCiphertext`, derived from the key point with HKDF-SHA256
+fn aes_key(key_point: &RistrettoPoint) -> [u8; AES_KEY_SIZE] {
+    let mut key = [0u8; AES_KEY_SIZE];
+    Hkdf::<Sha256>::new(None, key_point.compress().as_bytes())
+        .expand(VERIFIABLE_AES_KEY_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use curve25519_dalek::traits::Identity;
+    use rand::rngs::OsRng;
 
     #[test]
     fn test_hybrid_encryption_decryption() {
@@ -124,7 +892,7 @@ mod tests {
         let message = b"Hello, hybrid encryption!";
 
         // Generate ElGamal keypair
-        let keypair = HybridCiphertext::keygen();
+        let keypair = HybridCiphertext::keygen().unwrap();
 
         // Perform hybrid encryption
         let hybrid_ciphertext = HybridCiphertext::encrypt(message, &keypair.public_key)
@@ -138,7 +906,6 @@ mod tests {
         // Ensure the decrypted message matches the original
         assert_eq!(decrypted_message, message);
     }
-    
 
     #[test]
     fn test_serialization_deserialization() {
@@ -146,7 +913,7 @@ mod tests {
         let message = b"Hello, hybrid encryption!";
 
         // Generate ElGamal keypair
-        let keypair = HybridCiphertext::keygen();
+        let keypair = HybridCiphertext::keygen().unwrap();
 
         // Perform hybrid encryption
         let hybrid_ciphertext = HybridCiphertext::encrypt(message, &keypair.public_key)
@@ -166,4 +933,402 @@ mod tests {
         // Ensure the decrypted message matches the original
         assert_eq!(decrypted_message, message);
     }
+
+    #[test]
+    fn test_deterministic_encryption_separates_ikm_and_message() {
+        let keypair = HybridCiphertext::keygen().unwrap();
+        let encrypt = |message: &[u8], ikm: &[u8]| {
+            HybridCiphertext::encrypt_deterministic(message, b"", &keypair.public_key, ikm)
+                .unwrap()
+                .serialize()
+        };
+
+        assert_eq!(encrypt(b"cd", b"ab"), encrypt(b"cd", b"ab"));
+        // Same concatenation, different split
+        assert_ne!(encrypt(b"cd", b"ab"), encrypt(b"bcd", b"a"));
+    }
+
+    #[test]
+    fn test_deserialize_reports_distinct_errors() {
+        let keypair = HybridCiphertext::keygen().unwrap();
+        // The untagged layout, where c1 and c2 start the blob
+        let serialized = HybridCiphertext::encrypt_legacy(b"Hello", &keypair.public_key)
+            .expect("Hybrid encryption failed")
+            .serialize();
+
+        assert!(matches!(
+            HybridCiphertext::deserialize(&serialized[..31]),
+            Err(CryptoError::Truncated { actual: 31, .. })
+        ));
+
+        let mut over_order = serialized.clone();
+        over_order[32..64].copy_from_slice(&[0xff; 32]);
+        assert!(matches!(
+            HybridCiphertext::deserialize(&over_order),
+            Err(CryptoError::NonCanonicalScalar)
+        ));
+
+        let mut invalid_point = serialized;
+        invalid_point[..32].copy_from_slice(&[0xff; 32]);
+        assert!(matches!(
+            HybridCiphertext::deserialize(&invalid_point),
+            Err(CryptoError::InvalidPoint)
+        ));
+    }
+
+    #[cfg(feature = "siv")]
+    #[test]
+    fn test_siv_algorithm_is_recorded_in_serialized_form() {
+        let message = b"Hello, hybrid encryption!";
+        let keypair = HybridCiphertext::keygen().unwrap();
+
+        let siv = HybridCiphertext::encrypt_with_algorithm(
+            message,
+            &keypair.public_key,
+            AeadAlgorithm::Aes256GcmSiv,
+        )
+        .expect("Hybrid encryption failed")
+        .serialize();
+        assert_eq!(siv[0], TAGGED_FORMAT_MARKER);
+
+        let deserialized = HybridCiphertext::deserialize(&siv).unwrap();
+        assert_eq!(
+            deserialized.aes_ciphertext.algorithm,
+            AeadAlgorithm::Aes256GcmSiv
+        );
+        assert_eq!(deserialized.decrypt(&keypair.private_key).unwrap(), message);
+
+        // GCM with a raw key keeps the legacy untagged layout
+        let gcm = HybridCiphertext::encrypt_legacy(message, &keypair.public_key)
+            .unwrap()
+            .serialize();
+        assert_eq!(gcm[0] & 1, 0);
+        assert_eq!(
+            HybridCiphertext::deserialize(&gcm)
+                .unwrap()
+                .aes_ciphertext
+                .algorithm,
+            AeadAlgorithm::Aes256Gcm
+        );
+    }
+
+    #[test]
+    fn test_multi_recipient_extract_for() {
+        let message = b"Hello, everyone!";
+        let alice = HybridCiphertext::keygen().unwrap();
+        let bob = HybridCiphertext::keygen().unwrap();
+        let multi = MultiRecipientCiphertext::encrypt(message, &[alice.public_key, bob.public_key])
+            .expect("Multi-recipient encryption failed");
+        assert_eq!(multi.decrypt(&alice.private_key).unwrap(), message);
+
+        let for_bob = multi
+            .extract_for(&fingerprint(&bob.public_key))
+            .expect("Bob should have a slot");
+        assert_eq!(
+            for_bob.aes_ciphertext.ciphertext,
+            multi.aes_ciphertext.ciphertext
+        );
+        assert_eq!(for_bob.aes_ciphertext.nonce, multi.aes_ciphertext.nonce);
+
+        // The extracted copy survives serialization and only opens for Bob
+        let for_bob = HybridCiphertext::deserialize(&for_bob.serialize()).unwrap();
+        assert_eq!(for_bob.decrypt(&bob.private_key).unwrap(), message);
+        assert!(for_bob.decrypt(&alice.private_key).is_err());
+
+        let stranger = HybridCiphertext::keygen().unwrap();
+        assert!(multi
+            .extract_for(&fingerprint(&stranger.public_key))
+            .is_none());
+    }
+
+    #[test]
+    fn test_inbox_scanner_matches_trial_decryption() {
+        let bob = KeyPair::from_seed(2);
+        let carol = KeyPair::from_seed(3);
+        let mut inbox = Vec::new();
+        for index in 0..40u8 {
+            let recipient = if index % 3 == 0 { &bob } else { &carol };
+            let ciphertext = HybridCiphertext::encrypt(&[index], &recipient.public_key).unwrap();
+            // Every fourth blob goes out without a hint and has to be trial-decrypted
+            inbox.push(if index % 4 == 0 {
+                ciphertext.serialize()
+            } else {
+                ciphertext
+                    .with_recipient_hint(&recipient.public_key)
+                    .serialize()
+            });
+        }
+        inbox.push(vec![TAGGED_FORMAT_MARKER, 0, FLAG_RECIPIENT_HINT]);
+
+        let naive: Vec<(usize, Vec<u8>)> = inbox
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bytes)| {
+                let ciphertext = HybridCiphertext::deserialize(bytes).ok()?;
+                Some((index, ciphertext.decrypt(&bob.private_key).ok()?))
+            })
+            .collect();
+        let scanned = InboxScanner::new(&bob.private_key).scan(&inbox);
+        assert_eq!(scanned, naive);
+        assert_eq!(scanned.len(), 14);
+        assert!(scanned
+            .iter()
+            .all(|(index, plaintext)| plaintext == &[*index as u8]));
+
+        assert_eq!(
+            HybridCiphertext::peek_recipient_hint(&inbox[1]),
+            Some(fingerprint(&carol.public_key))
+        );
+        assert_eq!(HybridCiphertext::peek_recipient_hint(&inbox[0]), None);
+    }
+
+    #[test]
+    fn test_recipient_hint_selects_key() {
+        let message = b"Hello, hinted recipient!";
+        let alice = HybridCiphertext::keygen().unwrap();
+        let bob = HybridCiphertext::keygen().unwrap();
+
+        let serialized = HybridCiphertext::encrypt(message, &bob.public_key)
+            .expect("Hybrid encryption failed")
+            .with_recipient_hint(&bob.public_key)
+            .serialize();
+        assert_eq!(serialized[0], TAGGED_FORMAT_MARKER);
+
+        let deserialized = HybridCiphertext::deserialize(&serialized).unwrap();
+        let hint = deserialized
+            .recipient_hint()
+            .expect("The hint should be embedded");
+        assert_eq!(hint, fingerprint(&bob.public_key));
+        assert_ne!(hint, fingerprint(&alice.public_key));
+        assert_eq!(deserialized.decrypt(&bob.private_key).unwrap(), message);
+
+        // Legacy blobs still deserialize, just without a hint
+        let legacy = HybridCiphertext::encrypt(message, &bob.public_key)
+            .unwrap()
+            .serialize();
+        let deserialized = HybridCiphertext::deserialize(&legacy).unwrap();
+        assert_eq!(deserialized.recipient_hint(), None);
+        assert_eq!(deserialized.decrypt(&bob.private_key).unwrap(), message);
+
+        assert!(matches!(
+            HybridCiphertext::deserialize(&serialized[..TAGGED_HEADER_SIZE + 4]),
+            Err(CryptoError::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_zero_decapsulated_key_is_rejected() {
+        let keypair = HybridCiphertext::keygen().unwrap();
+
+        // A crafted ciphertext wrapping a zero AES key around a payload under the all-zero key
+        let crafted = HybridCiphertext {
+            elgamal_ciphertext: ElGamalCiphertext::encrypt(&Scalar::ZERO, &keypair.public_key)
+                .unwrap(),
+            aes_ciphertext: AESCiphertext::encrypt_with_key(&[0; AES_KEY_SIZE], b"Crafted")
+                .unwrap(),
+            recipient_hint: None,
+            key_derivation: KeyDerivation::Raw,
+        };
+        assert_eq!(
+            crafted.decrypt(&keypair.private_key),
+            Err(crate::error::AeadError::WeakSymmetricKey.to_string())
+        );
+        assert_eq!(
+            AESCiphertext::decrypt(&Scalar::ZERO, &crafted.aes_ciphertext),
+            Err(crate::error::AeadError::WeakSymmetricKey)
+        );
+    }
+
+    #[test]
+    fn test_multi_recipient_limit() {
+        let recipients: Vec<RistrettoPoint> = (0..3)
+            .map(|_| HybridCiphertext::keygen().unwrap().public_key)
+            .collect();
+
+        assert_eq!(
+            MultiRecipientCiphertext::encrypt_with_limit(b"Hello", &recipients, 2).err(),
+            Some(
+                CryptoError::TooManyRecipients {
+                    limit: 2,
+                    actual: 3
+                }
+                .to_string()
+            )
+        );
+        let multi = MultiRecipientCiphertext::encrypt_with_limit(b"Hello", &recipients, 3)
+            .expect("The limit is inclusive");
+        assert_eq!(multi.slots.len(), 3);
+
+        let too_many = vec![recipients[0]; DEFAULT_MAX_RECIPIENTS + 1];
+        assert!(MultiRecipientCiphertext::encrypt(b"Hello", &too_many).is_err());
+    }
+
+    #[test]
+    fn test_plaintext_proof() {
+        let recipient = KeyPair::from_seed(1);
+        let message = b"I promised to pay 10 coins";
+        let (ciphertext, randomness) =
+            HybridCiphertext::encrypt_retaining_randomness(message, &recipient.public_key).unwrap();
+        assert_eq!(ciphertext.decrypt(&recipient.private_key).unwrap(), message);
+
+        let proof = ciphertext.prove_plaintext(&randomness, message).unwrap();
+        assert!(verify_plaintext_proof(
+            &ciphertext,
+            message,
+            &recipient.public_key,
+            &proof
+        ));
+
+        // The proof doesn't carry over to another recipient key
+        let other = KeyPair::from_seed(2);
+        assert!(!verify_plaintext_proof(
+            &ciphertext,
+            message,
+            &other.public_key,
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_false_plaintext_claim_fails() {
+        let recipient = KeyPair::from_seed(1);
+        let (ciphertext, randomness) = HybridCiphertext::encrypt_retaining_randomness(
+            b"I promised to pay 10 coins",
+            &recipient.public_key,
+        )
+        .unwrap();
+
+        let claim = b"I promised to pay 99 coins";
+        let proof = ciphertext.prove_plaintext(&randomness, claim).unwrap();
+        assert!(!verify_plaintext_proof(
+            &ciphertext,
+            claim,
+            &recipient.public_key,
+            &proof
+        ));
+
+        // A made-up shared secret fails the Chaum-Pedersen check
+        let forged = PlaintextProof {
+            shared_secret: RistrettoPoint::random(&mut OsRng),
+            ..proof
+        };
+        assert!(!verify_plaintext_proof(
+            &ciphertext,
+            claim,
+            &recipient.public_key,
+            &forged
+        ));
+    }
+
+    #[test]
+    fn test_encryption_proof() {
+        let recipient = KeyPair::from_seed(1);
+        let message = b"Escrowed under the committed key";
+        let opening = KeyCommitmentOpening::random().unwrap();
+        let commitment = opening.commitment();
+
+        let (ciphertext, proof) =
+            VerifiableCiphertext::encrypt_with_proof(message, &recipient.public_key, &opening)
+                .unwrap();
+        assert!(verify_encryption_proof(
+            &ciphertext,
+            &recipient.public_key,
+            &commitment,
+            &proof
+        ));
+        assert_eq!(ciphertext.decrypt(&recipient.private_key).unwrap(), message);
+        assert!(ciphertext
+            .decrypt(&KeyPair::from_seed(2).private_key)
+            .is_err());
+
+        // A zero key value or an identity c1 would make the AES key public
+        let r = Scalar::random(&mut OsRng);
+        let zero_value = VerifiableCiphertext {
+            c1: r * RISTRETTO_BASEPOINT_POINT,
+            c2: r * recipient.public_key,
+            ..ciphertext.clone()
+        };
+        assert_eq!(
+            zero_value.decrypt(&recipient.private_key),
+            Err(AeadError::WeakSymmetricKey.to_string())
+        );
+        let identity_c1 = VerifiableCiphertext {
+            c1: RistrettoPoint::identity(),
+            ..ciphertext
+        };
+        assert_eq!(
+            identity_c1.decrypt(&recipient.private_key),
+            Err(CryptoError::WeakPoint.to_string())
+        );
+    }
+
+    #[test]
+    fn test_encryption_proof_rejects_other_commitment() {
+        let recipient = KeyPair::from_seed(1);
+        let opening = KeyCommitmentOpening::random().unwrap();
+        let (ciphertext, proof) =
+            VerifiableCiphertext::encrypt_with_proof(b"Payload", &recipient.public_key, &opening)
+                .unwrap();
+
+        let other = KeyCommitmentOpening::random().unwrap().commitment();
+        assert!(!verify_encryption_proof(
+            &ciphertext,
+            &recipient.public_key,
+            &other,
+            &proof
+        ));
+        // Same value, different blinding
+        let reblinded = KeyCommitmentOpening {
+            value: opening.value,
+            blinding: Scalar::random(&mut OsRng),
+        };
+        assert!(!verify_encryption_proof(
+            &ciphertext,
+            &recipient.public_key,
+            &reblinded.commitment(),
+            &proof
+        ));
+
+        // Swapping in a ciphertext of another key breaks the proof
+        let (swapped, _) = VerifiableCiphertext::encrypt_with_proof(
+            b"Payload",
+            &recipient.public_key,
+            &KeyCommitmentOpening::random().unwrap(),
+        )
+        .unwrap();
+        assert!(!verify_encryption_proof(
+            &swapped,
+            &recipient.public_key,
+            &opening.commitment(),
+            &proof
+        ));
+    }
+
+    #[test]
+    fn test_c2_equal_to_mask_is_rejected() {
+        let keypair = HybridCiphertext::keygen().unwrap();
+
+        // c2 = H(pk^r) makes decryption recover m = c2 - H(c1^sk) = 0
+        let r = Scalar::random(&mut OsRng);
+        let shared_secret = r * keypair.public_key;
+        let crafted = HybridCiphertext {
+            elgamal_ciphertext: ElGamalCiphertext {
+                c1: r * RISTRETTO_BASEPOINT_POINT,
+                c2: HashAlgorithm::Sha512.hash_to_scalar(&[shared_secret.compress().as_bytes()]),
+            },
+            aes_ciphertext: AESCiphertext::encrypt_with_key(&[0; AES_KEY_SIZE], b"Crafted")
+                .unwrap(),
+            recipient_hint: None,
+            key_derivation: KeyDerivation::Raw,
+        };
+
+        assert_eq!(
+            crafted.elgamal_ciphertext.decrypt(&keypair.private_key),
+            Scalar::ZERO
+        );
+        assert_eq!(
+            crafted.decrypt(&keypair.private_key),
+            Err(AeadError::WeakSymmetricKey.to_string())
+        );
+    }
 }
@@ -1,11 +1,34 @@
-use base64::prelude::*;
+use crate::error::CryptoError;
+use crate::schnorr::SchnorrSignature;
+use bech32::primitives::decode::CheckedHrpstring;
+use bech32::{Bech32, Hrp};
 use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::IsIdentity;
+use hkdf::Hkdf;
 use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use std::fs::File;
+use std::io::Read;
 use std::io::Write;
-use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+/// Domain separator prepended to challenges before signing, so a challenge response can't be
+/// passed off as a message signature or the other way round
+const CHALLENGE_DOMAIN: &[u8] = b"secure-channel/challenge-response";
+
+/// Domain separator of signatures bound to a verifier's nonce by `sign_fresh`
+const FRESH_SIGNATURE_DOMAIN: &[u8] = b"secure-channel/fresh-signature";
+
+/// Human-readable part of recipient strings, `scch1...`
+const RECIPIENT_HRP: Hrp = Hrp::parse_unchecked("scch");
+
+/// Prefix of the HKDF info labels of `expand_keys`, followed by the key index
+const EXPAND_KEYS_INFO: &[u8] = b"secure-channel/expand-keys/";
 
 /// Struct to hold public and private key pair
 #[derive(Debug)]
@@ -16,25 +39,51 @@ pub struct KeyPair {
 
 impl KeyPair {
     /// Generate a Schnorr signature key pair
-    pub fn generate() -> KeyPair {
-        let mut rng = OsRng; // Secure random number generator
+    pub fn generate() -> Result<KeyPair, CryptoError> {
+        let mut rng = secure_rng()?; // Secure random number generator
         let private_key = Scalar::random(&mut rng); // Generate random scalar sk
-        let public_key = &private_key * &RISTRETTO_BASEPOINT_POINT; // pk = g^sk
+        let public_key = private_key * RISTRETTO_BASEPOINT_POINT; // pk = g^sk
+
+        Ok(KeyPair {
+            private_key,
+            public_key,
+        })
+    }
+
+    /// Like `generate`, but runs `check_entropy` first and refuses to generate a key from
+    /// a randomness source that looks broken
+    pub fn generate_checked() -> Result<KeyPair, CryptoError> {
+        check_entropy()?;
+        KeyPair::generate()
+    }
+
+    /// Derives a reproducible key pair from `seed` with ChaCha20, for tests only:
+    /// the seed space is tiny, so these keys must never protect real data
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_seed(seed: u64) -> KeyPair {
+        use rand_chacha::rand_core::SeedableRng;
+
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(seed);
+        let private_key = Scalar::random(&mut rng);
+        let public_key = private_key * RISTRETTO_BASEPOINT_POINT;
 
         KeyPair {
             private_key,
             public_key,
         }
     }
+
     pub fn write_sk_to_file(&self, filepath: &str) -> Result<(), String> {
-        let mut file = File::create(filepath).map_err(|e| format!("Failed to create file: {}", e))?;
+        let mut file =
+            File::create(filepath).map_err(|e| format!("Failed to create file: {}", e))?;
         file.write_all(self.private_key.as_bytes())
             .map_err(|e| format!("Failed to write private key to file: {}", e))?;
         Ok(())
     }
 
     pub fn write_pk_to_file(&self, filepath: &str) -> Result<(), String> {
-        let mut file = File::create(filepath).map_err(|e| format!("Failed to create file: {}", e))?;
+        let mut file =
+            File::create(filepath).map_err(|e| format!("Failed to create file: {}", e))?;
         file.write_all(self.public_key.compress().as_bytes())
             .map_err(|e| format!("Failed to write public key to file: {}", e))?;
         Ok(())
@@ -46,7 +95,10 @@ impl KeyPair {
         file.read_exact(&mut buffer)
             .map_err(|e| format!("Failed to read private key: {}", e))?;
         let private_key = Scalar::from_bytes_mod_order(buffer);
-        let public_key = &private_key * &curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+        let public_key = private_key * curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+        if is_weak_point(&public_key) {
+            return Err("Private key yields a weak public key".to_string());
+        }
         Ok(KeyPair {
             private_key,
             public_key,
@@ -59,11 +111,269 @@ impl KeyPair {
         file.read_exact(&mut buffer)
             .map_err(|e| format!("Failed to read public key: {}", e))?;
         let compressed_point = CompressedRistretto(buffer);
-        compressed_point
+        let public_key = compressed_point
             .decompress()
-            .ok_or_else(|| "Failed to decompress RistrettoPoint".to_string())
+            .ok_or_else(|| "Failed to decompress RistrettoPoint".to_string())?;
+        if is_weak_point(&public_key) {
+            return Err("Weak public key".to_string());
+        }
+        Ok(public_key)
     }
 
+    /// Loads a private key and checks it against a separately stored public key,
+    /// catching key files that got mixed up
+    pub fn from_files(sk_path: &str, pk_path: &str) -> Result<KeyPair, CryptoError> {
+        let read_key = |path: &str| -> Result<[u8; 32], CryptoError> {
+            let bytes = std::fs::read(path).map_err(|e| CryptoError::Io(e.kind()))?;
+            let actual = bytes.len();
+            bytes.try_into().map_err(|_| CryptoError::WrongLength {
+                expected: 32,
+                actual,
+            })
+        };
+
+        let private_key = Scalar::from_bytes_mod_order(read_key(sk_path)?);
+        let public_key = private_key * RISTRETTO_BASEPOINT_POINT;
+        if is_weak_point(&public_key) {
+            return Err(CryptoError::WeakPoint);
+        }
+        let stored_public_key = read_key(pk_path)?;
+        if !public_keys_equal(&public_key.compress().to_bytes(), &stored_public_key) {
+            return Err(CryptoError::KeyPairMismatch);
+        }
+        Ok(KeyPair {
+            private_key,
+            public_key,
+        })
+    }
+
+    /// Derives a subkey pair from the private key with HKDF-SHA256, one per `info`
+    pub(crate) fn subkey(&self, info: &[u8]) -> KeyPair {
+        let mut wide = [0u8; 64];
+        Hkdf::<Sha256>::new(None, self.private_key.as_bytes())
+            .expand(info, &mut wide)
+            .expect("64 bytes is a valid HKDF-SHA256 output length");
+        let private_key = Scalar::from_bytes_mod_order_wide(&wide);
+        KeyPair {
+            private_key,
+            public_key: private_key * RISTRETTO_BASEPOINT_POINT,
+        }
+    }
+}
+
+/// Compares two compressed public keys in constant time, so timing doesn't reveal
+/// how many leading bytes match
+pub fn public_keys_equal(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Returns true for public keys that must not be used for key agreement or encryption:
+/// the identity, which makes every shared secret the identity as well, and (conservatively)
+/// any point that doesn't survive a compress/decompress round trip.
+/// Ristretto has no small-order points besides the identity, so this is a safety net.
+pub fn is_weak_point(point: &RistrettoPoint) -> bool {
+    if point.is_identity() {
+        return true;
+    }
+    CompressedRistretto(point.compress().to_bytes()).decompress() != Some(*point)
+}
+
+/// Computes the shared secret sk * pk, refusing weak public keys
+pub fn diffie_hellman(
+    private_key: &Scalar,
+    public_key: &RistrettoPoint,
+) -> Result<RistrettoPoint, CryptoError> {
+    if is_weak_point(public_key) {
+        return Err(CryptoError::WeakPoint);
+    }
+    let shared_secret = private_key * public_key;
+    if shared_secret.is_identity() {
+        return Err(CryptoError::WeakPoint); // Only possible with a zero private key
+    }
+    Ok(shared_secret)
+}
+
+/// Symmetric key for two parties that already know each other's static keys:
+/// HKDF-SHA256 over `diffie_hellman(my_sk, their_pk)` with `context` as info.
+/// Both sides get the same key for the same context, e.g. for `Message::encrypt_symmetric`.
+/// The key never changes for a pair and context, so use a distinct context per purpose.
+pub fn static_shared_key(
+    my_private_key: &Scalar,
+    their_public_key: &RistrettoPoint,
+    context: &[u8],
+) -> Result<[u8; 32], CryptoError> {
+    let shared_secret = diffie_hellman(my_private_key, their_public_key)?;
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, shared_secret.compress().as_bytes())
+        .expand(context, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Ok(key)
+}
+
+/// Derives `count` independent 32-byte keys from one shared secret, e.g. separate encryption
+/// and MAC keys for encrypt-then-MAC. Each key is HKDF-SHA256 output under its own info label,
+/// so learning one key reveals nothing about the others.
+pub fn expand_keys(shared_secret: &[u8; 32], count: usize) -> Vec<[u8; 32]> {
+    let hkdf = Hkdf::<Sha256>::new(None, shared_secret);
+    (0..count as u64)
+        .map(|index| {
+            let mut key = [0u8; 32];
+            hkdf.expand_multi_info(&[EXPAND_KEYS_INFO, &index.to_be_bytes()], &mut key)
+                .expect("32 bytes is a valid HKDF-SHA256 output length");
+            key
+        })
+        .collect()
+}
+
+/// Short identifier of a public key: the first 8 bytes of SHA-256 over its compressed form.
+/// Good for picking a key or slot, not for authenticating one.
+pub fn fingerprint(public_key: &RistrettoPoint) -> [u8; 8] {
+    let digest = Sha256::digest(public_key.compress().as_bytes());
+    let mut fingerprint = [0u8; 8];
+    fingerprint.copy_from_slice(&digest[..8]);
+    fingerprint
+}
+
+/// Formats a public key as a copy-pasteable bech32 recipient string in the style of
+/// `age`: the `scch` prefix, the compressed key and a checksum catching typos
+pub fn public_key_to_recipient_string(public_key: &RistrettoPoint) -> String {
+    bech32::encode::<Bech32>(RECIPIENT_HRP, public_key.compress().as_bytes())
+        .expect("32 bytes fit in a bech32 string")
+}
+
+/// Parses a string from `public_key_to_recipient_string`, rejecting bad checksums, other
+/// prefixes and weak keys. Like bech32 itself, accepts all-lowercase or all-uppercase input.
+pub fn recipient_string_to_public_key(recipient: &str) -> Result<RistrettoPoint, CryptoError> {
+    let parsed = CheckedHrpstring::new::<Bech32>(recipient)
+        .map_err(|_| CryptoError::InvalidRecipientString)?;
+    if parsed.hrp() != RECIPIENT_HRP {
+        return Err(CryptoError::InvalidRecipientString);
+    }
+    let bytes: Vec<u8> = parsed.byte_iter().collect();
+    let public_key = SchnorrSignature::bytes_to_point(&bytes)?;
+    if is_weak_point(&public_key) {
+        return Err(CryptoError::WeakPoint);
+    }
+    Ok(public_key)
+}
+
+/// How often `secure_rng_with` tries to reach the OS randomness source, waiting
+/// `initial_backoff` after the first failure and twice as long after each further one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RngRetryPolicy {
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl Default for RngRetryPolicy {
+    /// 5 attempts over about 150ms, enough to ride out a slow entropy pool at early boot
+    fn default() -> RngRetryPolicy {
+        RngRetryPolicy {
+            attempts: 5,
+            initial_backoff: Duration::from_millis(10),
+        }
+    }
+}
+
+/// `OsRng` once it has produced output, retrying with the default `RngRetryPolicy`.
+/// Fails with `RngUnavailable` rather than panicking inside a crypto operation.
+pub fn secure_rng() -> Result<OsRng, CryptoError> {
+    secure_rng_with(&RngRetryPolicy::default())
+}
+
+/// Like `secure_rng`, with a custom retry policy
+pub fn secure_rng_with(policy: &RngRetryPolicy) -> Result<OsRng, CryptoError> {
+    acquire_rng(OsRng, policy)
+}
+
+/// Returns `rng` as soon as it fills a probe byte, or `RngUnavailable` after
+/// `policy.attempts` failures
+fn acquire_rng<R: RngCore>(mut rng: R, policy: &RngRetryPolicy) -> Result<R, CryptoError> {
+    let mut backoff = policy.initial_backoff;
+    for attempt in 0..policy.attempts {
+        if attempt > 0 {
+            thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2);
+        }
+        if rng.try_fill_bytes(&mut [0u8; 1]).is_ok() {
+            return Ok(rng);
+        }
+    }
+    Err(CryptoError::RngUnavailable)
+}
+
+/// Basic sanity check of `OsRng`, e.g. before generating long-term keys on a freshly
+/// booted device: see `check_entropy_with`
+pub fn check_entropy() -> Result<(), CryptoError> {
+    check_entropy_with(&mut secure_rng()?)
+}
+
+/// Draws two 32-byte blocks from `rng` and fails with `WeakEntropy` if either is all zeros
+/// or both are equal. This only catches catastrophic failures, not subtly biased sources.
+pub fn check_entropy_with<R: RngCore>(rng: &mut R) -> Result<(), CryptoError> {
+    let mut first = [0u8; 32];
+    let mut second = [0u8; 32];
+    rng.try_fill_bytes(&mut first)
+        .and_then(|_| rng.try_fill_bytes(&mut second))
+        .map_err(|_| CryptoError::WeakEntropy)?;
+    if first == [0u8; 32] || second == [0u8; 32] || first == second {
+        return Err(CryptoError::WeakEntropy);
+    }
+    Ok(())
+}
+
+/// Random challenge for a challenge-response login: the verifier sends it, the prover
+/// answers with `respond`
+pub fn make_challenge() -> Result<[u8; 32], CryptoError> {
+    let mut challenge = [0u8; 32];
+    secure_rng()?.fill_bytes(&mut challenge);
+    Ok(challenge)
+}
+
+/// Proves possession of `signing_key` by signing the challenge
+pub fn respond(
+    challenge: &[u8; 32],
+    signing_key: &Scalar,
+) -> Result<SchnorrSignature, CryptoError> {
+    SchnorrSignature::sign(&challenge_bytes(challenge), signing_key)
+}
+
+/// Checks a response to `challenge` made with the private key of `public_key`
+pub fn verify_response(
+    challenge: &[u8; 32],
+    response: &SchnorrSignature,
+    public_key: &RistrettoPoint,
+) -> bool {
+    SchnorrSignature::verify(response, &challenge_bytes(challenge), public_key)
+}
+
+fn challenge_bytes(challenge: &[u8; 32]) -> Vec<u8> {
+    [CHALLENGE_DOMAIN, challenge].concat()
+}
+
+/// Signs `message` together with a nonce from the verifier's `make_challenge`, so the
+/// signature only verifies in the session that issued the nonce and can't be replayed
+pub fn sign_fresh(
+    message: &[u8],
+    nonce: &[u8; 32],
+    signing_key: &Scalar,
+) -> Result<SchnorrSignature, CryptoError> {
+    SchnorrSignature::sign(&fresh_bytes(message, nonce), signing_key)
+}
+
+/// Checks a `sign_fresh` signature over `message` and the nonce issued for this session
+pub fn verify_fresh(
+    message: &[u8],
+    nonce: &[u8; 32],
+    signature: &SchnorrSignature,
+    public_key: &RistrettoPoint,
+) -> bool {
+    SchnorrSignature::verify(signature, &fresh_bytes(message, nonce), public_key)
+}
+
+// The nonce has a fixed length, so appending it after the message is unambiguous
+fn fresh_bytes(message: &[u8], nonce: &[u8; 32]) -> Vec<u8> {
+    [FRESH_SIGNATURE_DOMAIN, message, nonce].concat()
 }
 
 // Unit tests for keys module
@@ -74,7 +384,7 @@ mod tests {
 
     #[test]
     fn test_generate_keypair() {
-        let keypair = KeyPair::generate();
+        let keypair = KeyPair::generate().unwrap();
         assert!(
             keypair.public_key != RistrettoPoint::default(),
             "Public key should not be default"
@@ -84,31 +394,30 @@ mod tests {
             "Private key should not be default"
         );
         assert!(
-            keypair.private_key * &RISTRETTO_BASEPOINT_POINT == keypair.public_key,
+            keypair.private_key * RISTRETTO_BASEPOINT_POINT == keypair.public_key,
             "Public key should be g^private_key"
         )
     }
 
-
     #[test]
     fn test_write_and_read_keypair() {
-        let keypair = KeyPair::generate();
+        let keypair = KeyPair::generate().unwrap();
         let pk_filepath = "pk_test.txt";
         let sk_filepath = "sk_test.txt";
 
         // Write the keypair to a file
         keypair
-            .write_sk_to_file(&sk_filepath)
+            .write_sk_to_file(sk_filepath)
             .expect("Failed to write sk to file");
         keypair
-            .write_pk_to_file(&pk_filepath)
+            .write_pk_to_file(pk_filepath)
             .expect("Failed to write pk to file");
 
         // Read the keypair back from the file
         let read_keypair =
-            KeyPair::from_file(&sk_filepath).expect("Failed to read keypair from file");
+            KeyPair::from_file(sk_filepath).expect("Failed to read keypair from file");
 
-        let read_pk = KeyPair::pk_from_file(&pk_filepath).expect("Failed to read pk from file");
+        let read_pk = KeyPair::pk_from_file(pk_filepath).expect("Failed to read pk from file");
 
         // Check if the written and read key pairs are equal
         assert_eq!(
@@ -122,7 +431,329 @@ mod tests {
         assert_eq!(keypair.public_key, read_pk, "Public keys should match");
 
         // Clean up the test file
-        fs::remove_file(&sk_filepath).expect("Failed to remove sk test file");
-        fs::remove_file(&pk_filepath).expect("Failed to remove pk test file");
+        fs::remove_file(sk_filepath).expect("Failed to remove sk test file");
+        fs::remove_file(pk_filepath).expect("Failed to remove pk test file");
+    }
+
+    #[test]
+    fn test_public_keys_equal() {
+        let pk1 = KeyPair::generate()
+            .unwrap()
+            .public_key
+            .compress()
+            .to_bytes();
+        let pk2 = KeyPair::generate()
+            .unwrap()
+            .public_key
+            .compress()
+            .to_bytes();
+
+        assert!(public_keys_equal(&pk1, &pk1), "Equal keys should match");
+        assert!(
+            !public_keys_equal(&pk1, &pk2),
+            "Different keys should not match"
+        );
+
+        // Keys differing only in the last byte must not match either
+        let mut almost = pk1;
+        almost[31] ^= 0x01;
+        assert!(!public_keys_equal(&pk1, &almost));
+    }
+
+    #[test]
+    fn test_from_seed_is_deterministic() {
+        let first = KeyPair::from_seed(42);
+        let second = KeyPair::from_seed(42);
+        assert_eq!(first.private_key, second.private_key);
+        assert_eq!(first.public_key, second.public_key);
+        assert_eq!(
+            first.private_key * RISTRETTO_BASEPOINT_POINT,
+            first.public_key
+        );
+
+        assert_ne!(first.public_key, KeyPair::from_seed(43).public_key);
+    }
+
+    #[test]
+    fn test_identity_point_is_rejected() {
+        let identity = RistrettoPoint::default();
+        let keypair = KeyPair::generate().unwrap();
+        assert!(is_weak_point(&identity));
+        assert!(!is_weak_point(&keypair.public_key));
+
+        assert_eq!(
+            diffie_hellman(&keypair.private_key, &identity),
+            Err(CryptoError::WeakPoint)
+        );
+        assert!(diffie_hellman(&keypair.private_key, &keypair.public_key).is_ok());
+
+        // Key-load path: the identity encodes as 32 zero bytes
+        let pk_filepath = "weak_pk_test.txt";
+        std::fs::write(pk_filepath, [0u8; 32]).expect("Failed to write pk test file");
+        let loaded = KeyPair::pk_from_file(pk_filepath);
+        fs::remove_file(pk_filepath).expect("Failed to remove pk test file");
+        assert!(
+            loaded.is_err(),
+            "The identity should not load as a public key"
+        );
+    }
+
+    #[test]
+    fn test_challenge_response() {
+        let prover = KeyPair::from_seed(1);
+        let impostor = KeyPair::from_seed(2);
+        let challenge = make_challenge().unwrap();
+        assert_ne!(
+            challenge,
+            make_challenge().unwrap(),
+            "Challenges should be fresh"
+        );
+
+        let response = respond(&challenge, &prover.private_key).unwrap();
+        assert!(verify_response(&challenge, &response, &prover.public_key));
+        assert!(!verify_response(
+            &make_challenge().unwrap(),
+            &response,
+            &prover.public_key
+        ));
+        assert!(!verify_response(
+            &challenge,
+            &response,
+            &impostor.public_key
+        ));
+
+        let impostor_response = respond(&challenge, &impostor.private_key).unwrap();
+        assert!(!verify_response(
+            &challenge,
+            &impostor_response,
+            &prover.public_key
+        ));
+
+        // The response is not a plain signature of the challenge
+        assert!(!SchnorrSignature::verify(
+            &response,
+            &challenge,
+            &prover.public_key
+        ));
+    }
+
+    #[test]
+    fn test_fresh_signature_rejects_replay() {
+        let signer = KeyPair::from_seed(1);
+        let message = b"Transfer 10 coins";
+        let stale_nonce = make_challenge().unwrap();
+        let captured = sign_fresh(message, &stale_nonce, &signer.private_key).unwrap();
+        assert!(verify_fresh(
+            message,
+            &stale_nonce,
+            &captured,
+            &signer.public_key
+        ));
+
+        // Replayed into a new session with a fresh nonce
+        let nonce = make_challenge().unwrap();
+        assert!(!verify_fresh(
+            message,
+            &nonce,
+            &captured,
+            &signer.public_key
+        ));
+
+        let signature = sign_fresh(message, &nonce, &signer.private_key).unwrap();
+        assert!(verify_fresh(
+            message,
+            &nonce,
+            &signature,
+            &signer.public_key
+        ));
+        assert!(!verify_fresh(
+            b"Transfer 99 coins",
+            &nonce,
+            &signature,
+            &signer.public_key
+        ));
+        assert!(!SchnorrSignature::verify(
+            &signature,
+            message,
+            &signer.public_key
+        ));
+    }
+
+    #[test]
+    fn test_from_files_detects_mismatched_pair() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let alice = KeyPair::from_seed(1);
+        let bob = KeyPair::from_seed(2);
+        alice.write_sk_to_file(&path("alice.sk")).unwrap();
+        alice.write_pk_to_file(&path("alice.pub")).unwrap();
+        bob.write_pk_to_file(&path("bob.pub")).unwrap();
+
+        let loaded = KeyPair::from_files(&path("alice.sk"), &path("alice.pub")).unwrap();
+        assert_eq!(loaded.public_key, alice.public_key);
+        assert!(matches!(
+            KeyPair::from_files(&path("alice.sk"), &path("bob.pub")),
+            Err(CryptoError::KeyPairMismatch)
+        ));
+        assert!(matches!(
+            KeyPair::from_files(&path("alice.sk"), &path("missing.pub")),
+            Err(CryptoError::Io(std::io::ErrorKind::NotFound))
+        ));
+    }
+
+    #[test]
+    fn test_static_shared_key() {
+        let alice = KeyPair::from_seed(1);
+        let bob = KeyPair::from_seed(2);
+
+        let alice_key = static_shared_key(&alice.private_key, &bob.public_key, b"chat").unwrap();
+        let bob_key = static_shared_key(&bob.private_key, &alice.public_key, b"chat").unwrap();
+        assert_eq!(alice_key, bob_key);
+
+        let other_context =
+            static_shared_key(&alice.private_key, &bob.public_key, b"file-transfer").unwrap();
+        assert_ne!(alice_key, other_context);
+    }
+
+    /// Randomness source stuck on one byte value
+    struct ConstantRng(u8);
+
+    impl RngCore for ConstantRng {
+        fn next_u32(&mut self) -> u32 {
+            u32::from_ne_bytes([self.0; 4])
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            u64::from_ne_bytes([self.0; 8])
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.fill(self.0);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// Randomness source that fails a number of times before working
+    struct FlakyRng {
+        failures_left: u32,
+    }
+
+    impl RngCore for FlakyRng {
+        fn next_u32(&mut self) -> u32 {
+            let mut bytes = [0u8; 4];
+            self.fill_bytes(&mut bytes);
+            u32::from_ne_bytes(bytes)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            let mut bytes = [0u8; 8];
+            self.fill_bytes(&mut bytes);
+            u64::from_ne_bytes(bytes)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.try_fill_bytes(dest).expect("Flaky RNG failed")
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                return Err(rand::Error::new("Entropy pool not ready"));
+            }
+            dest.fill(0x5a);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rng_acquisition_retries() {
+        let policy = RngRetryPolicy {
+            attempts: 4,
+            initial_backoff: Duration::from_millis(1),
+        };
+        let rng = acquire_rng(FlakyRng { failures_left: 3 }, &policy).unwrap();
+        assert_eq!(rng.failures_left, 0);
+        assert!(matches!(
+            acquire_rng(FlakyRng { failures_left: 4 }, &policy),
+            Err(CryptoError::RngUnavailable)
+        ));
+        assert!(secure_rng().is_ok());
+    }
+
+    #[test]
+    fn test_check_entropy_rejects_broken_rng() {
+        assert_eq!(check_entropy(), Ok(()));
+        assert!(KeyPair::generate_checked().is_ok());
+
+        assert_eq!(
+            check_entropy_with(&mut ConstantRng(0)),
+            Err(CryptoError::WeakEntropy)
+        );
+        assert_eq!(
+            check_entropy_with(&mut ConstantRng(0xa5)),
+            Err(CryptoError::WeakEntropy)
+        );
+    }
+
+    #[test]
+    fn test_expand_keys() {
+        let shared_secret = [7u8; 32];
+        let keys = expand_keys(&shared_secret, 3);
+
+        assert_eq!(keys.len(), 3);
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+        assert_ne!(keys[1], keys[2]);
+        assert!(!keys.contains(&shared_secret));
+
+        assert_eq!(expand_keys(&shared_secret, 3), keys);
+        // A shorter expansion is a prefix of a longer one
+        assert_eq!(expand_keys(&shared_secret, 2), keys[..2]);
+        assert_ne!(expand_keys(&[8u8; 32], 1)[0], keys[0]);
+    }
+
+    #[test]
+    fn test_recipient_string_roundtrip() {
+        let keypair = KeyPair::from_seed(1);
+        let recipient = public_key_to_recipient_string(&keypair.public_key);
+        assert!(
+            recipient.starts_with("scch1"),
+            "Unexpected prefix in {}",
+            recipient
+        );
+
+        assert_eq!(
+            recipient_string_to_public_key(&recipient),
+            Ok(keypair.public_key)
+        );
+        assert_eq!(
+            recipient_string_to_public_key(&recipient.to_uppercase()),
+            Ok(keypair.public_key)
+        );
+    }
+
+    #[test]
+    fn test_recipient_string_rejects_bad_checksum() {
+        let recipient = public_key_to_recipient_string(&KeyPair::from_seed(1).public_key);
+
+        // Swap the last character for another valid bech32 character
+        let mut typo = recipient.clone();
+        let last = typo.pop().unwrap();
+        typo.push(if last == 'q' { 'p' } else { 'q' });
+        assert_eq!(
+            recipient_string_to_public_key(&typo),
+            Err(CryptoError::InvalidRecipientString)
+        );
+
+        let other_prefix =
+            bech32::encode::<Bech32>(Hrp::parse_unchecked("age"), &[0u8; 32]).unwrap();
+        assert_eq!(
+            recipient_string_to_public_key(&other_prefix),
+            Err(CryptoError::InvalidRecipientString)
+        );
     }
 }
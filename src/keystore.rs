@@ -0,0 +1,264 @@
+use crate::error::{CryptoError, MessageError};
+use crate::keys::{fingerprint, is_weak_point, KeyPair};
+use crate::message::Message;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock};
+
+/// Extension of the public key files picked up by `load_dir`
+const PUBLIC_KEY_EXTENSION: &str = "pub";
+
+/// Recipient public keys indexed by their fingerprint
+#[derive(Debug, Default)]
+pub struct KeyStore {
+    keys: BTreeMap<[u8; 8], RistrettoPoint>,
+    rejected: Vec<(PathBuf, String)>, // Files that could not be loaded and why
+}
+
+impl KeyStore {
+    /// Loads every `*.pub` file in `path` with `KeyPair::pk_from_file`.
+    /// Invalid files are skipped and listed in `rejected`, only failing to read the
+    /// directory itself is an error.
+    pub fn load_dir(path: impl AsRef<Path>) -> Result<KeyStore, CryptoError> {
+        let entries = fs::read_dir(path).map_err(|e| CryptoError::Io(e.kind()))?;
+
+        let mut store = KeyStore::default();
+        for entry in entries {
+            let file_path = entry.map_err(|e| CryptoError::Io(e.kind()))?.path();
+            if file_path.extension().and_then(|ext| ext.to_str()) != Some(PUBLIC_KEY_EXTENSION) {
+                continue;
+            }
+
+            let loaded = file_path
+                .to_str()
+                .ok_or_else(|| "Path is not valid UTF-8".to_string())
+                .and_then(KeyPair::pk_from_file);
+            match loaded {
+                Ok(public_key) => store.insert(public_key),
+                Err(e) => store.rejected.push((file_path, e)),
+            }
+        }
+        Ok(store)
+    }
+
+    /// Adds a public key, replacing any key with the same fingerprint
+    pub fn insert(&mut self, public_key: RistrettoPoint) {
+        self.keys.insert(fingerprint(&public_key), public_key);
+    }
+
+    /// Looks up a key by its fingerprint
+    pub fn get(&self, fingerprint: &[u8; 8]) -> Option<&RistrettoPoint> {
+        self.keys.get(fingerprint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks every stored key for corruption: each must be a usable public key and be
+    /// stored under its own fingerprint. Returns one result per fingerprint, in order.
+    pub fn validate(&self) -> Vec<([u8; 8], Result<(), CryptoError>)> {
+        self.keys
+            .iter()
+            .map(|(stored_fingerprint, public_key)| {
+                let result = if is_weak_point(public_key) {
+                    Err(CryptoError::WeakPoint)
+                } else if fingerprint(public_key) != *stored_fingerprint {
+                    Err(CryptoError::FingerprintMismatch)
+                } else {
+                    Ok(())
+                };
+                (*stored_fingerprint, result)
+            })
+            .collect()
+    }
+
+    /// Files skipped by `load_dir`, with the reason
+    pub fn rejected(&self) -> &[(PathBuf, String)] {
+        &self.rejected
+    }
+}
+
+/// `KeyStore` behind an `RwLock`, for sharing between threads (e.g. in an `Arc`). Lookups
+/// return copies of the keys, so the lock is never held beyond a single call.
+#[derive(Debug, Default)]
+pub struct SharedKeyStore {
+    store: RwLock<KeyStore>,
+}
+
+impl SharedKeyStore {
+    pub fn new(store: KeyStore) -> SharedKeyStore {
+        SharedKeyStore {
+            store: RwLock::new(store),
+        }
+    }
+
+    /// Adds a public key, replacing any key with the same fingerprint
+    pub fn insert(&self, public_key: RistrettoPoint) {
+        // A panic elsewhere can't leave the map half-updated, so a poisoned lock is still usable
+        self.store
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(public_key);
+    }
+
+    /// Looks up a key by its fingerprint
+    pub fn get(&self, fingerprint: &[u8; 8]) -> Option<RistrettoPoint> {
+        self.store
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(fingerprint)
+            .copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.store
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encrypts the message to the key with `fingerprint`. The key is looked up first and
+    /// the lock released before encrypting.
+    pub fn encrypt_message_for(
+        &self,
+        fingerprint: &[u8; 8],
+        message: &mut Message,
+    ) -> Result<(), MessageError> {
+        message.encrypt_for_fingerprint(*fingerprint, |fp| self.get(&fp))
+    }
+}
+
+impl From<KeyStore> for SharedKeyStore {
+    fn from(store: KeyStore) -> SharedKeyStore {
+        SharedKeyStore::new(store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_dir_skips_invalid_files() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let keypairs: Vec<KeyPair> = (0..3).map(KeyPair::from_seed).collect();
+        for (index, keypair) in keypairs.iter().enumerate() {
+            let path = dir.path().join(format!("recipient{}.pub", index));
+            keypair
+                .write_pk_to_file(path.to_str().unwrap())
+                .expect("Failed to write pk");
+        }
+        fs::write(dir.path().join("broken.pub"), [0xffu8; 32]).unwrap();
+        fs::write(dir.path().join("notes.txt"), b"not a key").unwrap();
+
+        let store = KeyStore::load_dir(dir.path()).expect("Loading should not abort");
+        assert_eq!(store.len(), 3);
+        for keypair in &keypairs {
+            assert_eq!(
+                store.get(&fingerprint(&keypair.public_key)),
+                Some(&keypair.public_key)
+            );
+        }
+
+        assert_eq!(store.rejected().len(), 1);
+        assert_eq!(store.rejected()[0].0, dir.path().join("broken.pub"));
+    }
+
+    #[test]
+    fn test_validate_flags_corrupted_entries() {
+        let mut store = KeyStore::default();
+        let keypairs: Vec<KeyPair> = (0..3).map(KeyPair::from_seed).collect();
+        for keypair in &keypairs {
+            store.insert(keypair.public_key);
+        }
+        assert!(store.validate().iter().all(|(_, result)| result.is_ok()));
+
+        // Simulate an index bug filing a key under another key's fingerprint
+        let wrong_fingerprint = [0x42u8; 8];
+        store.keys.insert(wrong_fingerprint, keypairs[0].public_key);
+        let identity_fingerprint = [0u8; 8];
+        store
+            .keys
+            .insert(identity_fingerprint, RistrettoPoint::default());
+
+        let report = store.validate();
+        assert_eq!(report.len(), 5);
+        for (stored_fingerprint, result) in report {
+            let expected = match stored_fingerprint {
+                f if f == wrong_fingerprint => Err(CryptoError::FingerprintMismatch),
+                f if f == identity_fingerprint => Err(CryptoError::WeakPoint),
+                _ => Ok(()),
+            };
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn test_load_missing_dir_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            KeyStore::load_dir(dir.path().join("missing")),
+            Err(CryptoError::Io(std::io::ErrorKind::NotFound))
+        ));
+    }
+
+    #[test]
+    fn test_shared_store_concurrent_access() {
+        let store = SharedKeyStore::default();
+        let keypairs: Vec<KeyPair> = (0..32).map(KeyPair::from_seed).collect();
+
+        std::thread::scope(|scope| {
+            for chunk in keypairs.chunks(8) {
+                let store = &store;
+                scope.spawn(move || {
+                    for keypair in chunk {
+                        store.insert(keypair.public_key);
+                        let fp = fingerprint(&keypair.public_key);
+                        assert_eq!(store.get(&fp), Some(keypair.public_key));
+                    }
+                });
+            }
+            for _ in 0..4 {
+                let (store, keypairs) = (&store, &keypairs);
+                scope.spawn(move || {
+                    for keypair in keypairs {
+                        // Keys appear while other threads insert, any present key must be whole
+                        if let Some(key) = store.get(&fingerprint(&keypair.public_key)) {
+                            assert_eq!(key, keypair.public_key);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(store.len(), keypairs.len());
+
+        let recipient = &keypairs[5];
+        let mut message = Message::new(
+            1,
+            b"Shared store".to_vec(),
+            Default::default(),
+            Default::default(),
+            crate::schnorr::SchnorrSignature::emty_signature(),
+        );
+        store
+            .encrypt_message_for(&fingerprint(&recipient.public_key), &mut message)
+            .unwrap();
+        message.decrypt(&recipient.private_key).unwrap();
+        assert_eq!(message.payload, b"Shared store");
+        assert_eq!(
+            store.encrypt_message_for(&[0u8; 8], &mut message),
+            Err(MessageError::UnknownRecipient([0u8; 8]))
+        );
+    }
+}
@@ -0,0 +1,28 @@
+pub mod aes;
+pub mod archive;
+pub mod capabilities;
+pub mod certificate;
+pub mod elgamal;
+pub mod error;
+pub mod group;
+pub mod hash;
+pub mod hybrid_enc;
+pub mod keys;
+pub mod keystore;
+pub mod message;
+pub mod schnorr;
+pub mod selftest;
+pub mod serializers;
+pub mod session;
+#[cfg(test)]
+mod tamper_tests;
+#[cfg(test)]
+mod tests;
+pub mod timelock;
+pub mod timestamp;
+#[cfg(test)]
+mod timing_tests;
+pub mod transport;
+
+pub use capabilities::{capabilities, Capabilities};
+pub use selftest::self_test;
@@ -1,27 +1,287 @@
-use crate::hybrid_enc::HybridCiphertext;
-use crate::keys::KeyPair;
-use crate::schnorr::SchnorrSignature;
+use crate::aes::{AESCiphertext, CipherContext, AES_KEY_SIZE, AES_TAG_SIZE};
+use crate::archive::Archive;
+use crate::capabilities::AeadAlgorithm;
+use crate::certificate::{verify_chain, verify_chain_signatures, Certificate};
+use crate::elgamal::ElGamalCiphertext;
+use crate::error::{CryptoError, MessageError};
+use crate::hybrid_enc::{HybridCiphertext, KeyDerivation};
+use crate::keys::{fingerprint, is_weak_point, public_keys_equal, secure_rng, KeyPair};
+use crate::schnorr::{sign_bytes, verify_bytes, SchnorrSignature};
 use crate::serializers::*;
+use crate::timelock::TimelockPuzzle;
+use crate::timestamp::TimestampToken;
+use crate::transport::parse_frame;
+use base64::prelude::*;
+#[cfg(feature = "bincode")]
+use bincode::Options;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
-use serde::{de, Deserialize, Serialize};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
 use serde_json;
-use std::default;
+use sha2::{Digest, Sha256, Sha512};
+#[cfg(feature = "bincode")]
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::Write;
+use zeroize::Zeroize;
+
+/// Domain separators prepended to the bytes covered by a message signature, by the
+/// `domain_version` of the message. Add a new version instead of changing a tag, so
+/// signatures made under the old tag still verify.
+const SIGNING_DOMAINS: &[(u8, &[u8])] = &[
+    (1, b"secure-channel/message-signature"),
+    (2, b"secure-channel/message-signature/v2"),
+];
+
+/// Domain version of new messages
+pub const DEFAULT_DOMAIN_VERSION: u8 = 1;
+
+/// Looks up the signing domain tag of a domain version
+fn signing_domain(version: u8) -> Option<&'static [u8]> {
+    SIGNING_DOMAINS
+        .iter()
+        .find(|(known, _)| *known == version)
+        .map(|(_, domain)| *domain)
+}
+
+fn default_domain_version() -> u8 {
+    DEFAULT_DOMAIN_VERSION
+}
+
+fn is_default_domain_version(version: &u8) -> bool {
+    *version == DEFAULT_DOMAIN_VERSION
+}
+
+/// Names of the JSON fields of `Message`, used by `from_json_lenient`
+const MESSAGE_FIELDS: &[&str] = &[
+    "format_version",
+    "state",
+    "payload",
+    "recipient",
+    "sender",
+    "signature",
+    "mode",
+    "certificate_chain",
+    "headers",
+    "routing_headers",
+    "attachments",
+    "rotation_log",
+    "domain_version",
+    "producer",
+    "timestamp",
+];
+
+/// Rewrites a JSON envelope from before `format_version` and `state` were split out of
+/// `version`, which counted encryptions: 0 for plaintext, 1 or more for an encrypted payload.
+/// Those envelopes predate format versions and get 0. Returns false for any other input.
+fn upgrade_legacy_version(value: &mut serde_json::Value) -> bool {
+    let Some(fields) = value.as_object_mut() else {
+        return false;
+    };
+    if fields.contains_key("format_version") || fields.contains_key("state") {
+        return false;
+    }
+    let Some(version) = fields.get("version").and_then(serde_json::Value::as_u64) else {
+        return false;
+    };
+    let state = if version > 0 {
+        MessageState::Encrypted
+    } else {
+        MessageState::Plain
+    };
+    fields.remove("version");
+    fields.insert("format_version".to_string(), 0.into());
+    fields.insert("state".to_string(), serde_json::json!(state));
+    true
+}
+
+/// Parses a JSON envelope, upgrading legacy `version` envelopes with `upgrade_legacy_version`.
+/// Current envelopes are parsed directly, the legacy path only runs after that fails.
+pub(crate) fn message_from_json(json: &str) -> serde_json::Result<Message> {
+    serde_json::from_str(json).or_else(|error| {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        if !upgrade_legacy_version(&mut value) {
+            return Err(error);
+        }
+        serde_json::from_value(value)
+    })
+}
+
+/// Domain separator of the associated data authenticating routing headers
+const ROUTING_AAD_DOMAIN: &[u8] = b"secure-channel/routing-headers";
+
+/// HKDF info strings of the subkeys used by `encrypt_for_self`, so the long-term key itself
+/// never encrypts and signs the same note
+const SELF_ENCRYPTION_INFO: &[u8] = b"secure-channel/self/encryption";
+const SELF_SIGNING_INFO: &[u8] = b"secure-channel/self/signing";
+
+/// Header set by `reply`, holding the hex-encoded message ID of the original message
+pub const IN_REPLY_TO_HEADER: &str = "in-reply-to";
+
+/// Headers set by `split`: the position of a chunk, the number of chunks and a random
+/// hex identifier shared by all chunks of one payload
+pub const CHUNK_INDEX_HEADER: &str = "chunk-index";
+pub const TOTAL_CHUNKS_HEADER: &str = "total-chunks";
+pub const CHUNK_SET_HEADER: &str = "chunk-set";
+
+/// Software that sealed a message, recorded in `producer` by `seal`
+pub const PRODUCER: &str = concat!("secure-channel/", env!("CARGO_PKG_VERSION"));
+
+/// Recipient of broadcast messages, serialized as an empty string
+pub const BROADCAST_RECIPIENT: [u8; 32] = [0u8; 32];
+
+/// Sender of a message that hasn't been signed yet (or whose signature was cleared by
+/// encryption). It is the encoding of the identity, which is never a valid sender.
+pub const UNSET_SENDER: [u8; 32] = [0u8; 32];
+
+/// First byte of the padding appended by `to_fixed_size_token`
+const PADDING_MARKER: u8 = 0x80;
+
+const ARMOR_BEGIN: &str = "-----BEGIN SECURE-CHANNEL MESSAGE-----";
+const ARMOR_END: &str = "-----END SECURE-CHANNEL MESSAGE-----";
+const ARMOR_LINE_LENGTH: usize = 64;
+
+/// Number of bytes of a field `Display` shows before truncating it
+const DISPLAY_BYTES: usize = 32;
+
+/// Version of the JSON envelope layout written by `to_file` and `serialize_message_to_bytes`
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Named file carried along with the payload, encrypted and signed with it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Attachment {
+    pub name: String,
+    #[serde(
+        serialize_with = "serialize_base64_streaming",
+        deserialize_with = "deserialize_base64"
+    )]
+    pub content: Vec<u8>,
+}
+
+/// Public randomness of an encrypted payload, for test vectors and debugging.
+/// Only values that are sent in the clear anyway: the ElGamal `r` and the AES key stay hidden.
+#[cfg(any(test, feature = "debug-tools"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoParams {
+    pub aes_nonce: [u8; crate::aes::AES_NONCE_SIZE],
+    pub c1: Option<[u8; 32]>, // g^r of the ElGamal ciphertext, None for symmetric mode
+}
+
+/// Bytes the `[R, s]` signature layout saves over `{"R": .., "s": ..}` in compact JSON
+const COMPACT_SIGNATURE_SAVING: usize = 8;
+
+/// Writer that only counts the bytes written to it, for `Message::serialized_len`
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodings a whole envelope can be written in, see `Message::to_bytes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerializationFormat {
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+impl SerializationFormat {
+    /// Identifier of the format in framed transports
+    pub fn tag(self) -> u8 {
+        match self {
+            SerializationFormat::Json => 0,
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => 1,
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => 2,
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode => 3,
+        }
+    }
+
+    /// Looks up a format by its tag, None if it is unknown or not compiled in
+    pub fn from_tag(tag: u8) -> Option<SerializationFormat> {
+        match tag {
+            0 => Some(SerializationFormat::Json),
+            #[cfg(feature = "msgpack")]
+            1 => Some(SerializationFormat::MessagePack),
+            #[cfg(feature = "cbor")]
+            2 => Some(SerializationFormat::Cbor),
+            #[cfg(feature = "bincode")]
+            3 => Some(SerializationFormat::Bincode),
+            _ => None,
+        }
+    }
+}
+
+/// How the signature is laid out in the JSON envelope. Parsing accepts both forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignatureFormat {
+    /// `{"R": ..., "s": ...}` (the default)
+    #[default]
+    Map,
+    /// `[R, s]`, smaller and independent of key names
+    Array,
+}
+
+/// How the payload of an encrypted message was encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionMode {
+    /// ElGamal-wrapped AES key (the default)
+    #[default]
+    Hybrid,
+    /// AES with a key both parties already share, no ElGamal
+    Symmetric,
+    /// Headers and body encrypted under separate ElGamal-wrapped AES keys,
+    /// see `encrypt_split`
+    Split,
+    /// AES key locked behind a time-lock puzzle, see `timelock_encrypt`
+    Timelock,
+}
+
+/// Whether the payload of a message is plaintext or an encrypted inner message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageState {
+    /// Readable payload (the default)
+    #[default]
+    Plain,
+    /// The payload holds the encrypted message, see `encrypt`
+    Encrypted,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)] // Fields from a newer format must not be silently dropped
 pub struct Message {
-    pub version: u8, // The version number of the message (1 byte)
+    pub format_version: u8, // Version of the wire format the message was written in
+    #[serde(default)]
+    pub state: MessageState, // Flipped by encryption and decryption
 
     #[serde(
-        serialize_with = "serialize_base64",
-        deserialize_with = "deserialize_base64"
+        serialize_with = "serialize_payload_base64",
+        deserialize_with = "deserialize_payload_base64"
     )]
     pub payload: Vec<u8>, // The message content (or payload) stored as a Base64-encoded string in JSON.
     #[serde(
-        serialize_with = "serialize_fixed_base64",
-        deserialize_with = "deserialize_fixed_base64"
+        serialize_with = "serialize_recipient_base64",
+        deserialize_with = "deserialize_recipient_base64"
     )]
     pub recipient: [u8; 32], // The recipient's identifier (stored as Vec<u8> to serialize easily)
     #[serde(
@@ -34,310 +294,4135 @@ pub struct Message {
         deserialize_with = "deserialize_schnorr_signature"
     )]
     pub signature: SchnorrSignature,
+    #[serde(default)]
+    pub mode: EncryptionMode, // Tells decryptors which path was used to encrypt the payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub certificate_chain: Vec<Certificate>, // Delegates signing from a trusted root to the sender
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>, // Signed metadata, encrypted along with the payload
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    routing_headers: BTreeMap<String, String>, // Cleartext relay metadata, see `set_routing_header`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<Attachment>, // Signed files, encrypted along with the payload
+    #[serde(
+        default,
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_fingerprints",
+        deserialize_with = "deserialize_fingerprints"
+    )]
+    pub rotation_log: Vec<[u8; 8]>, // Fingerprints of the recipients of each re-encryption
+    #[serde(
+        default = "default_domain_version",
+        skip_serializing_if = "is_default_domain_version"
+    )]
+    pub domain_version: u8, // Selects the signing domain tag from `SIGNING_DOMAINS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer: Option<String>, // Crate and version that sealed the message, for debugging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<TimestampToken>, // Attests the time of the message, not signed by the sender
+    #[serde(skip)]
+    pub signature_format: SignatureFormat, // Layout of the signature when writing JSON
+    #[serde(skip)]
+    parsed_from: Option<ParsedFrom>, // Input of `from_bytes`, see `original_bytes`
+}
+
+/// The exact input a message was parsed from, with what identified the message then
+#[derive(Debug, Clone)]
+struct ParsedFrom {
+    bytes: Vec<u8>,
+    message_id: [u8; 16],
+    signature: SchnorrSignature,
+}
+
+/// Layout of a message in bincode. bincode isn't self-describing, so every field is written
+/// in a fixed order, where the `Message` layout leaves out empty optional fields.
+/// Borrows from the message when serializing, so encoding doesn't copy the payload.
+#[cfg(feature = "bincode")]
+#[derive(Serialize, Deserialize)]
+struct BincodeEnvelope<'a> {
+    format_version: u8,
+    state: MessageState,
+    payload: Cow<'a, [u8]>,
+    recipient: [u8; 32],
+    sender: [u8; 32],
+    #[serde(
+        serialize_with = "serialize_schnorr_signature",
+        deserialize_with = "deserialize_schnorr_signature"
+    )]
+    signature: SchnorrSignature,
+    mode: EncryptionMode,
+    certificate_chain: Cow<'a, [Certificate]>,
+    headers: Cow<'a, BTreeMap<String, String>>,
+    routing_headers: Cow<'a, BTreeMap<String, String>>,
+    attachments: Cow<'a, [Attachment]>,
+    rotation_log: Cow<'a, [[u8; 8]]>,
+    domain_version: u8,
+    producer: Option<Cow<'a, str>>,
+    timestamp: Option<Cow<'a, TimestampToken>>,
+}
+
+#[cfg(feature = "bincode")]
+impl<'a> BincodeEnvelope<'a> {
+    fn borrow(message: &'a Message) -> BincodeEnvelope<'a> {
+        BincodeEnvelope {
+            format_version: message.format_version,
+            state: message.state,
+            payload: Cow::Borrowed(&message.payload),
+            recipient: message.recipient,
+            sender: message.sender,
+            signature: message.signature.clone(),
+            mode: message.mode,
+            certificate_chain: Cow::Borrowed(&message.certificate_chain),
+            headers: Cow::Borrowed(&message.headers),
+            routing_headers: Cow::Borrowed(&message.routing_headers),
+            attachments: Cow::Borrowed(&message.attachments),
+            rotation_log: Cow::Borrowed(&message.rotation_log),
+            domain_version: message.domain_version,
+            producer: message.producer.as_deref().map(Cow::Borrowed),
+            timestamp: message.timestamp.as_ref().map(Cow::Borrowed),
+        }
+    }
+
+    fn into_message(self) -> Message {
+        Message {
+            format_version: self.format_version,
+            state: self.state,
+            payload: self.payload.into_owned(),
+            recipient: self.recipient,
+            sender: self.sender,
+            signature: self.signature,
+            mode: self.mode,
+            certificate_chain: self.certificate_chain.into_owned(),
+            headers: self.headers.into_owned(),
+            routing_headers: self.routing_headers.into_owned(),
+            attachments: self.attachments.into_owned(),
+            rotation_log: self.rotation_log.into_owned(),
+            domain_version: self.domain_version,
+            producer: self.producer.map(Cow::into_owned),
+            timestamp: self.timestamp.map(Cow::into_owned),
+            signature_format: SignatureFormat::Map,
+            parsed_from: None,
+        }
+    }
+}
+
+/// bincode settings of `Message::to_bincode`: variable-length integers, and trailing bytes
+/// after the message are an error
+#[cfg(feature = "bincode")]
+fn bincode_options() -> impl bincode::Options {
+    bincode::DefaultOptions::new()
 }
 
 impl Message {
     pub fn new(
-        version: u8,
+        format_version: u8,
         payload: Vec<u8>,
         sender: CompressedRistretto,
         recipient: CompressedRistretto,
         signature: SchnorrSignature,
     ) -> Self {
         Message {
-            version,
+            format_version,
+            state: MessageState::Plain,
             payload,
             recipient: recipient.to_bytes(),
             sender: sender.to_bytes(),
             signature,
+            mode: EncryptionMode::Hybrid,
+            certificate_chain: Vec::new(),
+            headers: BTreeMap::new(),
+            routing_headers: BTreeMap::new(),
+            attachments: Vec::new(),
+            rotation_log: Vec::new(),
+            domain_version: DEFAULT_DOMAIN_VERSION,
+            producer: None,
+            timestamp: None,
+            signature_format: SignatureFormat::Map,
+            parsed_from: None,
         }
     }
 
-    /// Writes the message to a JSON file
-    pub fn to_file(&self, filepath: &str) -> std::io::Result<()> {
-        let file = File::create(filepath)?;
-        serde_json::to_writer_pretty(file, &self)?; // Write JSON in a human-readable format
-        Ok(())
+    /// Reassembles an encrypted and signed hybrid-mode envelope from separately stored fields,
+    /// e.g. database columns, without re-encrypting. `signature` is the 32-byte R followed by
+    /// the 32-byte s. The fields are parsed and the result must pass `is_well_formed`, the
+    /// signature itself isn't checked.
+    pub fn from_parts(
+        format_version: u8,
+        payload: Vec<u8>,
+        recipient: &[u8],
+        sender: &[u8],
+        signature: &[u8],
+    ) -> Result<Message, MessageError> {
+        let field = |name: &str, bytes: &[u8]| -> Result<[u8; 32], MessageError> {
+            bytes.try_into().map_err(|_| {
+                MessageError::MalformedEnvelope(format!(
+                    "{} has {} bytes, expected 32",
+                    name,
+                    bytes.len()
+                ))
+            })
+        };
+        if signature.len() != 64 {
+            return Err(MessageError::MalformedEnvelope(format!(
+                "Signature has {} bytes, expected 64",
+                signature.len()
+            )));
+        }
+        let invalid_signature =
+            |e: CryptoError| MessageError::MalformedEnvelope(format!("Invalid signature: {}", e));
+        let signature = SchnorrSignature {
+            R: SchnorrSignature::bytes_to_point(&signature[..32]).map_err(invalid_signature)?,
+            s: SchnorrSignature::bytes_to_scalar(&signature[32..]).map_err(invalid_signature)?,
+        };
+
+        let mut message = Message::new(
+            format_version,
+            payload,
+            CompressedRistretto(field("Sender", sender)?),
+            CompressedRistretto(field("Recipient", recipient)?),
+            signature,
+        );
+        message.state = MessageState::Encrypted;
+        message.is_well_formed()?;
+        Ok(message)
     }
 
-    pub fn encrypt(&mut self, elgamal_public_key: &RistrettoPoint) -> Result<(), String> {
+    /// Creates an unencrypted public announcement: no recipient, only the signature matters
+    pub fn new_broadcast(payload: Vec<u8>) -> Self {
+        Message::new(
+            0,
+            payload,
+            CompressedRistretto(UNSET_SENDER),
+            CompressedRistretto(BROADCAST_RECIPIENT),
+            SchnorrSignature::emty_signature(),
+        )
+    }
 
-        // prit original payload
-        println!("Original payload: {:?}", self.payload);
-        // Step 1: Serialize the entire message using `serialize_message_to_bytes`
-        let serialized_message = serialize_message_to_bytes(self)?;
-    
-        // Step 2: Encrypt the serialized message
-        let hybrid_ciphertext = HybridCiphertext::encrypt(&serialized_message, elgamal_public_key)?;
-    
-        // Step 3: Update the fields of the message
-        self.payload = hybrid_ciphertext.serialize(); // Replace payload with encrypted data
-        self.version += 1; // Increment the version
-        self.signature = SchnorrSignature::emty_signature(); // Clear signature
-        self.sender = CompressedRistretto::default().to_bytes(); // Clear sender
-        self.recipient = elgamal_public_key.compress().to_bytes(); // Set recipient
-     
-        self.display();
-        Ok(())
+    /// Returns true if the message has no recipient, i.e. it is a public broadcast
+    pub fn is_broadcast(&self) -> bool {
+        self.recipient == BROADCAST_RECIPIENT
     }
-    
-    pub fn decrypt(&mut self, elgamal_private_key: &Scalar) -> Result<(), String> {
-        //Deserialize the hybrid ciphertext from the payload
-        let hybrid_ciphertext = HybridCiphertext::deserialize(&self.payload)?;
-    
-        //Decrypt the ciphertext to obtain the serialized plaintext
-        let plaintext = hybrid_ciphertext.decrypt(elgamal_private_key)?;
-    
-        //Deserialize the plaintext back into a Message using `deserialize_message_from_bytes`
-        let decrypted_message = deserialize_message_from_bytes(&plaintext)?;
-        
-        decrypted_message.display();
-        // Step 4: Update the current message's fields
-        //i want to print the verison of the decrypted message
-        println!("The version of the decrypted message is: {}", self.version);
-        self.version = decrypted_message.version;
-        self.payload = decrypted_message.payload;
-        self.sender = decrypted_message.sender;
-        self.recipient = decrypted_message.recipient;
-        self.signature = decrypted_message.signature;
-    
-        Ok(())
+
+    /// Writes the message to a pretty-printed JSON file, for debugging. Failures to create or
+    /// write the file are `FileIo`, failures to encode the message are `Serialization`, both
+    /// naming the path.
+    pub fn to_file(&self, filepath: &str) -> Result<(), MessageError> {
+        self.write_file(filepath, true)
     }
-    
-    
-    /// signs the payload using Schnorr signatures, sets the signing public key as sender
-    pub fn sign(&mut self, signing_key: &Scalar) {
-        let signature = SchnorrSignature::sign(&self.payload, signing_key);
-        let sender_public_key = signing_key * &curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
-        self.sender = sender_public_key.compress().to_bytes();
-        self.signature = signature;
+
+    /// Like `to_file`, without whitespace, for production. The signature covers
+    /// `signed_bytes` rather than the JSON text, so both forms verify alike.
+    pub fn to_file_compact(&self, filepath: &str) -> Result<(), MessageError> {
+        self.write_file(filepath, false)
     }
 
-    pub fn verify(&self) -> bool {
-        //Extract the sender's public key (vk)
-        let sender_public_key = CompressedRistretto(self.sender)
-            .decompress()
-            .expect("Failed to decompress sender's public key");
+    fn write_file(&self, filepath: &str, pretty: bool) -> Result<(), MessageError> {
+        let file_error = |kind| MessageError::FileIo {
+            path: filepath.to_string(),
+            kind,
+        };
+        let file = File::create(filepath).map_err(|e| file_error(e.kind()))?;
+        let result = match self.signature_format {
+            SignatureFormat::Map if pretty => serde_json::to_writer_pretty(file, self),
+            SignatureFormat::Map => serde_json::to_writer(file, self),
+            SignatureFormat::Array => message_to_json_value(self).and_then(|value| {
+                if pretty {
+                    serde_json::to_writer_pretty(file, &value)
+                } else {
+                    serde_json::to_writer(file, &value)
+                }
+            }),
+        };
+        result.map_err(|e| match e.io_error_kind() {
+            Some(kind) => file_error(kind),
+            None => MessageError::Serialization(format!("{}: {}", filepath, e)),
+        })
+    }
 
-        //Verify the signature
-        SchnorrSignature::verify(&self.signature, &self.payload, &sender_public_key)
+    /// Read-side counterpart of `to_file`: loads the envelope, checks it is well-formed and
+    /// that the signature verifies, optionally for an expected sender. Doesn't decrypt.
+    pub fn verify_file(
+        filepath: &str,
+        expected_sender: Option<RistrettoPoint>,
+    ) -> Result<(), MessageError> {
+        let json = std::fs::read_to_string(filepath)
+            .map_err(|e| MessageError::Serialization(format!("{}: {}", filepath, e)))?;
+        let message = Message::from_json(&json)?;
+        message.is_well_formed()?;
+
+        let sender = message.recover_and_verify()?;
+        match expected_sender {
+            Some(expected) if expected != sender => Err(MessageError::SenderMismatch),
+            _ => Ok(()),
+        }
     }
 
-    /// Display the message for debugging purposes
-    pub fn display(&self) {
-        println!("Version: {}", self.version);
-        println!("Payload: {:?}", self.payload);
-        println!("Recipient: {:?}", self.recipient);
-        println!("Sender: {:?}", self.sender);
-        println!("Signature: {:?}", self.signature);
+    /// Parses a JSON envelope, rejecting fields this build doesn't understand
+    /// with `UnsupportedFormat`
+    pub fn from_json(json: &str) -> Result<Message, MessageError> {
+        message_from_json(json).map_err(|e| {
+            if e.to_string().starts_with("unknown field") {
+                MessageError::UnsupportedFormat(e.to_string())
+            } else {
+                MessageError::Serialization(e.to_string())
+            }
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use curve25519_dalek::ristretto::RistrettoPoint;
-    use rand::rngs::OsRng;
-    use std::fs;
+    /// Parses a JSON envelope, ignoring unknown fields for forward compatibility.
+    /// Only use this where losing fields added by newer versions is acceptable.
+    pub fn from_json_lenient(json: &str) -> Result<Message, MessageError> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| MessageError::Serialization(e.to_string()))?;
+        upgrade_legacy_version(&mut value);
+        if let Some(fields) = value.as_object_mut() {
+            fields.retain(|name, _| MESSAGE_FIELDS.contains(&name.as_str()));
+        }
+        serde_json::from_value(value).map_err(|e| MessageError::Serialization(e.to_string()))
+    }
 
-    #[test]
-    fn test_message_creation() {
-        // Create a sample payload and recipient
-        let payload = b"Hello, this is a message!".to_vec();
-        let mut csprng = OsRng;
-        let recipient = RistrettoPoint::random(&mut csprng).compress();
+    /// Encodes the serialized envelope as a single Base64 token, convenient for text transports
+    pub fn to_token(&self) -> Result<String, MessageError> {
+        let bytes = serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
+        Ok(BASE64_STANDARD.encode(bytes))
+    }
 
-        // Create a new message
-        let version: u8 = 1;
-        let message = Message::new(
-            version,
-            payload.clone(),
-            recipient,
-            recipient,
-            SchnorrSignature::emty_signature(),
-        );
+    /// Parses a token produced by `to_token`
+    pub fn from_token(token: &str) -> Result<Message, MessageError> {
+        let bytes = BASE64_STANDARD
+            .decode(token.trim())
+            .map_err(|e| MessageError::InvalidEncoding(e.to_string()))?;
+        deserialize_message_from_bytes(&bytes).map_err(MessageError::Serialization)
+    }
 
-        // Check if the fields match
-        assert_eq!(message.version, version);
-        assert_eq!(message.payload, payload);
-        assert_eq!(message.recipient, recipient.to_bytes());
+    /// Serializes the message as MessagePack, with byte fields as binary instead of Base64.
+    /// Fields are keyed by name so optional fields can be left out.
+    #[cfg(feature = "msgpack")]
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, MessageError> {
+        rmp_serde::to_vec_named(self).map_err(|e| MessageError::Serialization(e.to_string()))
+    }
 
-        // Display the message
-        message.display();
+    /// Parses a message produced by `to_msgpack`
+    #[cfg(feature = "msgpack")]
+    pub fn from_msgpack(bytes: &[u8]) -> Result<Message, MessageError> {
+        rmp_serde::from_slice(bytes).map_err(|e| MessageError::Serialization(e.to_string()))
     }
 
-    #[test]
-    fn test_message_verification_failure() {
-        // Create a sample payload and recipient
-        let payload = b"Hello, this is a message!".to_vec();
-        let mut csprng = OsRng;
-        let recipient = RistrettoPoint::random(&mut csprng).compress();
-        let sender = RistrettoPoint::random(&mut csprng).compress();
+    /// Serializes the message as CBOR, with byte fields as binary instead of Base64
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor(&self) -> Result<Vec<u8>, MessageError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes)
+            .map_err(|e| MessageError::Serialization(e.to_string()))?;
+        Ok(bytes)
+    }
 
-        // Generate a random signing key
-        let signing_key = Scalar::random(&mut csprng);
+    /// Parses a message produced by `to_cbor`
+    #[cfg(feature = "cbor")]
+    pub fn from_cbor(bytes: &[u8]) -> Result<Message, MessageError> {
+        ciborium::from_reader(bytes).map_err(|e| MessageError::Serialization(e.to_string()))
+    }
 
-        // Create a new message
-        let mut message = Message::new(
-            1,
-            payload.clone(),
-            sender,
-            recipient,
-            SchnorrSignature::emty_signature(),
-        );
+    /// Serializes the message as bincode, the most compact of the formats. Every field is
+    /// written, empty or not, in a fixed order that both sides have to agree on.
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self) -> Result<Vec<u8>, MessageError> {
+        bincode_options()
+            .serialize(&BincodeEnvelope::borrow(self))
+            .map_err(|e| MessageError::Serialization(e.to_string()))
+    }
 
-        // Sign the message with the signing key
-        message.sign(&signing_key);
+    /// Parses a message produced by `to_bincode`
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(bytes: &[u8]) -> Result<Message, MessageError> {
+        bincode_options()
+            .deserialize::<BincodeEnvelope>(bytes)
+            .map(BincodeEnvelope::into_message)
+            .map_err(|e| MessageError::Serialization(e.to_string()))
+    }
 
-        // Tamper with the payload to make the signature invalid
-        message.payload[0] ^= 0xFF;
+    /// Serializes the message in the given format
+    pub fn to_bytes(&self, format: SerializationFormat) -> Result<Vec<u8>, MessageError> {
+        match format {
+            SerializationFormat::Json => {
+                serialize_message_to_bytes(self).map_err(MessageError::Serialization)
+            }
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => self.to_msgpack(),
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => self.to_cbor(),
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode => self.to_bincode(),
+        }
+    }
 
-        // Verify the message, which should fail
-        assert!(!message.verify(), "Verification should fail for tampered message");
+    /// Number of bytes `to_bytes` would produce in `format`, e.g. to size a transport buffer.
+    /// Runs the serializer into a byte counter, so no throwaway buffer is allocated, and fails
+    /// where `to_bytes` would.
+    pub fn serialized_len(&self, format: SerializationFormat) -> Result<usize, MessageError> {
+        let mut counter = ByteCounter(0);
+        match format {
+            SerializationFormat::Json => {
+                serde_json::to_writer(&mut counter, self)
+                    .map_err(|e| MessageError::Serialization(e.to_string()))?;
+                // The array form drops the `"R":` and `"s":` keys of the map form
+                if self.signature_format == SignatureFormat::Array {
+                    counter.0 -= COMPACT_SIGNATURE_SAVING;
+                }
+            }
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => rmp_serde::encode::write_named(&mut counter, self)
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => ciborium::into_writer(self, &mut counter)
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode => bincode_options()
+                .serialize_into(&mut counter, &BincodeEnvelope::borrow(self))
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+        }
+        Ok(counter.0)
     }
 
+    /// Parses a message serialized with `to_bytes` in the given format, keeping a copy of
+    /// `bytes` for `original_bytes`
+    pub fn from_bytes(bytes: &[u8], format: SerializationFormat) -> Result<Message, MessageError> {
+        let mut message = match format {
+            SerializationFormat::Json => {
+                let json = std::str::from_utf8(bytes)
+                    .map_err(|e| MessageError::InvalidEncoding(e.to_string()))?;
+                Message::from_json(json)
+            }
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => Message::from_msgpack(bytes),
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => Message::from_cbor(bytes),
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode => Message::from_bincode(bytes),
+        }?;
+        message.parsed_from = Some(ParsedFrom {
+            bytes: bytes.to_vec(),
+            message_id: message.message_id(),
+            signature: message.signature.clone(),
+        });
+        Ok(message)
+    }
 
+    /// The exact bytes the message was parsed from with `from_bytes`, so a relay can forward
+    /// it byte for byte instead of re-serializing it with different formatting.
+    /// None for messages that weren't parsed with `from_bytes` or have been changed since,
+    /// e.g. re-signed or decrypted.
+    pub fn original_bytes(&self) -> Option<&[u8]> {
+        self.parsed_from
+            .as_ref()
+            .filter(|parsed| {
+                parsed.signature == self.signature && parsed.message_id == self.message_id()
+            })
+            .map(|parsed| parsed.bytes.as_slice())
+    }
 
-    #[test]
-    fn test_message_encryption_and_decryption() {
-        // Sample message to encrypt
-        let payload = b"Hello, hybrid encryption!".to_vec();
+    /// Exports the whole message as a self-contained encrypted backup readable by any of
+    /// `recipients`, in the `archive` module's layout
+    pub fn to_archive(&self, recipients: &[RistrettoPoint]) -> Result<Vec<u8>, MessageError> {
+        if recipients.len() > u16::MAX as usize {
+            return Err(MessageError::Serialization(format!(
+                "{} recipients don't fit in an archive",
+                recipients.len()
+            )));
+        }
+        let serialized_message =
+            serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
+        Archive::seal(&serialized_message, self.format_version, recipients)
+            .map_err(MessageError::Encryption)?
+            .to_bytes()
+            .map_err(|e| MessageError::Serialization(e.to_string()))
+    }
 
-        // Generate ElGamal keypair
-        let keypair = KeyPair::generate();
+    /// Restores a message from a `to_archive` blob with the slot matching `private_key`
+    pub fn from_archive(bytes: &[u8], private_key: &Scalar) -> Result<Message, MessageError> {
+        let archive = Archive::from_bytes(bytes).map_err(|e| match e {
+            CryptoError::UnsupportedArchiveVersion(_) => {
+                MessageError::UnsupportedFormat(e.to_string())
+            }
+            _ => MessageError::MalformedCiphertext(e.to_string()),
+        })?;
+        let own_public_key = private_key * RISTRETTO_BASEPOINT_POINT;
+        if archive
+            .ciphertext
+            .extract_for(&fingerprint(&own_public_key))
+            .is_none()
+        {
+            return Err(MessageError::RecipientMismatch);
+        }
+        let plaintext = archive
+            .open(private_key)
+            .map_err(MessageError::Decryption)?;
+        deserialize_message_from_bytes(&plaintext).map_err(MessageError::Serialization)
+    }
 
-        // Create a new message with version 0
-        let mut message = Message::new(
-            0,
-            payload.clone(),
-            keypair.public_key.compress(),
-            keypair.public_key.compress(),
-            SchnorrSignature::emty_signature(),
-        );
+    /// Wraps the token in PGP-style ASCII armor with a CRC24 checksum line,
+    /// so the message survives being pasted into an email
+    pub fn to_armored(&self) -> Result<String, MessageError> {
+        let bytes = serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
+        let token = BASE64_STANDARD.encode(&bytes);
+        let checksum = BASE64_STANDARD.encode(&crc24(&bytes).to_be_bytes()[1..]);
 
-        // Encrypt the message
-        message
-            .encrypt(&keypair.public_key)
-            .expect("Encryption failed");
+        let mut armored =
+            String::with_capacity(token.len() + token.len() / ARMOR_LINE_LENGTH + 100);
+        armored.push_str(ARMOR_BEGIN);
+        armored.push_str("\n\n");
+        for line in token.as_bytes().chunks(ARMOR_LINE_LENGTH) {
+            armored.push_str(std::str::from_utf8(line).expect("Base64 is ASCII"));
+            armored.push('\n');
+        }
+        armored.push('=');
+        armored.push_str(&checksum);
+        armored.push('\n');
+        armored.push_str(ARMOR_END);
+        armored.push('\n');
 
-        // Ensure the message version is 1 after encryption
-        assert_eq!(message.version, 1, "Version should be 1 after encryption");
+        Ok(armored)
+    }
 
-        // Ensure the payload is not the same as the original (it should be encrypted)
-        assert_ne!(
-            message.payload, payload,
-            "Encrypted payload should not match the original payload"
-        );
+    /// Parses a message produced by `to_armored`, validating the CRC24 checksum
+    pub fn from_armored(armored: &str) -> Result<Message, MessageError> {
+        let mut lines = armored
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| line.is_empty());
+        if lines.next() != Some(ARMOR_BEGIN) {
+            return Err(MessageError::InvalidArmor);
+        }
 
-        // Decrypt the message
-        message
-            .decrypt(&keypair.private_key)
-            .expect("Decryption failed");
+        let mut token = String::new();
+        let mut checksum = None;
+        let mut ended = false;
+        for line in lines {
+            if line == ARMOR_END {
+                ended = true;
+                break;
+            } else if let Some(crc) = line.strip_prefix('=') {
+                checksum = Some(crc);
+            } else {
+                token.push_str(line);
+            }
+        }
+        let checksum = match (ended, checksum) {
+            (true, Some(checksum)) => checksum,
+            _ => return Err(MessageError::InvalidArmor),
+        };
 
-        // Ensure the message version is back to 0 after decryption
-        assert_eq!(message.version, 0, "Version should be 0 after decryption");
+        let bytes = BASE64_STANDARD
+            .decode(&token)
+            .map_err(|e| MessageError::InvalidEncoding(e.to_string()))?;
+        let expected = BASE64_STANDARD
+            .decode(checksum)
+            .map_err(|e| MessageError::InvalidEncoding(e.to_string()))?;
+        if expected[..] != crc24(&bytes).to_be_bytes()[1..] {
+            return Err(MessageError::ChecksumMismatch);
+        }
 
-        // Ensure the decrypted message matches the original payload
-        assert_eq!(
-            message.payload, payload,
-            "Decrypted payload should match the original payload"
-        );
+        deserialize_message_from_bytes(&bytes).map_err(MessageError::Serialization)
     }
 
-    #[test]
-fn test_versioning_during_encryption_decryption() {
-    // Sample message to encrypt
-    let payload = b"Message with versioning".to_vec();
+    /// Like `to_token`, but pads the serialized envelope to exactly `n` bytes before encoding,
+    /// so every token has the same length on the wire.
+    /// Padding is a single 0x80 marker followed by zeros (ISO/IEC 7816-4), so it needs at least
+    /// one spare byte: envelopes of `n` bytes or more are rejected.
+    pub fn to_fixed_size_token(&self, n: usize) -> Result<String, MessageError> {
+        let mut bytes = serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
+        if bytes.len() >= n {
+            return Err(MessageError::EnvelopeTooLarge {
+                size: bytes.len(),
+                limit: n,
+            });
+        }
 
-    // Generate ElGamal keypair
-    let keypair = KeyPair::generate();
+        bytes.push(PADDING_MARKER);
+        bytes.resize(n, 0x00);
 
-    // Create a new message with version 0
-    let mut message = Message::new(
-        0,
-        payload.clone(),
-        keypair.public_key.compress(),
-        keypair.public_key.compress(),
-        SchnorrSignature::emty_signature(),
-    );
+        Ok(BASE64_STANDARD.encode(bytes))
+    }
 
-    // Ensure initial version is 0
-    assert_eq!(message.version, 0, "Initial version should be 0");
+    /// Parses a token produced by `to_fixed_size_token`, stripping the padding
+    pub fn from_fixed_size_token(token: &str) -> Result<Message, MessageError> {
+        let mut bytes = BASE64_STANDARD
+            .decode(token.trim())
+            .map_err(|e| MessageError::InvalidEncoding(e.to_string()))?;
 
-    // Encrypt the message
-    message
-        .encrypt(&keypair.public_key)
-        .expect("Encryption failed");
+        // The padding is the last 0x80 byte and everything after it, which must be zeros
+        let marker = bytes
+            .iter()
+            .rposition(|&b| b != 0x00)
+            .ok_or(MessageError::InvalidPadding)?;
+        if bytes[marker] != PADDING_MARKER {
+            return Err(MessageError::InvalidPadding);
+        }
+        bytes.truncate(marker);
 
-    // Ensure version is incremented after encryption
-    assert_eq!(message.version, 1, "Version should be 1 after encryption");
+        deserialize_message_from_bytes(&bytes).map_err(MessageError::Serialization)
+    }
 
-    // Decrypt the message
-    message
-        .decrypt(&keypair.private_key)
-        .expect("Decryption failed");
+    pub fn encrypt(&mut self, elgamal_public_key: &RistrettoPoint) -> Result<(), MessageError> {
+        self.encrypt_with(elgamal_public_key, None)
+    }
 
-    // Ensure version is reset after decryption
-    assert_eq!(message.version, 0, "Version should be reset to 0 after decryption");
-}
+    /// Like `encrypt`, but refuses a signed message unless `force` is set. Encrypting clears
+    /// the envelope signature: it only survives inside the ciphertext, where relays can't
+    /// check it, so a flow that signs before encrypting silently loses authentication.
+    pub fn encrypt_strict(
+        &mut self,
+        elgamal_public_key: &RistrettoPoint,
+        force: bool,
+    ) -> Result<(), MessageError> {
+        if self.is_signed() && !force {
+            return Err(MessageError::SignatureWouldBeCleared);
+        }
+        self.encrypt(elgamal_public_key)
+    }
+
+    /// Encrypts to the recipient with `recipient_fingerprint`, fetching the public key from
+    /// `resolver`, e.g. a directory lookup. Fails with `UnknownRecipient` if the resolver has
+    /// no key for the fingerprint or returns a key with a different fingerprint.
+    pub fn encrypt_for_fingerprint(
+        &mut self,
+        recipient_fingerprint: [u8; 8],
+        resolver: impl Fn([u8; 8]) -> Option<RistrettoPoint>,
+    ) -> Result<(), MessageError> {
+        let public_key = resolver(recipient_fingerprint)
+            .filter(|key| fingerprint(key) == recipient_fingerprint)
+            .ok_or(MessageError::UnknownRecipient(recipient_fingerprint))?;
+        if self.state == MessageState::Encrypted {
+            return Err(MessageError::AlreadyEncrypted);
+        }
+        self.recipient = public_key.compress().to_bytes();
+        self.encrypt(&public_key)
+    }
 
-#[test]
-fn test_signature_verification() {
-    // Sample payload
-    let payload = b"Message to verify signature".to_vec();
+    /// Like `encrypt`, but derives all randomness from `ikm` and the serialized message, see
+    /// `HybridCiphertext::encrypt_deterministic`. Encrypting the same message to the same key
+    /// with the same `ikm` gives byte-identical envelopes, so anyone seeing two of them learns
+    /// the messages are equal. Meant for test vectors and deduplication only.
+    pub fn encrypt_deterministic(
+        &mut self,
+        elgamal_public_key: &RistrettoPoint,
+        ikm: &[u8],
+    ) -> Result<(), MessageError> {
+        self.encrypt_with(elgamal_public_key, Some(ikm))
+    }
 
-    // Generate signing keypair
-    let signing_key = Scalar::random(&mut rand::rngs::OsRng);
-    let sender_public_key = signing_key * &curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+    fn encrypt_with(
+        &mut self,
+        elgamal_public_key: &RistrettoPoint,
+        deterministic_ikm: Option<&[u8]>,
+    ) -> Result<(), MessageError> {
+        if self.state == MessageState::Encrypted {
+            return Err(MessageError::AlreadyEncrypted);
+        }
+        if self.is_broadcast() {
+            return Err(MessageError::BroadcastNotEncryptable);
+        }
+        if is_weak_point(elgamal_public_key) {
+            return Err(MessageError::WeakKey);
+        }
+        #[cfg(feature = "tracing")]
+        let started = std::time::Instant::now();
 
-    // Create a new message
-    let mut message = Message::new(
-        0,
-        payload.clone(),
-        sender_public_key.compress(),
-        sender_public_key.compress(),
-        SchnorrSignature::emty_signature(),
-    );
+        // Step 1: Serialize the entire message using `serialize_message_to_bytes`
+        let serialized_message =
+            serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
 
-    // Sign the message
-    message.sign(&signing_key);
+        // Step 2: Encrypt the serialized message
+        let aad = self.routing_aad();
+        let hybrid_ciphertext = match deterministic_ikm {
+            Some(ikm) => HybridCiphertext::encrypt_deterministic(
+                &serialized_message,
+                &aad,
+                elgamal_public_key,
+                ikm,
+            ),
+            None => {
+                HybridCiphertext::encrypt_with_aad(&serialized_message, &aad, elgamal_public_key)
+            }
+        }
+        .map_err(MessageError::Encryption)?;
+        let payload = hybrid_ciphertext.serialize();
+        let recipient = elgamal_public_key.compress().to_bytes();
 
-    // Verify the message
-    assert!(
-        message.verify(),
-        "Message verification failed for correct payload and signature"
-    );
-}
+        // Step 3: Commit the new envelope. Nothing below can fail, so an error above
+        // leaves the message exactly as it was
+        self.payload = payload; // Replace payload with encrypted data
+        self.state = MessageState::Encrypted;
+        self.signature = SchnorrSignature::emty_signature(); // Clear signature
+        self.sender = UNSET_SENDER; // Clear sender
+        self.recipient = recipient; // Set recipient
+        self.headers.clear(); // Headers and attachments are only readable inside the ciphertext
+        self.attachments.clear();
 
-#[test]
-fn test_signature_verification_failure_on_tampered_signature() {
-    // Sample payload
-    let payload = b"Original message".to_vec();
+        #[cfg(feature = "tracing")]
+        trace_operation("encrypt", &self.message_id(), &self.recipient, started);
+        Ok(())
+    }
 
-    // Generate signing keypair
-    let signing_key = Scalar::random(&mut rand::rngs::OsRng);
-    let sender_public_key = signing_key * &curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+    pub fn decrypt(&mut self, elgamal_private_key: &Scalar) -> Result<(), MessageError> {
+        #[cfg(feature = "tracing")]
+        let (started, envelope_id) = (std::time::Instant::now(), self.message_id());
+        let decrypted_message = self.decrypted_message(elgamal_private_key)?;
 
-    // Create a new message
-    let mut message = Message::new(
-        0,
-        payload.clone(),
-        sender_public_key.compress(),
-        sender_public_key.compress(),
-        SchnorrSignature::emty_signature(),
-    );
+        // Step 4: Update the current message's fields
+        #[cfg(feature = "tracing")]
+        trace_operation("decrypt", &envelope_id, &self.recipient, started);
+        self.restore(decrypted_message);
 
-    // Sign the message
-    message.sign(&signing_key);
+        Ok(())
+    }
 
-    // Tamper with the signature by modifying the scalar 's'
-    message.signature.s += Scalar::random(&mut rand::rngs::OsRng);
+    /// Decrypts a hybrid-mode message and writes the decrypted payload to `writer`, leaving
+    /// the envelope encrypted. The whole ciphertext is authenticated before any plaintext is
+    /// written, so a tampered message writes nothing, and the plaintext is zeroized after
+    /// writing instead of being handed back.
+    pub fn decrypt_to_writer<W: Write>(
+        &self,
+        elgamal_private_key: &Scalar,
+        writer: &mut W,
+    ) -> Result<(), MessageError> {
+        let mut decrypted_message = self.decrypted_message(elgamal_private_key)?;
+        let written = writer
+            .write_all(&decrypted_message.payload)
+            .and_then(|_| writer.flush())
+            .map_err(|e| MessageError::Io(e.kind()));
+        decrypted_message.zeroize_payload();
+        written
+    }
 
-    // Verify the tampered message
-    assert!(
-        !message.verify(),
-        "Message verification should fail for tampered signature"
-    );
-}
+    /// Replaces the envelope fields with those of the decrypted message, keeping the
+    /// outer rotation log
+    fn restore(&mut self, decrypted_message: Message) {
+        self.format_version = decrypted_message.format_version;
+        self.state = decrypted_message.state;
+        self.payload = decrypted_message.payload;
+        self.sender = decrypted_message.sender;
+        self.recipient = decrypted_message.recipient;
+        self.signature = decrypted_message.signature;
+        self.certificate_chain = decrypted_message.certificate_chain;
+        self.headers = decrypted_message.headers;
+        self.routing_headers = decrypted_message.routing_headers;
+        self.attachments = decrypted_message.attachments;
+        self.mode = decrypted_message.mode;
+    }
+
+    /// Like `encrypt`, but encrypts the headers and the rest of the message under separate
+    /// AES keys, each ElGamal-wrapped to the recipient. `decrypt_headers` then reads the
+    /// headers without touching a possibly large body.
+    /// The payload is the length of the serialized header ciphertext (u32, little-endian),
+    /// the header ciphertext and the body ciphertext. The body authenticates a hash of the
+    /// header ciphertext, so headers can't be moved onto another body.
+    pub fn encrypt_split(
+        &mut self,
+        elgamal_public_key: &RistrettoPoint,
+    ) -> Result<(), MessageError> {
+        if self.state == MessageState::Encrypted {
+            return Err(MessageError::AlreadyEncrypted);
+        }
+        if self.is_broadcast() {
+            return Err(MessageError::BroadcastNotEncryptable);
+        }
+        if is_weak_point(elgamal_public_key) {
+            return Err(MessageError::WeakKey);
+        }
+
+        let headers = serde_json::to_vec(&self.headers)
+            .map_err(|e| MessageError::Serialization(e.to_string()))?;
+        let mut body = self.clone();
+        body.headers.clear();
+        let body = serialize_message_to_bytes(&body).map_err(MessageError::Serialization)?;
+
+        let encrypted_headers = HybridCiphertext::encrypt(&headers, elgamal_public_key)
+            .map_err(MessageError::Encryption)?
+            .serialize();
+        let encrypted_body = HybridCiphertext::encrypt_with_aad(
+            &body,
+            &self.split_body_aad(&encrypted_headers),
+            elgamal_public_key,
+        )
+        .map_err(MessageError::Encryption)?
+        .serialize();
 
+        let mut payload = Vec::with_capacity(4 + encrypted_headers.len() + encrypted_body.len());
+        payload.extend_from_slice(&(encrypted_headers.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&encrypted_headers);
+        payload.extend_from_slice(&encrypted_body);
+
+        self.payload = payload;
+        self.state = MessageState::Encrypted;
+        self.signature = SchnorrSignature::emty_signature();
+        self.sender = UNSET_SENDER;
+        self.recipient = elgamal_public_key.compress().to_bytes();
+        self.headers.clear();
+        self.attachments.clear();
+        self.mode = EncryptionMode::Split;
+        Ok(())
+    }
+
+    /// Decrypts only the headers of a message produced by `encrypt_split`
+    pub fn decrypt_headers(
+        &self,
+        elgamal_private_key: &Scalar,
+    ) -> Result<BTreeMap<String, String>, MessageError> {
+        self.check_split_recipient(elgamal_private_key)?;
+        let (encrypted_headers, _) = self.split_parts()?;
+        let headers = encrypted_headers
+            .decrypt(elgamal_private_key)
+            .map_err(MessageError::Decryption)?;
+        serde_json::from_slice(&headers).map_err(|e| MessageError::Serialization(e.to_string()))
+    }
+
+    /// Decrypts a message produced by `encrypt_split`, headers and body
+    pub fn decrypt_body(&mut self, elgamal_private_key: &Scalar) -> Result<(), MessageError> {
+        let headers = self.decrypt_headers(elgamal_private_key)?;
+        let (_, encrypted_body) = self.split_parts()?;
+        // `split_parts` checked the header ciphertext length
+        let header_length = u32::from_le_bytes(self.payload[..4].try_into().unwrap()) as usize;
+        let aad = self.split_body_aad(&self.payload[4..4 + header_length]);
+        let body = encrypted_body
+            .decrypt_with_aad(elgamal_private_key, &aad)
+            .map_err(MessageError::Decryption)?;
+        let mut decrypted_message =
+            deserialize_message_from_bytes(&body).map_err(MessageError::Serialization)?;
+        decrypted_message.headers = headers;
+
+        self.restore(decrypted_message);
+        Ok(())
+    }
+
+    fn check_split_recipient(&self, elgamal_private_key: &Scalar) -> Result<(), MessageError> {
+        self.check_format_version()?;
+        if self.mode != EncryptionMode::Split {
+            return Err(MessageError::WrongMode(self.mode));
+        }
+        let own_public_key = elgamal_private_key * RISTRETTO_BASEPOINT_POINT;
+        if !public_keys_equal(&own_public_key.compress().to_bytes(), &self.recipient) {
+            return Err(MessageError::RecipientMismatch);
+        }
+        Ok(())
+    }
+
+    /// Parses the header and body ciphertexts of a split-mode payload
+    fn split_parts(&self) -> Result<(HybridCiphertext, HybridCiphertext), MessageError> {
+        let malformed = |e: String| MessageError::MalformedCiphertext(e);
+        let length: [u8; 4] = self
+            .payload
+            .get(..4)
+            .and_then(|length| length.try_into().ok())
+            .ok_or_else(|| malformed("Missing header ciphertext length".to_string()))?;
+        let split = 4 + u32::from_le_bytes(length) as usize;
+        if split > self.payload.len() {
+            return Err(malformed(
+                "Header ciphertext length out of range".to_string(),
+            ));
+        }
+
+        let headers = HybridCiphertext::deserialize(&self.payload[4..split])
+            .map_err(|e| malformed(e.to_string()))?;
+        let body = HybridCiphertext::deserialize(&self.payload[split..])
+            .map_err(|e| malformed(e.to_string()))?;
+        Ok((headers, body))
+    }
+
+    /// Returns the decrypted payload without modifying the message, so a relay can inspect
+    /// it and still forward the original encrypted and signed envelope
+    pub fn peek_decrypt(&self, elgamal_private_key: &Scalar) -> Result<Vec<u8>, MessageError> {
+        Ok(self.decrypted_message(elgamal_private_key)?.payload)
+    }
+
+    /// Decrypts the hybrid-encrypted payload into the message it wraps
+    fn decrypted_message(&self, elgamal_private_key: &Scalar) -> Result<Message, MessageError> {
+        self.check_format_version()?;
+        if self.mode != EncryptionMode::Hybrid {
+            return Err(MessageError::WrongMode(self.mode));
+        }
+
+        // Refuse to decrypt messages addressed to someone else
+        let own_public_key = elgamal_private_key * RISTRETTO_BASEPOINT_POINT;
+        if !public_keys_equal(&own_public_key.compress().to_bytes(), &self.recipient) {
+            return Err(MessageError::RecipientMismatch);
+        }
+
+        //Deserialize the hybrid ciphertext from the payload
+        let hybrid_ciphertext = HybridCiphertext::deserialize(&self.payload)
+            .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?;
+
+        //Decrypt the ciphertext to obtain the serialized plaintext
+        let plaintext = hybrid_ciphertext
+            .decrypt_with_aad(elgamal_private_key, &self.routing_aad())
+            .map_err(MessageError::Decryption)?;
+
+        //Deserialize the plaintext back into a Message using `deserialize_message_from_bytes`
+        deserialize_message_from_bytes(&plaintext).map_err(MessageError::Serialization)
+    }
+
+    /// Moves a hybrid-encrypted message to a new recipient by decrypting it with
+    /// `current_private_key` and encrypting it again to `new_recipient`, appending the new
+    /// recipient's fingerprint to `rotation_log`. Like `encrypt`, this clears the signature.
+    pub fn reencrypt(
+        &mut self,
+        current_private_key: &Scalar,
+        new_recipient: &RistrettoPoint,
+    ) -> Result<(), MessageError> {
+        let rotation_log = self.rotation_log.clone();
+        let mut reencrypted = self.clone();
+        reencrypted.decrypt(current_private_key)?;
+        reencrypted.recipient = new_recipient.compress().to_bytes();
+        reencrypted.rotation_log = rotation_log;
+        reencrypted.encrypt(new_recipient)?;
+        reencrypted.rotation_log.push(fingerprint(new_recipient));
+
+        *self = reencrypted;
+        Ok(())
+    }
+
+    /// Like `reencrypt`, but only rewraps the ElGamal-encrypted AES key for `new_recipient`
+    /// and leaves the AES body untouched, so the payload is never decrypted.
+    /// Clears the signature and appends to `rotation_log`.
+    pub fn reencrypt_key(
+        &mut self,
+        current_private_key: &Scalar,
+        new_recipient: &RistrettoPoint,
+    ) -> Result<(), MessageError> {
+        if self.mode != EncryptionMode::Hybrid {
+            return Err(MessageError::WrongMode(self.mode));
+        }
+        let own_public_key = current_private_key * RISTRETTO_BASEPOINT_POINT;
+        if !public_keys_equal(&own_public_key.compress().to_bytes(), &self.recipient) {
+            return Err(MessageError::RecipientMismatch);
+        }
+        if is_weak_point(new_recipient) {
+            return Err(MessageError::WeakKey);
+        }
+
+        let mut hybrid_ciphertext = HybridCiphertext::deserialize(&self.payload)
+            .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?;
+        let aes_key = hybrid_ciphertext
+            .elgamal_ciphertext
+            .decrypt(current_private_key);
+        hybrid_ciphertext.elgamal_ciphertext = ElGamalCiphertext::encrypt(&aes_key, new_recipient)
+            .map_err(|_| MessageError::RngUnavailable)?;
+
+        self.payload = hybrid_ciphertext.serialize();
+        self.recipient = new_recipient.compress().to_bytes();
+        self.signature = SchnorrSignature::emty_signature();
+        self.sender = UNSET_SENDER;
+        self.rotation_log.push(fingerprint(new_recipient));
+        Ok(())
+    }
+
+    /// Exposes the AES nonce and ElGamal `c1` of the encrypted payload
+    #[cfg(any(test, feature = "debug-tools"))]
+    pub fn debug_crypto_params(&self) -> Result<CryptoParams, MessageError> {
+        match self.mode {
+            EncryptionMode::Hybrid => {
+                let hybrid_ciphertext = HybridCiphertext::deserialize(&self.payload)
+                    .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?;
+                Ok(CryptoParams {
+                    aes_nonce: hybrid_ciphertext.aes_ciphertext.nonce,
+                    c1: Some(
+                        hybrid_ciphertext
+                            .elgamal_ciphertext
+                            .c1
+                            .compress()
+                            .to_bytes(),
+                    ),
+                })
+            }
+            EncryptionMode::Symmetric => {
+                let aes_ciphertext = AESCiphertext::deserialize(&self.payload)
+                    .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?;
+                Ok(CryptoParams {
+                    aes_nonce: aes_ciphertext.nonce,
+                    c1: None,
+                })
+            }
+            EncryptionMode::Split => {
+                let (_, body) = self.split_parts()?; // The body carries the payload
+                Ok(CryptoParams {
+                    aes_nonce: body.aes_ciphertext.nonce,
+                    c1: Some(body.elgamal_ciphertext.c1.compress().to_bytes()),
+                })
+            }
+            EncryptionMode::Timelock => {
+                let (_, aes_ciphertext) = self.timelock_parts()?;
+                Ok(CryptoParams {
+                    aes_nonce: aes_ciphertext.nonce,
+                    c1: None,
+                })
+            }
+        }
+    }
+
+    /// Encrypts the message with a pre-shared AES key, skipping ElGamal entirely
+    pub fn encrypt_symmetric(&mut self, key: &[u8; AES_KEY_SIZE]) -> Result<(), MessageError> {
+        if self.state == MessageState::Encrypted {
+            return Err(MessageError::AlreadyEncrypted);
+        }
+        if self.is_broadcast() {
+            return Err(MessageError::BroadcastNotEncryptable);
+        }
+
+        let serialized_message =
+            serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
+
+        let aes_ciphertext = CipherContext::new(AeadAlgorithm::Aes256Gcm, key)
+            .encrypt_with_aad(&serialized_message, &self.routing_aad())
+            .map_err(MessageError::Encryption)?;
+
+        self.payload = aes_ciphertext.serialize();
+        self.state = MessageState::Encrypted;
+        self.signature = SchnorrSignature::emty_signature();
+        self.sender = UNSET_SENDER;
+        self.headers.clear();
+        self.attachments.clear();
+        self.mode = EncryptionMode::Symmetric; // Mark the envelope so decrypt picks the right path
+
+        Ok(())
+    }
+
+    /// Decrypts a message produced by `encrypt_symmetric` using the same pre-shared key
+    pub fn decrypt_symmetric(&mut self, key: &[u8; AES_KEY_SIZE]) -> Result<(), MessageError> {
+        self.check_format_version()?;
+        if self.mode != EncryptionMode::Symmetric {
+            return Err(MessageError::WrongMode(self.mode));
+        }
+
+        let aes_ciphertext = AESCiphertext::deserialize(&self.payload)
+            .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?;
+        let plaintext = CipherContext::new(aes_ciphertext.algorithm, key)
+            .decrypt_with_aad(&aes_ciphertext, &self.routing_aad())
+            .map_err(|e| MessageError::Decryption(e.to_string()))?;
+        let decrypted_message =
+            deserialize_message_from_bytes(&plaintext).map_err(MessageError::Serialization)?;
+
+        *self = decrypted_message;
+
+        Ok(())
+    }
+
+    /// Encrypts the message under a random AES key locked behind a time-lock puzzle, so
+    /// anyone can decrypt it but only after about `difficulty` sequential squarings.
+    /// See the `timelock` module for how difficulty relates to wall-clock time.
+    pub fn timelock_encrypt(&mut self, difficulty: u64) -> Result<(), MessageError> {
+        if self.state == MessageState::Encrypted {
+            return Err(MessageError::AlreadyEncrypted);
+        }
+        if self.is_broadcast() {
+            return Err(MessageError::BroadcastNotEncryptable);
+        }
+
+        let serialized_message =
+            serialize_message_to_bytes(self).map_err(MessageError::Serialization)?;
+        let mut key = [0u8; AES_KEY_SIZE];
+        secure_rng()
+            .map_err(|_| MessageError::RngUnavailable)?
+            .fill_bytes(&mut key);
+        let aes_ciphertext = CipherContext::new(AeadAlgorithm::Aes256Gcm, &key)
+            .encrypt_with_aad(&serialized_message, &self.routing_aad())
+            .map_err(MessageError::Encryption)?;
+        let puzzle = TimelockPuzzle::lock(&key, difficulty);
+        key.zeroize();
+        let puzzle = puzzle.map_err(|_| MessageError::RngUnavailable)?.to_bytes();
+
+        // Length-prefixed puzzle followed by the AES ciphertext
+        let mut payload = Vec::with_capacity(4 + puzzle.len());
+        payload.extend_from_slice(&(puzzle.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&puzzle);
+        payload.extend_from_slice(&aes_ciphertext.serialize());
+
+        self.payload = payload;
+        self.state = MessageState::Encrypted;
+        self.signature = SchnorrSignature::emty_signature();
+        self.sender = UNSET_SENDER;
+        self.headers.clear();
+        self.attachments.clear();
+        self.mode = EncryptionMode::Timelock;
+
+        Ok(())
+    }
+
+    /// Solves the time-lock puzzle of a message produced by `timelock_encrypt` and decrypts
+    /// it. Blocks for as long as the squarings take.
+    pub fn timelock_decrypt(&mut self) -> Result<(), MessageError> {
+        self.check_format_version()?;
+        if self.mode != EncryptionMode::Timelock {
+            return Err(MessageError::WrongMode(self.mode));
+        }
+
+        let (puzzle, aes_ciphertext) = self.timelock_parts()?;
+        let mut key = puzzle.solve();
+        let plaintext = CipherContext::new(aes_ciphertext.algorithm, &key)
+            .decrypt_with_aad(&aes_ciphertext, &self.routing_aad())
+            .map_err(|e| MessageError::Decryption(e.to_string()));
+        key.zeroize();
+        let decrypted_message =
+            deserialize_message_from_bytes(&plaintext?).map_err(MessageError::Serialization)?;
+
+        *self = decrypted_message;
+
+        Ok(())
+    }
+
+    /// Splits a time-locked payload into the puzzle and the AES ciphertext
+    fn timelock_parts(&self) -> Result<(TimelockPuzzle, AESCiphertext), MessageError> {
+        let malformed = |e: String| MessageError::MalformedCiphertext(e);
+        let length: [u8; 4] = self
+            .payload
+            .get(..4)
+            .and_then(|length| length.try_into().ok())
+            .ok_or_else(|| malformed("Missing puzzle length".to_string()))?;
+        let split = 4 + u32::from_le_bytes(length) as usize;
+        if split > self.payload.len() {
+            return Err(malformed("Puzzle length out of range".to_string()));
+        }
+
+        let puzzle = TimelockPuzzle::from_bytes(&self.payload[4..split])
+            .map_err(|e| malformed(e.to_string()))?;
+        let aes_ciphertext = AESCiphertext::deserialize(&self.payload[split..])
+            .map_err(|e| malformed(e.to_string()))?;
+        Ok((puzzle, aes_ciphertext))
+    }
+
+    /// Encrypts to the recipient and then signs the ciphertext, in the order the
+    /// receiving side expects with `open`. Records `PRODUCER` in the signed `producer` field.
+    pub fn seal(
+        &mut self,
+        recipient_public_key: &RistrettoPoint,
+        signing_key: &Scalar,
+    ) -> Result<(), MessageError> {
+        self.encrypt(recipient_public_key)?;
+        self.producer = Some(PRODUCER.to_string());
+        self.sign(signing_key)
+    }
+
+    /// Counterpart of `seal`: checks the envelope is signed by `sender_public_key` and only
+    /// then decrypts, so forged envelopes never reach the decryption
+    pub fn open(
+        &mut self,
+        sender_public_key: &RistrettoPoint,
+        elgamal_private_key: &Scalar,
+    ) -> Result<(), MessageError> {
+        if self.recover_and_verify()? != *sender_public_key {
+            return Err(MessageError::SenderMismatch);
+        }
+        self.decrypt(elgamal_private_key)
+    }
+
+    /// Seals a note only `keypair` can read, e.g. for secure storage. Encryption and
+    /// signing use separate subkeys derived from the private key.
+    pub fn encrypt_for_self(keypair: &KeyPair, message: &[u8]) -> Result<Message, MessageError> {
+        let encryption_key = keypair.subkey(SELF_ENCRYPTION_INFO);
+        let signing_key = keypair.subkey(SELF_SIGNING_INFO);
+
+        let mut note = Message::new(
+            FORMAT_VERSION,
+            message.to_vec(),
+            CompressedRistretto(UNSET_SENDER),
+            encryption_key.public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+        note.seal(&encryption_key.public_key, &signing_key.private_key)?;
+        Ok(note)
+    }
+
+    /// Opens a note from `encrypt_for_self`, checking it was signed by `keypair`
+    pub fn decrypt_own(&self, keypair: &KeyPair) -> Result<Vec<u8>, MessageError> {
+        let encryption_key = keypair.subkey(SELF_ENCRYPTION_INFO);
+        let signing_key = keypair.subkey(SELF_SIGNING_INFO);
+
+        let mut note = self.clone();
+        note.open(&signing_key.public_key, &encryption_key.private_key)?;
+        Ok(note.payload)
+    }
+
+    /// Bytes covered by the signature: every envelope field except the signature itself.
+    /// The payload, headers and attachments are length-prefixed so field boundaries are
+    /// unambiguous. Headers, routing headers, the rotation log and attachments are only
+    /// appended when present, each section starting with a marker byte and its entry count,
+    /// followed by the length-prefixed producer if set.
+    /// Unknown domain versions get no domain tag, `sign` and `verify` reject them.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let domain = signing_domain(self.domain_version).unwrap_or_default();
+        let mut buffer = Vec::with_capacity(domain.len() + 2 + 64 + 8 + self.payload.len());
+        buffer.extend_from_slice(domain);
+        buffer.push(self.format_version);
+        buffer.push(self.state as u8);
+        buffer.push(self.mode as u8);
+        buffer.extend_from_slice(&self.recipient);
+        buffer.extend_from_slice(&self.sender);
+        buffer.extend_from_slice(&(self.payload.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(&self.payload);
+        fn push_prefixed(buffer: &mut Vec<u8>, bytes: &[u8]) {
+            buffer.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            buffer.extend_from_slice(bytes);
+        }
+        if !self.headers.is_empty() {
+            buffer.push(b'H');
+            buffer.extend_from_slice(&(self.headers.len() as u64).to_le_bytes());
+            for (name, value) in &self.headers {
+                push_prefixed(&mut buffer, name.as_bytes());
+                push_prefixed(&mut buffer, value.as_bytes());
+            }
+        }
+        if !self.routing_headers.is_empty() {
+            buffer.push(b'O');
+            buffer.extend_from_slice(&(self.routing_headers.len() as u64).to_le_bytes());
+            for (name, value) in &self.routing_headers {
+                push_prefixed(&mut buffer, name.as_bytes());
+                push_prefixed(&mut buffer, value.as_bytes());
+            }
+        }
+        if !self.rotation_log.is_empty() {
+            buffer.push(b'R');
+            buffer.extend_from_slice(&(self.rotation_log.len() as u64).to_le_bytes());
+            for fingerprint in &self.rotation_log {
+                buffer.extend_from_slice(fingerprint);
+            }
+        }
+        if !self.attachments.is_empty() {
+            buffer.push(b'A');
+            buffer.extend_from_slice(&(self.attachments.len() as u64).to_le_bytes());
+            for attachment in &self.attachments {
+                push_prefixed(&mut buffer, attachment.name.as_bytes());
+                push_prefixed(&mut buffer, &attachment.content);
+            }
+        }
+        if let Some(producer) = &self.producer {
+            buffer.push(b'P');
+            push_prefixed(&mut buffer, producer.as_bytes());
+        }
+        buffer
+    }
+
+    /// SHA-512 of `signed_bytes`, the value the Schnorr signature is computed over.
+    /// Signers that only accept a fixed-size input (e.g. an HSM) sign this digest, with the
+    /// usual e = H(R || digest), s = r + e * sk, and the result is attached with `set_signature`.
+    /// `sender` has to be set to the signer's key before computing it.
+    pub fn signing_digest(&self) -> [u8; 64] {
+        Sha512::digest(self.signed_bytes()).into()
+    }
+
+    /// Attaches a signature produced externally over `signing_digest`
+    pub fn set_signature(&mut self, signature: SchnorrSignature) {
+        self.signature = signature;
+    }
+
+    /// Stable 16-byte identifier for deduplication and acknowledgments: the first 16 bytes of
+    /// SHA-256 over `signed_bytes` (format version, state, mode, recipient, sender, payload,
+    /// headers and attachments).
+    /// The signature and certificate chain don't contribute, so re-signing or re-serializing a
+    /// message keeps its ID. Encrypting changes the payload and therefore the ID, while the
+    /// decrypted inner message keeps the ID it had before encryption.
+    pub fn message_id(&self) -> [u8; 16] {
+        let digest = Sha256::digest(self.signed_bytes());
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&digest[..16]);
+        id
+    }
+
+    /// JSON summary for audit logs: format version, state, mode, the fingerprints of recipient
+    /// and sender, the message ID and the payload length. Leaves out the payload itself,
+    /// headers and attachments, so it is safe to log even for unencrypted messages.
+    pub fn to_public_json(&self) -> String {
+        let key_fingerprint = |bytes: &[u8; 32]| {
+            CompressedRistretto(*bytes)
+                .decompress()
+                .map(|point| to_hex(&fingerprint(&point)))
+        };
+        let recipient = if self.is_broadcast() {
+            None
+        } else {
+            key_fingerprint(&self.recipient)
+        };
+        let sender = if self.sender == UNSET_SENDER {
+            None
+        } else {
+            key_fingerprint(&self.sender)
+        };
+
+        serde_json::json!({
+            "format_version": self.format_version,
+            "state": self.state,
+            "mode": self.mode,
+            "recipient": recipient,
+            "sender": sender,
+            "message_id": to_hex(&self.message_id()),
+            "payload_length": self.payload.len(),
+        })
+        .to_string()
+    }
+
+    /// Creates a reply to this (received, still encrypted) message: the reply is addressed to
+    /// the original sender, carries the original's message ID in the `in-reply-to` header,
+    /// is encrypted to the sender's key and signed with `sender_sk`.
+    /// The original has to verify, otherwise its `sender` field can't be trusted.
+    pub fn reply(&self, payload: Vec<u8>, sender_sk: &Scalar) -> Result<Message, MessageError> {
+        if !self.verify() {
+            return Err(MessageError::InvalidSignature);
+        }
+        let original_sender = CompressedRistretto(self.sender)
+            .decompress()
+            .ok_or(MessageError::InvalidSignature)?;
+
+        let mut reply = Message::new(
+            0,
+            payload,
+            CompressedRistretto(UNSET_SENDER),
+            original_sender.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+        reply
+            .headers
+            .insert(IN_REPLY_TO_HEADER.to_string(), to_hex(&self.message_id()));
+        reply.encrypt(&original_sender)?;
+        reply.sign(sender_sk)?;
+        Ok(reply)
+    }
+
+    /// Splits `payload` into chunks of at most `chunk_size` bytes, each encrypted to `recipient`
+    /// and signed as its own message. The chunk headers are encrypted along with the data.
+    /// An empty payload still produces one (empty) chunk. A `chunk_size` of 0 is refused.
+    pub fn split(
+        payload: &[u8],
+        chunk_size: usize,
+        signing_key: &Scalar,
+        recipient: &RistrettoPoint,
+    ) -> Result<Vec<Message>, MessageError> {
+        if chunk_size == 0 {
+            return Err(MessageError::InvalidChunkSize);
+        }
+
+        let chunks: Vec<&[u8]> = if payload.is_empty() {
+            vec![payload]
+        } else {
+            payload.chunks(chunk_size).collect()
+        };
+        let mut set_id = [0u8; 16];
+        secure_rng()
+            .map_err(|_| MessageError::RngUnavailable)?
+            .fill_bytes(&mut set_id);
+        let set_id = to_hex(&set_id);
+
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut message = Message::new(
+                    0,
+                    chunk.to_vec(),
+                    CompressedRistretto(UNSET_SENDER),
+                    recipient.compress(),
+                    SchnorrSignature::emty_signature(),
+                );
+                message
+                    .headers
+                    .insert(CHUNK_INDEX_HEADER.to_string(), index.to_string());
+                message
+                    .headers
+                    .insert(TOTAL_CHUNKS_HEADER.to_string(), chunks.len().to_string());
+                message
+                    .headers
+                    .insert(CHUNK_SET_HEADER.to_string(), set_id.clone());
+                message.encrypt(recipient)?;
+                message.sign(signing_key)?;
+                Ok(message)
+            })
+            .collect()
+    }
+
+    /// Verifies and decrypts the chunks produced by `split` and concatenates their payloads.
+    /// The chunks may arrive in any order, but they have to come from the same sender and
+    /// split, and every index from 0 to `total-chunks - 1` has to be present exactly once.
+    pub fn reassemble(
+        messages: &[Message],
+        recipient_sk: &Scalar,
+    ) -> Result<Vec<u8>, MessageError> {
+        let first = messages.first().ok_or(MessageError::MissingChunk(0))?;
+        // Keyed by index rather than preallocated: the chunk count comes from the sender
+        let mut chunks: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        let mut expected: Option<(String, usize)> = None;
+
+        for message in messages {
+            if !message.verify() {
+                return Err(MessageError::InvalidSignature);
+            }
+            if message.sender != first.sender {
+                return Err(MessageError::InconsistentChunks);
+            }
+
+            let mut chunk = message.clone();
+            chunk.decrypt(recipient_sk)?;
+            let header = |name: &str| {
+                chunk
+                    .headers
+                    .get(name)
+                    .cloned()
+                    .ok_or(MessageError::InconsistentChunks)
+            };
+            let parse = |value: String| {
+                value
+                    .parse::<usize>()
+                    .map_err(|_| MessageError::InconsistentChunks)
+            };
+            let index = parse(header(CHUNK_INDEX_HEADER)?)?;
+            let total = parse(header(TOTAL_CHUNKS_HEADER)?)?;
+            let chunk_set = header(CHUNK_SET_HEADER)?;
+
+            if total == 0
+                || expected.get_or_insert_with(|| (chunk_set.clone(), total)) != &(chunk_set, total)
+                || index >= total
+                || chunks.insert(index, chunk.payload).is_some()
+            {
+                return Err(MessageError::InconsistentChunks);
+            }
+        }
+
+        let total = expected.map_or(0, |(_, total)| total);
+        if chunks.len() != total {
+            // Every index is below total and there are fewer chunks, so a gap exists
+            let missing = (0..).find(|index| !chunks.contains_key(index)).unwrap_or(0);
+            return Err(MessageError::MissingChunk(missing));
+        }
+        Ok(chunks.into_values().flatten().collect())
+    }
+
+    /// signs the envelope using Schnorr signatures, sets the signing public key as sender.
+    /// Refuses to replace an existing signature made by a different key, use `sign_force`
+    /// to re-sign deliberately.
+    pub fn sign(&mut self, signing_key: &Scalar) -> Result<(), MessageError> {
+        #[cfg(feature = "tracing")]
+        let started = std::time::Instant::now();
+        let sender_public_key = signing_key * RISTRETTO_BASEPOINT_POINT;
+        if is_weak_point(&sender_public_key) {
+            return Err(MessageError::WeakKey); // A zero key would leave the message unsigned
+        }
+        if self.is_signed()
+            && !public_keys_equal(&self.sender, &sender_public_key.compress().to_bytes())
+        {
+            return Err(MessageError::AlreadySigned);
+        }
+        if signing_domain(self.domain_version).is_none() {
+            return Err(MessageError::UnsupportedDomainVersion(self.domain_version));
+        }
+        self.sign_force(signing_key)?;
+        #[cfg(feature = "tracing")]
+        trace_operation("sign", &self.message_id(), &self.recipient, started);
+        Ok(())
+    }
+
+    /// Signs the envelope, replacing any existing signature and sender
+    pub fn sign_force(&mut self, signing_key: &Scalar) -> Result<(), MessageError> {
+        let sender_public_key = signing_key * RISTRETTO_BASEPOINT_POINT;
+        // The sender is part of the signed bytes, restore it if signing fails
+        let sender = sender_public_key.compress().to_bytes();
+        let previous_sender = std::mem::replace(&mut self.sender, sender);
+        match sign_bytes(&self.signing_digest(), signing_key) {
+            Ok(signature) => {
+                self.signature = signature;
+                Ok(())
+            }
+            Err(_) => {
+                self.sender = previous_sender;
+                Err(MessageError::RngUnavailable)
+            }
+        }
+    }
+
+    /// Cheap structural checks for a received envelope, without any key operations, so
+    /// gateways can drop garbage before spending CPU on `verify` or `decrypt`:
+    /// - the sender is set and a valid point, the recipient is a valid point or broadcast
+    /// - envelopes with a recipient are in the `Encrypted` state
+    /// - the ciphertext is long enough for its mode (nonce, tag and for hybrid mode the
+    ///   ElGamal part)
+    pub fn is_well_formed(&self) -> Result<(), MessageError> {
+        let valid_point = |bytes: &[u8; 32]| {
+            CompressedRistretto(*bytes)
+                .decompress()
+                .is_some_and(|point| !is_weak_point(&point))
+        };
+        if !valid_point(&self.sender) {
+            return Err(MessageError::MalformedEnvelope(
+                "Invalid sender".to_string(),
+            ));
+        }
+        if self.is_broadcast() {
+            return Ok(()); // Broadcast payloads are plaintext, any length goes
+        }
+        if !valid_point(&self.recipient) {
+            return Err(MessageError::MalformedEnvelope(
+                "Invalid recipient".to_string(),
+            ));
+        }
+        if self.state != MessageState::Encrypted {
+            return Err(MessageError::MalformedEnvelope(
+                "Plaintext message addressed to a recipient".to_string(),
+            ));
+        }
+
+        let aes_ciphertexts = match self.mode {
+            EncryptionMode::Hybrid => {
+                vec![
+                    HybridCiphertext::deserialize(&self.payload)
+                        .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?
+                        .aes_ciphertext,
+                ]
+            }
+            EncryptionMode::Symmetric => vec![AESCiphertext::deserialize(&self.payload)
+                .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?],
+            EncryptionMode::Split => {
+                let (headers, body) = self.split_parts()?;
+                vec![headers.aes_ciphertext, body.aes_ciphertext]
+            }
+            EncryptionMode::Timelock => vec![self.timelock_parts()?.1],
+        };
+        if aes_ciphertexts
+            .iter()
+            .any(|aes_ciphertext| aes_ciphertext.ciphertext.len() < AES_TAG_SIZE)
+        {
+            return Err(MessageError::MalformedCiphertext(
+                "Ciphertext shorter than the authentication tag".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns true if `sender` holds a usable public key rather than `UNSET_SENDER`
+    pub fn has_valid_sender(&self) -> bool {
+        self.sender != UNSET_SENDER
+            && CompressedRistretto(self.sender)
+                .decompress()
+                .is_some_and(|sender| !is_weak_point(&sender))
+    }
+
+    /// Returns true if the message carries a signature and a sender, without checking the
+    /// signature. Use `verify` for that.
+    pub fn is_signed(&self) -> bool {
+        self.has_valid_sender() && self.signature != SchnorrSignature::emty_signature()
+    }
+
+    /// Checks the signature against the embedded sender and, if the message carries a
+    /// certificate chain, that the chain links up from the sender with valid issuer
+    /// signatures. Use `verify_with_chain` to also check expiry and the trusted roots.
+    pub fn verify(&self) -> bool {
+        self.recover_and_verify().is_ok()
+            && (self.certificate_chain.is_empty()
+                || verify_chain_signatures(&self.certificate_chain, &self.sender).is_ok())
+    }
+
+    /// Refuses envelopes written in a newer format than this build understands, whose
+    /// fields may mean something else than they do here
+    fn check_format_version(&self) -> Result<(), MessageError> {
+        if self.format_version > FORMAT_VERSION {
+            return Err(MessageError::UnsupportedFormatVersion {
+                found: self.format_version,
+                max_supported: FORMAT_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Verifies the signature against the embedded sender and returns the authenticated
+    /// sender key. Unlike `verify`, tells an unsigned message or a malformed sender apart
+    /// from a bad signature.
+    pub fn recover_and_verify(&self) -> Result<RistrettoPoint, MessageError> {
+        self.check_format_version()?;
+        if signing_domain(self.domain_version).is_none() {
+            return Err(MessageError::UnsupportedDomainVersion(self.domain_version));
+        }
+        // Fresh and encrypted messages carry the placeholders until they are signed
+        if self.sender == UNSET_SENDER || self.signature == SchnorrSignature::emty_signature() {
+            return Err(MessageError::Unsigned);
+        }
+        //Extract the sender's public key (vk)
+        let sender_public_key = CompressedRistretto(self.sender)
+            .decompress()
+            .filter(|sender| !is_weak_point(sender))
+            .ok_or_else(|| MessageError::MalformedEnvelope("Invalid sender".to_string()))?;
+
+        if !verify_bytes(&self.signing_digest(), &self.signature, &sender_public_key) {
+            return Err(MessageError::InvalidSignature);
+        }
+        Ok(sender_public_key)
+    }
+
+    /// Overwrites the payload with zeros, keeping its length, to limit how long a decrypted
+    /// plaintext stays in memory. Headers and attachments are left as they are.
+    pub fn zeroize_payload(&mut self) {
+        self.payload.as_mut_slice().zeroize();
+    }
+
+    /// Sets a header that stays readable on the encrypted envelope, e.g. a destination for
+    /// relays. Set it before encrypting: every encryption mode authenticates the routing
+    /// headers as associated data, so a relay can read them but any change makes decryption
+    /// fail. Don't put anything confidential here.
+    pub fn set_routing_header(&mut self, name: &str, value: &str) {
+        self.routing_headers
+            .insert(name.to_string(), value.to_string());
+    }
+
+    /// The routing headers, readable with or without decrypting
+    pub fn routing_headers(&self) -> &BTreeMap<String, String> {
+        &self.routing_headers
+    }
+
+    /// Associated data binding the routing headers to the ciphertext, empty without any
+    /// so envelopes without routing headers stay compatible
+    fn routing_aad(&self) -> Vec<u8> {
+        if self.routing_headers.is_empty() {
+            return Vec::new();
+        }
+        let mut aad = ROUTING_AAD_DOMAIN.to_vec();
+        aad.extend_from_slice(&(self.routing_headers.len() as u64).to_le_bytes());
+        for (name, value) in &self.routing_headers {
+            for field in [name, value] {
+                aad.extend_from_slice(&(field.len() as u64).to_le_bytes());
+                aad.extend_from_slice(field.as_bytes());
+            }
+        }
+        aad
+    }
+
+    /// AAD of a split-mode body: the routing headers and a hash of the header ciphertext
+    fn split_body_aad(&self, encrypted_headers: &[u8]) -> Vec<u8> {
+        let mut aad = self.routing_aad();
+        aad.extend_from_slice(&Sha256::digest(encrypted_headers));
+        aad
+    }
+
+    /// Adds a named file to the message. Add attachments before encrypting and signing,
+    /// like the payload they are covered by both.
+    pub fn add_attachment(&mut self, name: &str, content: Vec<u8>) {
+        self.attachments.push(Attachment {
+            name: name.to_string(),
+            content,
+        });
+    }
+
+    /// The attachments of the message, empty for an encrypted envelope until it is decrypted
+    pub fn attachments(&self) -> &[Attachment] {
+        &self.attachments
+    }
+
+    /// Checks the signature against each of `candidates` instead of trusting the embedded
+    /// `sender`, and returns the first key it validates under. The sender is part of the
+    /// signed bytes, so each candidate is put in its place before verifying.
+    pub fn verify_against(&self, candidates: &[RistrettoPoint]) -> Option<RistrettoPoint> {
+        let mut envelope = self.clone();
+        candidates.iter().copied().find(|candidate| {
+            envelope.sender = candidate.compress().to_bytes();
+            SchnorrSignature::verify(&self.signature, &envelope.signing_digest(), candidate)
+        })
+    }
+
+    /// Verifies the signature and that the certificate chain authorizes the sender,
+    /// ending at one of `trusted_roots`. `now` is the current Unix time in seconds.
+    pub fn verify_with_chain(
+        &self,
+        trusted_roots: &[RistrettoPoint],
+        now: u64,
+    ) -> Result<(), MessageError> {
+        if !self.verify() {
+            return Err(MessageError::InvalidSignature);
+        }
+        verify_chain(&self.certificate_chain, &self.sender, trusted_roots, now)
+    }
+
+    /// Attaches a token from a timestamping authority for `message_id`. Attach it after
+    /// signing: the token isn't part of the signed bytes, but any change to them changes the ID.
+    pub fn attach_timestamp(&mut self, token: TimestampToken) {
+        self.timestamp = Some(token);
+    }
+
+    /// Verifies the attached timestamp token against the authority's key and this message's
+    /// ID, and returns the attested Unix time
+    pub fn verify_timestamp(&self, authority_pk: &RistrettoPoint) -> Result<u64, MessageError> {
+        let token = self
+            .timestamp
+            .as_ref()
+            .ok_or(MessageError::MissingTimestamp)?;
+        token.verify(authority_pk, &self.message_id())?;
+        Ok(token.time)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Format version: {}", self.format_version)?;
+        writeln!(f, "State: {:?}", self.state)?;
+        writeln!(f, "Mode: {:?}", self.mode)?;
+        writeln!(
+            f,
+            "Payload: {}",
+            to_hex_truncated(&self.payload, DISPLAY_BYTES)
+        )?;
+        writeln!(f, "Recipient: {}", to_hex(&self.recipient))?;
+        writeln!(f, "Sender: {}", to_hex(&self.sender))?;
+        write!(
+            f,
+            "Signature: R={} s={}",
+            to_hex(self.signature.R.compress().as_bytes()),
+            to_hex(self.signature.s.as_bytes())
+        )
+    }
+}
+
+/// Records a finished crypto operation as a `tracing` event. Only the message ID, the
+/// recipient's key fingerprint and the duration are logged, never keys or payload bytes.
+#[cfg(feature = "tracing")]
+fn trace_operation(
+    operation: &'static str,
+    message_id: &[u8; 16],
+    recipient: &[u8; 32],
+    started: std::time::Instant,
+) {
+    let recipient_fingerprint = CompressedRistretto(*recipient)
+        .decompress()
+        .map(|public_key| to_hex(&fingerprint(&public_key)));
+    tracing::debug!(
+        operation,
+        message_id = %to_hex(message_id),
+        recipient_fingerprint = recipient_fingerprint.as_deref().unwrap_or("none"),
+        duration_us = started.elapsed().as_micros() as u64,
+        "Crypto operation finished"
+    );
+}
+
+/// CRC-24 as used by OpenPGP armor (RFC 4880, section 6.1)
+fn crc24(bytes: &[u8]) -> u32 {
+    const CRC24_INIT: u32 = 0xB704CE;
+    const CRC24_POLY: u32 = 0x1864CFB;
+
+    let mut crc = CRC24_INIT;
+    for &byte in bytes {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+/// The only field `peek_version` decodes, every other field is skipped
+#[derive(Deserialize)]
+struct FormatVersionOnly {
+    format_version: u8,
+}
+
+/// Reads the format version of a message framed by `transport::send_message`, e.g. for
+/// routing, without decoding the payload or any keys
+pub fn peek_version(bytes: &[u8]) -> Result<u8, MessageError> {
+    let (format, body) = parse_frame(bytes)?;
+    let header: FormatVersionOnly =
+        match format {
+            SerializationFormat::Json => serde_json::from_slice(body)
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack => rmp_serde::from_slice(body)
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor => ciborium::from_reader(body)
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+            // The version is the first field, the rest of the message is left unread
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode => bincode_options()
+                .allow_trailing_bytes()
+                .deserialize(body)
+                .map_err(|e| MessageError::Serialization(e.to_string()))?,
+        };
+    Ok(header.format_version)
+}
+
+/// Re-encrypts hybrid messages whose AES key is the raw ElGamal scalar under the HKDF-derived
+/// key, in place and in a single pass. Reports success or failure per message, a failed
+/// message is left unchanged. Already migrated messages are skipped. Re-encrypting would
+/// clear the envelope signature, so signed messages fail with `SignatureWouldBeCleared`.
+pub fn migrate_aes_derivation(
+    messages: &mut [Message],
+    private_key: &Scalar,
+) -> Vec<Result<(), MessageError>> {
+    let public_key = private_key * RISTRETTO_BASEPOINT_POINT;
+    messages
+        .iter_mut()
+        .map(|message| {
+            if message.mode != EncryptionMode::Hybrid {
+                return Err(MessageError::WrongMode(message.mode));
+            }
+            let hybrid_ciphertext = HybridCiphertext::deserialize(&message.payload)
+                .map_err(|e| MessageError::MalformedCiphertext(e.to_string()))?;
+            if hybrid_ciphertext.key_derivation() == KeyDerivation::Hkdf {
+                return Ok(());
+            }
+            if message.is_signed() {
+                return Err(MessageError::SignatureWouldBeCleared);
+            }
+
+            let mut migrated = message.clone();
+            migrated.decrypt(private_key)?;
+            migrated.encrypt(&public_key)?;
+            *message = migrated;
+            Ok(())
+        })
+        .collect()
+}
+
+/// Inclusion proof of one message in a batch committed to by `merkle_root`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: usize,            // Position of the message in the batch
+    pub leaf_count: usize,       // Number of messages in the batch
+    pub siblings: Vec<[u8; 32]>, // Sibling hashes from the leaf up to the root
+}
+
+// Leaves and inner nodes are hashed under different prefixes, so an inner node can't be
+// passed off as a message ID
+fn merkle_leaf(message_id: &[u8; 16]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([0x00])
+        .chain_update(message_id)
+        .finalize()
+        .into()
+}
+
+fn merkle_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([0x01])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+/// Hashes pairs of a tree level into the next one. A node without a pair moves up unchanged.
+fn merkle_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    let pairs = level.chunks_exact(2);
+    let unpaired = pairs.remainder().first().copied();
+    pairs
+        .map(|pair| merkle_node(&pair[0], &pair[1]))
+        .chain(unpaired)
+        .collect()
+}
+
+fn merkle_leaves(msgs: &[Message]) -> Vec<[u8; 32]> {
+    msgs.iter()
+        .map(|msg| merkle_leaf(&msg.message_id()))
+        .collect()
+}
+
+/// Merkle root over the `message_id`s of a batch, in order, e.g. for anchoring the batch
+/// in a public log. The root of an empty batch is all zeros.
+pub fn merkle_root(msgs: &[Message]) -> [u8; 32] {
+    let mut level = merkle_leaves(msgs);
+    if level.is_empty() {
+        return [0u8; 32];
+    }
+    while level.len() > 1 {
+        level = merkle_level(&level);
+    }
+    level[0]
+}
+
+/// Proves that `msgs[index]` is part of the batch with root `merkle_root(msgs)`
+pub fn merkle_proof(msgs: &[Message], index: usize) -> Result<MerkleProof, MessageError> {
+    if index >= msgs.len() {
+        return Err(MessageError::IndexOutOfRange {
+            index,
+            len: msgs.len(),
+        });
+    }
+    let mut level = merkle_leaves(msgs);
+    let mut position = index;
+    let mut siblings = Vec::new();
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(position ^ 1) {
+            siblings.push(*sibling);
+        }
+        level = merkle_level(&level);
+        position /= 2;
+    }
+    Ok(MerkleProof {
+        index,
+        leaf_count: msgs.len(),
+        siblings,
+    })
+}
+
+/// Checks that `proof` leads from the message ID to `root`
+pub fn verify_merkle_proof(root: &[u8; 32], proof: &MerkleProof, msg_id: &[u8; 16]) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+    let mut hash = merkle_leaf(msg_id);
+    let mut siblings = proof.siblings.iter();
+    let (mut position, mut width) = (proof.index, proof.leaf_count);
+    while width > 1 {
+        // The last node of an odd-sized level has no sibling
+        if position ^ 1 < width {
+            let Some(sibling) = siblings.next() else {
+                return false;
+            };
+            hash = if position % 2 == 0 {
+                merkle_node(&hash, sibling)
+            } else {
+                merkle_node(sibling, &hash)
+            };
+        }
+        position /= 2;
+        width = width.div_ceil(2);
+    }
+    siblings.next().is_none() && hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aes::AES_NONCE_SIZE;
+    use crate::keys::KeyPair;
+    use curve25519_dalek::ristretto::RistrettoPoint;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_message_creation() {
+        // Create a sample payload and recipient
+        let payload = b"Hello, this is a message!".to_vec();
+        let mut csprng = OsRng;
+        let recipient = RistrettoPoint::random(&mut csprng).compress();
+
+        // Create a new message
+        let format_version: u8 = 1;
+        let message = Message::new(
+            format_version,
+            payload.clone(),
+            recipient,
+            recipient,
+            SchnorrSignature::emty_signature(),
+        );
+
+        // Check if the fields match
+        assert_eq!(message.format_version, format_version);
+        assert_eq!(message.state, MessageState::Plain);
+        assert_eq!(message.payload, payload);
+        assert_eq!(message.recipient, recipient.to_bytes());
+
+        // The hex `Display` form starts with the format version
+        assert!(message.to_string().starts_with("Format version: 1"));
+    }
+
+    #[test]
+    fn test_message_verification_failure() {
+        // Create a sample payload and recipient
+        let payload = b"Hello, this is a message!".to_vec();
+        let mut csprng = OsRng;
+        let recipient = RistrettoPoint::random(&mut csprng).compress();
+        let sender = RistrettoPoint::random(&mut csprng).compress();
+
+        // Generate a random signing key
+        let signing_key = Scalar::random(&mut csprng);
+
+        // Create a new message
+        let mut message = Message::new(
+            1,
+            payload.clone(),
+            sender,
+            recipient,
+            SchnorrSignature::emty_signature(),
+        );
+
+        // Sign the message with the signing key
+        message.sign(&signing_key).unwrap();
+
+        // Tamper with the payload to make the signature invalid
+        message.payload[0] ^= 0xFF;
+
+        // Verify the message, which should fail
+        assert!(
+            !message.verify(),
+            "Verification should fail for tampered message"
+        );
+    }
+
+    #[test]
+    fn test_message_encryption_and_decryption() {
+        // Sample message to encrypt
+        let payload = b"Hello, hybrid encryption!".to_vec();
+
+        // Generate ElGamal keypair
+        let keypair = KeyPair::generate().unwrap();
+
+        // Create a new plaintext message
+        let mut message = Message::new(
+            0,
+            payload.clone(),
+            keypair.public_key.compress(),
+            keypair.public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+
+        // Encrypt the message
+        message
+            .encrypt(&keypair.public_key)
+            .expect("Encryption failed");
+
+        // Ensure the message is marked as encrypted
+        assert_eq!(
+            message.state,
+            MessageState::Encrypted,
+            "Message should be encrypted"
+        );
+
+        // Ensure the payload is not the same as the original (it should be encrypted)
+        assert_ne!(
+            message.payload, payload,
+            "Encrypted payload should not match the original payload"
+        );
+
+        // Decrypt the message
+        message
+            .decrypt(&keypair.private_key)
+            .expect("Decryption failed");
+
+        // Ensure the message is plaintext again after decryption
+        assert_eq!(
+            message.state,
+            MessageState::Plain,
+            "Message should be plain after decryption"
+        );
+
+        // Ensure the decrypted message matches the original payload
+        assert_eq!(
+            message.payload, payload,
+            "Decrypted payload should match the original payload"
+        );
+    }
+
+    #[test]
+    fn test_state_during_encryption_decryption() {
+        // Sample message to encrypt
+        let payload = b"Message with state".to_vec();
+
+        // Generate ElGamal keypair
+        let keypair = KeyPair::generate().unwrap();
+
+        // Create a new message in format version 1
+        let mut message = Message::new(
+            1,
+            payload.clone(),
+            keypair.public_key.compress(),
+            keypair.public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+
+        // Ensure the message starts out as plaintext
+        assert_eq!(
+            message.state,
+            MessageState::Plain,
+            "Initial state should be Plain"
+        );
+
+        // Encrypt the message
+        message
+            .encrypt(&keypair.public_key)
+            .expect("Encryption failed");
+
+        // Ensure the state flips while the format version stays
+        assert_eq!(
+            message.state,
+            MessageState::Encrypted,
+            "State should be Encrypted"
+        );
+        assert_eq!(
+            message.format_version, 1,
+            "Encryption should keep the format version"
+        );
+
+        // Decrypt the message
+        message
+            .decrypt(&keypair.private_key)
+            .expect("Decryption failed");
+
+        // Ensure the state is reset after decryption
+        assert_eq!(
+            message.state,
+            MessageState::Plain,
+            "State should be Plain after decryption"
+        );
+        assert_eq!(message.format_version, 1);
+    }
+
+    #[test]
+    fn test_double_encryption_is_rejected() {
+        let keypair = KeyPair::generate().unwrap();
+        let mut message = Message::new(
+            1,
+            b"Encrypt me once".to_vec(),
+            keypair.public_key.compress(),
+            keypair.public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+        message.encrypt(&keypair.public_key).unwrap();
+        let encrypted_payload = message.payload.clone();
+
+        assert_eq!(
+            message.encrypt(&keypair.public_key),
+            Err(MessageError::AlreadyEncrypted)
+        );
+        assert_eq!(
+            message.encrypt_split(&keypair.public_key),
+            Err(MessageError::AlreadyEncrypted)
+        );
+        assert_eq!(
+            message.encrypt_symmetric(&[0x11; AES_KEY_SIZE]),
+            Err(MessageError::AlreadyEncrypted)
+        );
+        assert_eq!(
+            message.payload, encrypted_payload,
+            "The payload should be untouched"
+        );
+
+        // The decision is made on the state, whatever the format version says
+        message.format_version = 0;
+        assert_eq!(
+            message.encrypt(&keypair.public_key),
+            Err(MessageError::AlreadyEncrypted)
+        );
+    }
+
+    #[test]
+    fn test_signature_verification() {
+        // Sample payload
+        let payload = b"Message to verify signature".to_vec();
+
+        // Generate signing keypair
+        let signing_key = Scalar::random(&mut rand::rngs::OsRng);
+        let sender_public_key =
+            signing_key * curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+
+        // Create a new message
+        let mut message = Message::new(
+            0,
+            payload.clone(),
+            sender_public_key.compress(),
+            sender_public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+
+        // Sign the message
+        message.sign(&signing_key).unwrap();
+
+        // Verify the message
+        assert!(
+            message.verify(),
+            "Message verification failed for correct payload and signature"
+        );
+    }
+
+    #[test]
+    fn test_signature_verification_failure_on_tampered_signature() {
+        // Sample payload
+        let payload = b"Original message".to_vec();
+
+        // Generate signing keypair
+        let signing_key = Scalar::random(&mut rand::rngs::OsRng);
+        let sender_public_key =
+            signing_key * curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+
+        // Create a new message
+        let mut message = Message::new(
+            0,
+            payload.clone(),
+            sender_public_key.compress(),
+            sender_public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+
+        // Sign the message
+        message.sign(&signing_key).unwrap();
+
+        // Tamper with the signature by modifying the scalar 's'
+        message.signature.s += Scalar::random(&mut rand::rngs::OsRng);
+
+        // Verify the tampered message
+        assert!(
+            !message.verify(),
+            "Message verification should fail for tampered signature"
+        );
+    }
+
+    #[test]
+    fn test_symmetric_encryption_roundtrip() {
+        let payload = b"Hello, pre-shared key!".to_vec();
+        let shared_key = [42u8; AES_KEY_SIZE];
+        let recipient = RistrettoPoint::random(&mut OsRng).compress();
+
+        let mut message = Message::new(
+            0,
+            payload.clone(),
+            CompressedRistretto::default(),
+            recipient,
+            SchnorrSignature::emty_signature(),
+        );
+
+        message
+            .encrypt_symmetric(&shared_key)
+            .expect("Symmetric encryption failed");
+        assert_eq!(message.mode, EncryptionMode::Symmetric);
+        assert_ne!(message.payload, payload);
+
+        // The mode survives serialization so the receiver knows which path to take
+        let serialized = serialize_message_to_bytes(&message).unwrap();
+        let mut received = deserialize_message_from_bytes(&serialized).unwrap();
+        assert_eq!(received.mode, EncryptionMode::Symmetric);
+        assert!(
+            received.decrypt(&Scalar::ONE).is_err(),
+            "Hybrid decryption should refuse a symmetric-mode message"
+        );
+
+        received
+            .decrypt_symmetric(&shared_key)
+            .expect("Symmetric decryption failed");
+        assert_eq!(received.payload, payload);
+        assert_eq!(received.state, MessageState::Plain);
+        assert_eq!(received.mode, EncryptionMode::Hybrid);
+    }
+
+    #[test]
+    fn test_symmetric_decryption_with_wrong_key_fails() {
+        let mut message = Message::new(
+            0,
+            b"Hello, pre-shared key!".to_vec(),
+            CompressedRistretto::default(),
+            RistrettoPoint::random(&mut OsRng).compress(),
+            SchnorrSignature::emty_signature(),
+        );
+
+        message
+            .encrypt_symmetric(&[1u8; AES_KEY_SIZE])
+            .expect("Symmetric encryption failed");
+
+        assert!(
+            message.decrypt_symmetric(&[2u8; AES_KEY_SIZE]).is_err(),
+            "Decryption with a different shared key should fail"
+        );
+    }
+
+    fn token_test_message() -> Message {
+        Message::new(
+            0,
+            b"Fixed size token payload".to_vec(),
+            CompressedRistretto::default(),
+            CompressedRistretto::default(),
+            SchnorrSignature::emty_signature(),
+        )
+    }
+
+    #[test]
+    fn test_token_roundtrip() {
+        let message = token_test_message();
+
+        let token = message.to_token().expect("Failed to create token");
+        let parsed = Message::from_token(&token).expect("Failed to parse token");
+
+        assert_eq!(parsed.payload, message.payload);
+        assert_eq!(parsed.signature, message.signature);
+    }
+
+    #[test]
+    fn test_fixed_size_token_smaller_than_n() {
+        let message = token_test_message();
+        let n = 1024;
+
+        let token = message.to_fixed_size_token(n).expect("Message should fit");
+        assert_eq!(
+            BASE64_STANDARD.decode(&token).unwrap().len(),
+            n,
+            "Padded envelope should be exactly n bytes"
+        );
+
+        // A longer payload still produces a token of the same length
+        let mut longer = token_test_message();
+        longer.payload = vec![0xAB; 200];
+        let longer_token = longer.to_fixed_size_token(n).expect("Message should fit");
+        assert_eq!(token.len(), longer_token.len());
+
+        let parsed = Message::from_fixed_size_token(&token).expect("Failed to parse token");
+        assert_eq!(parsed.payload, message.payload);
+        assert_eq!(parsed.format_version, message.format_version);
+    }
+
+    #[test]
+    fn test_fixed_size_token_equal_to_n() {
+        let message = token_test_message();
+        let size = serialize_message_to_bytes(&message).unwrap().len();
+
+        // The marker byte needs one spare byte, so an envelope of exactly n bytes doesn't fit
+        assert_eq!(
+            message.to_fixed_size_token(size),
+            Err(MessageError::EnvelopeTooLarge { size, limit: size })
+        );
+
+        // With a single spare byte the padding is just the marker
+        let token = message
+            .to_fixed_size_token(size + 1)
+            .expect("Message should fit");
+        let parsed = Message::from_fixed_size_token(&token).expect("Failed to parse token");
+        assert_eq!(parsed.payload, message.payload);
+    }
+
+    #[test]
+    fn test_fixed_size_token_larger_than_n() {
+        let message = token_test_message();
+
+        assert!(matches!(
+            message.to_fixed_size_token(16),
+            Err(MessageError::EnvelopeTooLarge { limit: 16, .. })
+        ));
+    }
+
+    #[test]
+    fn test_fixed_size_token_rejects_missing_padding() {
+        let unpadded = token_test_message().to_token().unwrap();
+
+        assert_eq!(
+            Message::from_fixed_size_token(&unpadded).map(|m| m.format_version),
+            Err(MessageError::InvalidPadding)
+        );
+    }
+
+    /// Builds a message signed by a short-term key certified by `root`
+    fn delegated_message(root: &KeyPair, not_after: u64) -> Message {
+        let short_term = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.certificate_chain =
+            vec![Certificate::issue(&root.private_key, &short_term.public_key, not_after).unwrap()];
+        message.sign(&short_term.private_key).unwrap();
+        message
+    }
+
+    #[test]
+    fn test_certificate_chain_verifies() {
+        let root = KeyPair::generate().unwrap();
+        let message = delegated_message(&root, 2_000);
+
+        assert_eq!(message.verify_with_chain(&[root.public_key], 1_000), Ok(()));
+
+        // The chain survives serialization
+        let parsed = Message::from_token(&message.to_token().unwrap()).unwrap();
+        assert_eq!(parsed.verify_with_chain(&[root.public_key], 1_000), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_rejects_a_bogus_chain() {
+        let root = KeyPair::generate().unwrap();
+        let mut message = delegated_message(&root, 2_000);
+        assert!(message.verify());
+
+        // The chain isn't part of the signed bytes, so only the chain check catches these
+        let mut forged = message.clone();
+        forged.certificate_chain[0].not_after = 3_000;
+        assert!(!forged.verify());
+
+        message.certificate_chain[0].subject = root.public_key.compress().to_bytes();
+        assert!(!message.verify());
+    }
+
+    #[test]
+    fn test_certificate_chain_expired() {
+        let root = KeyPair::generate().unwrap();
+        let message = delegated_message(&root, 2_000);
+
+        assert_eq!(
+            message.verify_with_chain(&[root.public_key], 2_001),
+            Err(MessageError::CertificateExpired)
+        );
+    }
+
+    #[test]
+    fn test_certificate_chain_untrusted_root() {
+        let root = KeyPair::generate().unwrap();
+        let other_root = KeyPair::generate().unwrap();
+        let message = delegated_message(&root, 2_000);
+
+        assert_eq!(
+            message.verify_with_chain(&[other_root.public_key], 1_000),
+            Err(MessageError::UntrustedRoot)
+        );
+    }
+
+    #[test]
+    fn test_timestamp_verifies_against_authority() {
+        let sender = KeyPair::generate().unwrap();
+        let authority = KeyPair::generate().unwrap();
+        let mut message = Message::new_broadcast(b"Timestamped announcement".to_vec());
+        message.sign(&sender.private_key).unwrap();
+        assert_eq!(
+            message.verify_timestamp(&authority.public_key),
+            Err(MessageError::MissingTimestamp)
+        );
+
+        let token =
+            TimestampToken::issue(&authority.private_key, &message.message_id(), 1_000).unwrap();
+        message.attach_timestamp(token.clone());
+        assert_eq!(message.verify_timestamp(&authority.public_key), Ok(1_000));
+        assert!(
+            message.verify(),
+            "The token shouldn't affect the sender's signature"
+        );
+        assert_eq!(
+            message.verify_timestamp(&sender.public_key),
+            Err(MessageError::InvalidTimestamp)
+        );
+
+        let mut other = Message::new_broadcast(b"Another announcement".to_vec());
+        other.sign(&sender.private_key).unwrap();
+        other.attach_timestamp(token);
+        assert_eq!(
+            other.verify_timestamp(&authority.public_key),
+            Err(MessageError::InvalidTimestamp)
+        );
+    }
+
+    #[test]
+    fn test_crc24_known_value() {
+        // Check value of CRC-24/OPENPGP
+        assert_eq!(crc24(b"123456789"), 0x21CF02);
+    }
+
+    #[test]
+    fn test_armored_roundtrip() {
+        let mut message = token_test_message();
+        message.payload = vec![0x5A; 300]; // Long enough to span several armor lines
+
+        let armored = message.to_armored().expect("Failed to armor message");
+        assert!(armored.starts_with("-----BEGIN SECURE-CHANNEL MESSAGE-----\n"));
+        assert!(armored.ends_with("-----END SECURE-CHANNEL MESSAGE-----\n"));
+        assert!(armored.lines().all(|line| line.len() <= 64));
+
+        let parsed = Message::from_armored(&armored).expect("Failed to parse armor");
+        assert_eq!(parsed.payload, message.payload);
+        assert_eq!(parsed.signature, message.signature);
+    }
+
+    #[test]
+    fn test_armored_corrupted_checksum_rejected() {
+        let armored = token_test_message().to_armored().unwrap();
+
+        let checksum_line = armored.lines().find(|line| line.starts_with('=')).unwrap();
+        let corrupted_line = if checksum_line == "=AAAA" {
+            "=AAAB"
+        } else {
+            "=AAAA"
+        };
+        let corrupted = armored.replace(checksum_line, corrupted_line);
+
+        assert_eq!(
+            Message::from_armored(&corrupted).map(|m| m.format_version),
+            Err(MessageError::ChecksumMismatch)
+        );
+
+        let truncated = armored.replace("-----END SECURE-CHANNEL MESSAGE-----", "");
+        assert_eq!(
+            Message::from_armored(&truncated).map(|m| m.format_version),
+            Err(MessageError::InvalidArmor)
+        );
+    }
+
+    #[test]
+    fn test_broadcast_message_verifies() {
+        let signing_key = Scalar::random(&mut OsRng);
+        let mut message = Message::new_broadcast(b"Public announcement".to_vec());
+        assert!(message.is_broadcast());
+
+        message.sign(&signing_key).unwrap();
+        assert!(
+            message.verify(),
+            "Broadcast message should verify without a recipient"
+        );
+    }
+
+    #[test]
+    fn test_broadcast_message_cannot_be_encrypted() {
+        let keypair = KeyPair::generate().unwrap();
+        let mut message = Message::new_broadcast(b"Public announcement".to_vec());
+
+        assert_eq!(
+            message.encrypt(&keypair.public_key),
+            Err(MessageError::BroadcastNotEncryptable)
+        );
+        assert_eq!(
+            message.encrypt_symmetric(&[1u8; AES_KEY_SIZE]),
+            Err(MessageError::BroadcastNotEncryptable)
+        );
+        assert_eq!(
+            message.payload, b"Public announcement",
+            "Payload should be untouched"
+        );
+    }
+
+    #[test]
+    fn test_broadcast_message_serializes_empty_recipient() {
+        let signing_key = Scalar::random(&mut OsRng);
+        let mut message = Message::new_broadcast(b"Public announcement".to_vec());
+        message.sign(&signing_key).unwrap();
+
+        let json = String::from_utf8(serialize_message_to_bytes(&message).unwrap()).unwrap();
+        assert!(
+            json.contains("\"recipient\":\"\""),
+            "Recipient should be empty: {}",
+            json
+        );
+
+        let parsed = deserialize_message_from_bytes(json.as_bytes()).unwrap();
+        assert!(parsed.is_broadcast());
+        assert!(parsed.verify());
+    }
+
+    #[test]
+    fn test_message_id_stable_across_serialization() {
+        let signing_key = Scalar::random(&mut OsRng);
+        let mut message = token_test_message();
+        message.sign(&signing_key).unwrap();
+
+        let parsed = Message::from_token(&message.to_token().unwrap()).unwrap();
+        assert_eq!(parsed.message_id(), message.message_id());
+
+        // Signatures are randomized, but re-signing doesn't change the ID
+        let mut resigned = message.clone();
+        resigned.sign(&signing_key).unwrap();
+        assert_ne!(resigned.signature, message.signature);
+        assert_eq!(resigned.message_id(), message.message_id());
+    }
+
+    #[test]
+    fn test_message_id_differs_for_different_payloads() {
+        let message = token_test_message();
+        let mut other = token_test_message();
+        other.payload = b"Another payload".to_vec();
+
+        assert_ne!(message.message_id(), other.message_id());
+    }
+
+    /// Serializes a message to a JSON object with an extra field a newer version might add
+    fn json_with_extra_field(message: &Message) -> String {
+        let mut value = serde_json::to_value(message).unwrap();
+        value
+            .as_object_mut()
+            .unwrap()
+            .insert("future_field".to_string(), serde_json::json!("surprise"));
+        value.to_string()
+    }
+
+    #[test]
+    fn test_unknown_field_rejected_in_strict_mode() {
+        let json = json_with_extra_field(&token_test_message());
+
+        assert!(matches!(
+            Message::from_json(&json),
+            Err(MessageError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_unknown_field_accepted_in_lenient_mode() {
+        let message = token_test_message();
+        let json = json_with_extra_field(&message);
+
+        let parsed = Message::from_json_lenient(&json).expect("Lenient parsing should succeed");
+        assert_eq!(parsed.payload, message.payload);
+        assert!(Message::from_json(&serde_json::to_string(&message).unwrap()).is_ok());
+    }
+
+    /// signed_encrypted_message.json as the original `main` wrote it, before `version` was
+    /// split into two fields. Running `main` overwrites the file, so the test keeps a copy.
+    const LEGACY_ENVELOPE: &str = r#"{
+  "version": 1,
+  "payload": "6Nb4Raunq6JsFiFa/xDxBmA89iswLbk09f2d2xtmhDOQz98TM1tLTS+x68WjNJ+KMwTKOxIMijWyRNPNcxcXAtwhatncjv5ppnhmUepbZIRLAQ2aEmDyDsaTMdYpQAaewkpQTxvzftL59vb4t1w/NdBTanBNKV4ApzNwWcQ/vveWHYKjUesaapbZyQ3Zq3HCLKIapVxm66jXBPz/yH+qWoF6LFJD7TzEGcsogods/VizN/5lfYwFz7AhtqpdNGzhGqhMSaSrXfdbM4h39Gd/fssrYeT+hxKpgpPuFpllv2DBeKwi/pj0a7CCvh3NFjobOCF8uNb1TVSmJ+XyCZVI8xjcs4XIdQawLLdcaGQMfSapnC8bYAWBZdYMdWS4wyfbyQhj/eYdVco7yWu7WUKJzM3Y442HxKzmbrKCarsJoxkjnzLehw/HMXmkW0z0G42RO01YjyFrUIdlOzke8F4Uy24HG8MbCJLzYf1Phzy0uzza",
+  "recipient": "HIn1HpHqWUR1bzTRmCjdpbqTB5RUFu7eERX0yi/rcR8=",
+  "sender": "nFrRkU8AeesMMIHidGuOr4x6LAoUnZ8lsa4ywEjI9Qk=",
+  "signature": {
+    "R": "sCGyT12Kp0oWiw7aSXR5ai4hBAIGUWc6RHVkG29oGwc=",
+    "s": "qE/xRyhqFwg3RWq5Ie5NVvbli3WcEbTLDOMULz2vHQk="
+  }
+}"#;
+
+    #[test]
+    fn test_legacy_version_envelope_is_upgraded() {
+        let message = Message::from_json(LEGACY_ENVELOPE).expect("Legacy envelope should parse");
+        assert_eq!(message.format_version, 0);
+        assert_eq!(message.state, MessageState::Encrypted);
+        assert!(message.has_valid_sender());
+        assert!(HybridCiphertext::deserialize(&message.payload).is_ok());
+
+        // Legacy plaintext, e.g. the inner message of a legacy envelope
+        let mut value = serde_json::to_value(token_test_message()).unwrap();
+        let fields = value.as_object_mut().unwrap();
+        fields.remove("format_version");
+        fields.remove("state");
+        fields.insert("version".to_string(), serde_json::json!(0));
+        let plain = deserialize_message_from_bytes(value.to_string().as_bytes()).unwrap();
+        assert_eq!(plain.state, MessageState::Plain);
+        assert_eq!(plain.payload, token_test_message().payload);
+
+        // A current envelope can't smuggle in the legacy field
+        let mut mixed = serde_json::to_value(token_test_message()).unwrap();
+        mixed["version"] = serde_json::json!(1);
+        assert!(matches!(
+            Message::from_json(&mixed.to_string()),
+            Err(MessageError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_lenient_mode_knows_every_field() {
+        let root = KeyPair::generate().unwrap();
+        let message = delegated_message(&root, 2_000); // Populates the optional fields too
+
+        let value = serde_json::to_value(&message).unwrap();
+        for name in value.as_object().unwrap().keys() {
+            assert!(
+                MESSAGE_FIELDS.contains(&name.as_str()),
+                "MESSAGE_FIELDS is missing {}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_reply_is_addressed_to_original_sender() {
+        let alice = KeyPair::generate().unwrap();
+        let bob = KeyPair::generate().unwrap();
+
+        let mut original = token_test_message();
+        original.recipient = bob.public_key.compress().to_bytes();
+        original
+            .encrypt(&bob.public_key)
+            .expect("Encryption failed");
+        original.sign(&alice.private_key).unwrap();
+        let original_id = original.message_id();
+
+        let mut reply = original
+            .reply(b"Reply payload".to_vec(), &bob.private_key)
+            .expect("Reply failed");
+        assert_eq!(reply.recipient, alice.public_key.compress().to_bytes());
+        assert!(reply.verify(), "Reply should be signed by Bob");
+        assert!(
+            reply.headers.is_empty(),
+            "Headers should only be inside the ciphertext"
+        );
+
+        reply
+            .decrypt(&alice.private_key)
+            .expect("Decryption failed");
+        assert_eq!(reply.payload, b"Reply payload");
+        assert_eq!(
+            reply.headers.get(IN_REPLY_TO_HEADER),
+            Some(&to_hex(&original_id))
+        );
+    }
+
+    #[test]
+    fn test_reply_to_unverified_message_fails() {
+        let bob = KeyPair::generate().unwrap();
+        let mut original = token_test_message();
+        original
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+        original.payload.push(0x00);
+
+        assert!(matches!(
+            original.reply(b"Reply".to_vec(), &bob.private_key),
+            Err(MessageError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let payload: Vec<u8> = (0..100).collect();
+
+        let mut chunks =
+            Message::split(&payload, 30, &sender.private_key, &recipient.public_key).unwrap();
+        assert_eq!(chunks.len(), 4);
+        assert!(chunks.iter().all(|chunk| chunk.verify()));
+
+        // Arrival order doesn't matter
+        chunks.swap(0, 3);
+        let reassembled = Message::reassemble(&chunks, &recipient.private_key).unwrap();
+        assert_eq!(reassembled, payload);
+    }
+
+    #[test]
+    fn test_reassemble_missing_chunk_fails() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let payload: Vec<u8> = (0..100).collect();
+
+        let mut chunks =
+            Message::split(&payload, 30, &sender.private_key, &recipient.public_key).unwrap();
+        chunks.remove(2);
+        assert_eq!(
+            Message::reassemble(&chunks, &recipient.private_key),
+            Err(MessageError::MissingChunk(2))
+        );
+
+        // A chunk from another split of the same payload doesn't fill the gap
+        let other =
+            Message::split(&payload, 30, &sender.private_key, &recipient.public_key).unwrap();
+        chunks.push(other[2].clone());
+        assert_eq!(
+            Message::reassemble(&chunks, &recipient.private_key),
+            Err(MessageError::InconsistentChunks)
+        );
+    }
+
+    #[test]
+    fn test_split_and_reassemble_reject_bad_sizes() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        assert_eq!(
+            Message::split(b"payload", 0, &sender.private_key, &recipient.public_key).err(),
+            Some(MessageError::InvalidChunkSize)
+        );
+
+        // A signed chunk claiming a huge or zero count is refused without allocating for it
+        for total in [usize::MAX, 0] {
+            let mut chunk = Message::new(
+                0,
+                b"chunk".to_vec(),
+                CompressedRistretto(UNSET_SENDER),
+                recipient.public_key.compress(),
+                SchnorrSignature::emty_signature(),
+            );
+            chunk
+                .headers
+                .insert(CHUNK_INDEX_HEADER.to_string(), "0".to_string());
+            chunk
+                .headers
+                .insert(TOTAL_CHUNKS_HEADER.to_string(), total.to_string());
+            chunk
+                .headers
+                .insert(CHUNK_SET_HEADER.to_string(), "set".to_string());
+            chunk.encrypt(&recipient.public_key).unwrap();
+            chunk.sign(&sender.private_key).unwrap();
+
+            let expected = if total == 0 {
+                MessageError::InconsistentChunks
+            } else {
+                MessageError::MissingChunk(1)
+            };
+            assert_eq!(
+                Message::reassemble(&[chunk], &recipient.private_key),
+                Err(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_display_is_hex_and_truncated() {
+        let payload = vec![0xab; 1_000];
+        let message = Message::new(
+            0,
+            payload.clone(),
+            CompressedRistretto::default(),
+            RistrettoPoint::random(&mut OsRng).compress(),
+            SchnorrSignature::emty_signature(),
+        );
+
+        let output = message.to_string();
+        assert!(!output.is_empty());
+        assert!(output.contains(&format!("Payload: {}...", "ab".repeat(DISPLAY_BYTES))));
+        assert!(output.contains("(1000 bytes)"));
+        assert!(
+            !output.contains(&to_hex(&payload)),
+            "Long payloads should be truncated"
+        );
+        assert!(
+            !output.contains('['),
+            "Bytes should not be printed as arrays"
+        );
+
+        // The raw bytes are still available through Debug
+        assert!(format!("{:?}", message).contains("171, 171"));
+    }
+
+    #[test]
+    fn test_verify_against_candidates() {
+        let candidates: Vec<KeyPair> = (0..4).map(|_| KeyPair::generate().unwrap()).collect();
+        let public_keys: Vec<RistrettoPoint> = candidates
+            .iter()
+            .map(|keypair| keypair.public_key)
+            .collect();
+
+        let mut message = token_test_message();
+        message.sign(&candidates[2].private_key).unwrap();
+        // The embedded sender is ignored
+        message.sender = candidates[0].public_key.compress().to_bytes();
+
+        assert_eq!(message.verify_against(&public_keys), Some(public_keys[2]));
+        assert_eq!(message.verify_against(&public_keys[..2]), None);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_roundtrip() {
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.encrypt(&recipient.public_key).unwrap();
+        message
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+
+        let packed = message
+            .to_msgpack()
+            .expect("MessagePack serialization failed");
+        let json = serialize_message_to_bytes(&message).unwrap();
+        assert!(
+            packed.len() < json.len(),
+            "MessagePack should be smaller than JSON"
+        );
+
+        let unpacked = Message::from_msgpack(&packed).expect("MessagePack parsing failed");
+        assert_eq!(unpacked.payload, message.payload);
+        assert_eq!(unpacked.signature, message.signature);
+        assert!(unpacked.verify());
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack_broadcast_roundtrip() {
+        let mut message = Message::new_broadcast(b"Announcement".to_vec());
+        message
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+
+        let unpacked = Message::from_msgpack(&message.to_msgpack().unwrap()).unwrap();
+        assert!(unpacked.is_broadcast());
+        assert!(unpacked.verify());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_roundtrip() {
+        let root = KeyPair::generate().unwrap();
+        let signer = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        // Fills the optional fields the self-describing formats leave out when empty
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.set_routing_header("queue", "inbound");
+        message.add_attachment("notes.txt", b"Attached".to_vec());
+        message.encrypt(&recipient.public_key).unwrap();
+        message.producer = Some("secure-channel test".to_string());
+        message.certificate_chain =
+            vec![Certificate::issue(&root.private_key, &signer.public_key, 2_000).unwrap()];
+        message.sign(&signer.private_key).unwrap();
+
+        let encoded = message.to_bincode().expect("bincode serialization failed");
+        assert!(encoded.len() < serialize_message_to_bytes(&message).unwrap().len());
+        let mut decoded = Message::from_bincode(&encoded).expect("bincode parsing failed");
+        assert_eq!(decoded.to_bincode().unwrap(), encoded);
+        assert_eq!(decoded.routing_headers(), message.routing_headers());
+        assert!(decoded.verify());
+
+        decoded.decrypt(&recipient.private_key).unwrap();
+        assert_eq!(decoded.payload, token_test_message().payload);
+        assert_eq!(decoded.attachments()[0].content, b"Attached");
+
+        // bincode can't tell where a message ends, so trailing bytes are refused
+        let mut padded = encoded;
+        padded.push(0);
+        assert!(Message::from_bincode(&padded).is_err());
+    }
+
+    #[test]
+    fn test_resigning_with_different_key_requires_force() {
+        let first = KeyPair::generate().unwrap();
+        let second = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+
+        message.sign(&first.private_key).unwrap();
+        // Re-signing with the same key is harmless
+        message.sign(&first.private_key).unwrap();
+
+        assert_eq!(
+            message.sign(&second.private_key),
+            Err(MessageError::AlreadySigned)
+        );
+        assert!(message.verify(), "The original signature should be kept");
+
+        message.sign_force(&second.private_key).unwrap();
+        assert_eq!(message.sender, second.public_key.compress().to_bytes());
+        assert!(message.verify());
+    }
+
+    #[test]
+    fn test_strict_encryption_of_signed_message_requires_force() {
+        let signer = KeyPair::generate().unwrap();
+        let bob = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = bob.public_key.compress().to_bytes();
+
+        // Unsigned messages encrypt as usual
+        message
+            .clone()
+            .encrypt_strict(&bob.public_key, false)
+            .unwrap();
+
+        message.sign(&signer.private_key).unwrap();
+        assert_eq!(
+            message.encrypt_strict(&bob.public_key, false),
+            Err(MessageError::SignatureWouldBeCleared)
+        );
+        assert_eq!(message.state, MessageState::Plain);
+        assert!(
+            message.verify(),
+            "A refused encryption must keep the signature"
+        );
+
+        message.encrypt_strict(&bob.public_key, true).unwrap();
+        assert_eq!(message.state, MessageState::Encrypted);
+        assert!(!message.is_signed());
+        // The inner signature comes back with the decrypted message
+        message.decrypt(&bob.private_key).unwrap();
+        assert!(message.verify());
+    }
+
+    #[test]
+    fn test_attachments_roundtrip_and_are_signed() {
+        let recipient = KeyPair::generate().unwrap();
+        let signing_key = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.add_attachment("notes.txt", b"Meeting at noon".to_vec());
+        message.add_attachment("logo.png", vec![0x89, 0x50, 0x4e, 0x47]);
+        let expected = message.attachments().to_vec();
+
+        // Tampering with a signed attachment breaks the signature
+        let mut signed = message.clone();
+        signed.sign(&signing_key.private_key).unwrap();
+        assert!(signed.verify());
+        signed.attachments[1].content[0] ^= 0x01;
+        assert!(
+            !signed.verify(),
+            "Tampered attachment should fail verification"
+        );
+
+        message.encrypt(&recipient.public_key).unwrap();
+        assert!(
+            message.attachments().is_empty(),
+            "Attachments should be encrypted"
+        );
+        message.sign(&signing_key.private_key).unwrap();
+        assert!(message.verify());
+
+        message.decrypt(&recipient.private_key).unwrap();
+        assert_eq!(message.attachments(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_external_signature_over_digest() {
+        let signer = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.sender = signer.public_key.compress().to_bytes();
+        let digest = message.signing_digest();
+
+        // What an external signer does with the digest alone
+        let r = Scalar::random(&mut OsRng);
+        let commitment = r * RISTRETTO_BASEPOINT_POINT;
+        let mut hasher = Sha512::new();
+        hasher.update(commitment.compress().as_bytes());
+        hasher.update(digest);
+        let e = Scalar::from_hash(hasher);
+        let s = r + e * signer.private_key;
+
+        message.set_signature(SchnorrSignature { R: commitment, s });
+        assert!(
+            message.verify(),
+            "Externally produced signature should verify"
+        );
+
+        message.payload.push(0x00);
+        assert!(!message.verify());
+    }
+
+    #[test]
+    fn test_encrypt_rejects_identity_key() {
+        let mut message = token_test_message();
+        message.recipient = KeyPair::generate()
+            .unwrap()
+            .public_key
+            .compress()
+            .to_bytes();
+        assert_eq!(
+            message.encrypt(&RistrettoPoint::default()),
+            Err(MessageError::WeakKey)
+        );
+        assert!(HybridCiphertext::encrypt(b"Hello", &RistrettoPoint::default()).is_err());
+    }
+
+    #[test]
+    fn test_stream_verifier_matches_one_shot_verify() {
+        use crate::schnorr::StreamVerifier;
+
+        let signer = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.payload = (0..1_000_000u32).map(|i| i as u8).collect();
+        message.sign(&signer.private_key).unwrap();
+        assert!(message.verify());
+
+        let stream_verify = |message: &Message| {
+            let mut verifier = StreamVerifier::new();
+            for chunk in message.signed_bytes().chunks(4096) {
+                verifier.update(chunk);
+            }
+            verifier.finalize(&message.signature, &signer.public_key)
+        };
+        assert!(stream_verify(&message));
+
+        // Signing the same bytes from a reader gives a signature the message accepts
+        let signature = SchnorrSignature::sign_reader(
+            std::io::Cursor::new(message.signed_bytes()),
+            &signer.private_key,
+        )
+        .unwrap();
+        message.set_signature(signature);
+        assert!(message.verify());
+
+        message.payload[500_000] ^= 0x01;
+        assert!(!message.verify());
+        assert!(!stream_verify(&message));
+    }
+
+    #[test]
+    fn test_reencryptions_are_logged_in_order() {
+        let bob = KeyPair::generate().unwrap();
+        let carol = KeyPair::generate().unwrap();
+        let dave = KeyPair::generate().unwrap();
+        let signer = KeyPair::generate().unwrap();
+
+        let mut message = token_test_message();
+        let payload = message.payload.clone();
+        message.recipient = bob.public_key.compress().to_bytes();
+        message.encrypt(&bob.public_key).unwrap();
+        assert!(message.rotation_log.is_empty());
+
+        message
+            .reencrypt(&bob.private_key, &carol.public_key)
+            .unwrap();
+        message
+            .reencrypt_key(&carol.private_key, &dave.public_key)
+            .unwrap();
+        assert_eq!(
+            message.rotation_log,
+            vec![
+                fingerprint(&carol.public_key),
+                fingerprint(&dave.public_key)
+            ]
+        );
+
+        // The log is signed and survives serialization
+        message.sign(&signer.private_key).unwrap();
+        let mut received = Message::from_json(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert!(received.verify());
+        let mut tampered = received.clone();
+        tampered.rotation_log.pop();
+        assert!(
+            !tampered.verify(),
+            "Truncating the log should break the signature"
+        );
+
+        received.decrypt(&dave.private_key).unwrap();
+        assert_eq!(received.payload, payload);
+    }
+
+    #[test]
+    fn test_debug_crypto_params_match_ciphertext() {
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.encrypt(&recipient.public_key).unwrap();
+
+        // Tagged hybrid layout: 3-byte header || c1 || c2 || nonce || AES ciphertext
+        let params = message.debug_crypto_params().unwrap();
+        assert_eq!(
+            params.c1.as_ref().map(|c1| &c1[..]),
+            Some(&message.payload[3..35])
+        );
+        assert_eq!(params.aes_nonce, message.payload[67..67 + AES_NONCE_SIZE]);
+
+        let mut symmetric = token_test_message();
+        symmetric.recipient = recipient.public_key.compress().to_bytes();
+        symmetric.encrypt_symmetric(&[7u8; AES_KEY_SIZE]).unwrap();
+        let params = symmetric.debug_crypto_params().unwrap();
+        assert_eq!(params.aes_nonce, symmetric.payload[..AES_NONCE_SIZE]);
+        assert_eq!(params.c1, None);
+    }
+
+    #[test]
+    fn test_signed_and_sender_predicates() {
+        let signer = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        assert!(!message.has_valid_sender(), "A fresh message has no sender");
+        assert!(!message.is_signed());
+        assert!(
+            !message.verify(),
+            "The placeholder signature must not verify"
+        );
+        assert_eq!(message.recover_and_verify(), Err(MessageError::Unsigned));
+
+        message.sign(&signer.private_key).unwrap();
+        assert!(message.has_valid_sender());
+        assert!(message.is_signed());
+
+        // Encryption clears the sender and signature again
+        message.encrypt(&recipient.public_key).unwrap();
+        assert_eq!(message.sender, UNSET_SENDER);
+        assert!(!message.has_valid_sender());
+        assert!(!message.is_signed());
+        assert!(
+            !message.verify(),
+            "An encrypted but unsigned message must not verify"
+        );
+        assert_eq!(message.recover_and_verify(), Err(MessageError::Unsigned));
+
+        // A sender without a signature is still unsigned
+        message.sender = signer.public_key.compress().to_bytes();
+        assert_eq!(message.recover_and_verify(), Err(MessageError::Unsigned));
+
+        assert_eq!(message.sign(&Scalar::ZERO), Err(MessageError::WeakKey));
+    }
+
+    /// A named in-place malformation of one envelope field
+    type Mutation = (&'static str, fn(&mut Message));
+
+    #[test]
+    fn test_is_well_formed_catches_malformations() {
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.encrypt(&recipient.public_key).unwrap();
+        assert!(
+            matches!(
+                message.is_well_formed(),
+                Err(MessageError::MalformedEnvelope(_))
+            ),
+            "An unsigned envelope has no sender"
+        );
+        message
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+        assert_eq!(message.is_well_formed(), Ok(()));
+
+        let mutations: Vec<Mutation> = vec![
+            ("invalid sender point", |m| m.sender = [0xff; 32]),
+            ("invalid recipient point", |m| m.recipient = [0xff; 32]),
+            ("plain state", |m| m.state = MessageState::Plain),
+            ("too-short ciphertext", |m| m.payload.truncate(80)),
+            ("payload shorter than the ElGamal part", |m| {
+                m.payload.truncate(40)
+            }),
+            ("bad symmetric length", |m| {
+                m.mode = EncryptionMode::Symmetric;
+                m.payload.truncate(AES_KEY_SIZE / 4);
+            }),
+        ];
+        for (malformation, mutate) in mutations {
+            let mut malformed = message.clone();
+            mutate(&mut malformed);
+            assert!(
+                malformed.is_well_formed().is_err(),
+                "{} should be caught",
+                malformation
+            );
+        }
+
+        let mut broadcast = Message::new_broadcast(Vec::new());
+        broadcast
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+        assert_eq!(broadcast.is_well_formed(), Ok(()));
+    }
+
+    #[test]
+    fn test_recover_and_verify_returns_signer() {
+        let sender = KeyPair::generate().unwrap();
+        let mut message = Message::new_broadcast(b"Who sent this?".to_vec());
+        assert_eq!(message.recover_and_verify(), Err(MessageError::Unsigned));
+
+        message.sign(&sender.private_key).unwrap();
+        assert_eq!(message.recover_and_verify(), Ok(sender.public_key));
+
+        let mut tampered = message.clone();
+        tampered.sender = KeyPair::generate()
+            .unwrap()
+            .public_key
+            .compress()
+            .to_bytes();
+        assert_eq!(
+            tampered.recover_and_verify(),
+            Err(MessageError::InvalidSignature)
+        );
+
+        let mut malformed = message.clone();
+        malformed.sender = [0xff; 32];
+        assert!(matches!(
+            malformed.recover_and_verify(),
+            Err(MessageError::MalformedEnvelope(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_file() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("message.json");
+        let path = path.to_str().unwrap();
+
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.encrypt(&recipient.public_key).unwrap();
+        message.sign(&sender.private_key).unwrap();
+        message.to_file(path).unwrap();
+
+        assert_eq!(Message::verify_file(path, None), Ok(()));
+        assert_eq!(Message::verify_file(path, Some(sender.public_key)), Ok(()));
+        assert_eq!(
+            Message::verify_file(path, Some(recipient.public_key)),
+            Err(MessageError::SenderMismatch)
+        );
+
+        message.format_version += 1;
+        message.to_file(path).unwrap();
+        assert_eq!(
+            Message::verify_file(path, None),
+            Err(MessageError::InvalidSignature)
+        );
+        assert!(Message::verify_file(&format!("{}.missing", path), None).is_err());
+    }
+
+    #[test]
+    fn test_compact_file_is_smaller_and_verifies() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let pretty_path = dir.path().join("pretty.json");
+        let compact_path = dir.path().join("compact.json");
+
+        let mut message = token_test_message();
+        message
+            .headers
+            .insert("subject".to_string(), "Compact".to_string());
+        message.sign(&KeyPair::from_seed(1).private_key).unwrap();
+        message.to_file(pretty_path.to_str().unwrap()).unwrap();
+        message
+            .to_file_compact(compact_path.to_str().unwrap())
+            .unwrap();
+
+        let pretty = std::fs::read_to_string(&pretty_path).unwrap();
+        let compact = std::fs::read_to_string(&compact_path).unwrap();
+        assert!(compact.len() < pretty.len());
+        assert!(!compact.contains('\n'));
+
+        // The signature is over `signed_bytes`, so formatting can't break it
+        for json in [pretty, compact] {
+            let loaded = Message::from_json(&json).unwrap();
+            assert!(loaded.verify());
+            assert_eq!(loaded.signing_digest(), message.signing_digest());
+        }
+    }
+
+    #[test]
+    fn test_to_file_reports_io_errors_with_path() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        // A directory can't be opened as a file for writing
+        let path = dir.path().to_str().unwrap();
+
+        match token_test_message().to_file(path) {
+            Err(MessageError::FileIo {
+                path: failed_path, ..
+            }) => assert_eq!(failed_path, path),
+            other => panic!("Expected an I/O error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_domain_versions_select_signing_tag() {
+        let sender = KeyPair::generate().unwrap();
+        let mut v1 = Message::new_broadcast(b"Domain versions".to_vec());
+        let mut v2 = v1.clone();
+        v2.domain_version = 2;
+        v1.sign(&sender.private_key).unwrap();
+        v2.sign(&sender.private_key).unwrap();
+        assert!(v1.verify());
+        assert!(v2.verify());
+
+        // Each signature only verifies under its own tag
+        let mut relabeled = v1.clone();
+        relabeled.domain_version = 2;
+        assert_eq!(
+            relabeled.recover_and_verify(),
+            Err(MessageError::InvalidSignature)
+        );
+
+        // The version survives serialization, v1 keeps the old JSON layout
+        let json = String::from_utf8(serialize_message_to_bytes(&v1).unwrap()).unwrap();
+        assert!(!json.contains("domain_version"));
+        let parsed = Message::from_json(
+            &String::from_utf8(serialize_message_to_bytes(&v2).unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(parsed.domain_version, 2);
+        assert!(parsed.verify());
+
+        let mut unknown = v1.clone();
+        unknown.domain_version = 99;
+        assert_eq!(
+            unknown.recover_and_verify(),
+            Err(MessageError::UnsupportedDomainVersion(99))
+        );
+        assert_eq!(
+            unknown.sign(&sender.private_key),
+            Err(MessageError::UnsupportedDomainVersion(99))
+        );
+    }
+
+    #[test]
+    fn test_seal_then_open() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        let payload = message.payload.clone();
+
+        message
+            .seal(&recipient.public_key, &sender.private_key)
+            .unwrap();
+        assert!(message.verify());
+        let sealed = message.clone();
+        message
+            .open(&sender.public_key, &recipient.private_key)
+            .unwrap();
+        assert_eq!(message.payload, payload);
+
+        // A forger re-signing the envelope is caught before decryption
+        let forger = KeyPair::generate().unwrap();
+        let mut forged = sealed.clone();
+        forged.sign_force(&forger.private_key).unwrap();
+        assert_eq!(
+            forged.open(&sender.public_key, &recipient.private_key),
+            Err(MessageError::SenderMismatch)
+        );
+
+        let mut tampered = sealed.clone();
+        tampered.signature.s += Scalar::ONE;
+        assert_eq!(
+            tampered.open(&sender.public_key, &recipient.private_key),
+            Err(MessageError::InvalidSignature)
+        );
+        assert_eq!(
+            tampered.payload, sealed.payload,
+            "Nothing should be decrypted"
+        );
+    }
+
+    #[test]
+    fn test_seal_records_signed_producer() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message
+            .seal(&recipient.public_key, &sender.private_key)
+            .unwrap();
+
+        assert_eq!(
+            message.producer.as_deref(),
+            Some(concat!("secure-channel/", env!("CARGO_PKG_VERSION")))
+        );
+        let parsed = Message::from_json(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert_eq!(parsed.producer, message.producer);
+        assert!(parsed.verify());
+
+        let mut relabeled = message.clone();
+        relabeled.producer = Some("secure-channel/0.0.0".to_string());
+        assert_eq!(
+            relabeled.recover_and_verify(),
+            Err(MessageError::InvalidSignature)
+        );
+        relabeled.producer = None;
+        assert_eq!(
+            relabeled.recover_and_verify(),
+            Err(MessageError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_note_to_self_roundtrip() {
+        let keypair = KeyPair::generate().unwrap();
+        let note = Message::encrypt_for_self(&keypair, b"Remember the milk").unwrap();
+        assert!(note.verify());
+        assert_ne!(note.recipient, keypair.public_key.compress().to_bytes());
+
+        assert_eq!(note.decrypt_own(&keypair).unwrap(), b"Remember the milk");
+        assert!(note.decrypt_own(&KeyPair::generate().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_from_parts_matches_sealed_message() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.encrypt(&recipient.public_key).unwrap();
+        message.sign(&sender.private_key).unwrap();
+
+        let mut signature = message.signature.R.compress().to_bytes().to_vec();
+        signature.extend_from_slice(message.signature.s.as_bytes());
+        let mut rebuilt = Message::from_parts(
+            message.format_version,
+            message.payload.clone(),
+            &message.recipient,
+            &message.sender,
+            &signature,
+        )
+        .unwrap();
+        assert_eq!(rebuilt.signed_bytes(), message.signed_bytes());
+        assert_eq!(rebuilt.signature, message.signature);
+        assert!(rebuilt.verify());
+        rebuilt
+            .open(&sender.public_key, &recipient.private_key)
+            .unwrap();
+
+        assert!(matches!(
+            Message::from_parts(
+                message.format_version,
+                message.payload.clone(),
+                &message.recipient[..31],
+                &message.sender,
+                &signature,
+            ),
+            Err(MessageError::MalformedEnvelope(_))
+        ));
+    }
+
+    #[test]
+    fn test_peek_decrypt_keeps_envelope_intact() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        let payload = message.payload.clone();
+        message
+            .seal(&recipient.public_key, &sender.private_key)
+            .unwrap();
+        let original = serialize_message_to_bytes(&message).unwrap();
+
+        assert_eq!(
+            message.peek_decrypt(&recipient.private_key).unwrap(),
+            payload
+        );
+        assert_eq!(serialize_message_to_bytes(&message).unwrap(), original);
+        assert!(
+            message.verify(),
+            "The envelope should still verify for forwarding"
+        );
+        assert_eq!(
+            message.peek_decrypt(&sender.private_key),
+            Err(MessageError::RecipientMismatch)
+        );
+    }
+
+    #[test]
+    fn test_split_encryption_decrypts_headers_separately() {
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message
+            .headers
+            .insert("subject".to_string(), "Quarterly report".to_string());
+        message.add_attachment("report.bin", vec![0x42; 4096]);
+        let original = message.clone();
+
+        message.encrypt_split(&recipient.public_key).unwrap();
+        assert_eq!(message.mode, EncryptionMode::Split);
+        assert!(message.headers.is_empty());
+        message
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+        assert_eq!(message.is_well_formed(), Ok(()));
+
+        // Corrupting the body doesn't keep the headers from decrypting
+        let mut corrupted_body = message.clone();
+        let last = corrupted_body.payload.len() - 1;
+        corrupted_body.payload[last] ^= 0x01;
+        assert_eq!(
+            corrupted_body
+                .decrypt_headers(&recipient.private_key)
+                .unwrap(),
+            original.headers
+        );
+        assert!(matches!(
+            corrupted_body.decrypt_body(&recipient.private_key),
+            Err(MessageError::Decryption(_))
+        ));
+        assert_eq!(
+            message.clone().decrypt(&recipient.private_key),
+            Err(MessageError::WrongMode(EncryptionMode::Split))
+        );
+
+        message.decrypt_body(&recipient.private_key).unwrap();
+        assert_eq!(message.payload, original.payload);
+        assert_eq!(message.headers, original.headers);
+        assert_eq!(message.attachments(), original.attachments());
+        assert_eq!(message.mode, EncryptionMode::Hybrid);
+    }
+
+    #[test]
+    fn test_split_headers_cannot_be_spliced_onto_another_body() {
+        let recipient = KeyPair::generate().unwrap();
+        let split_message = |subject: &str, payload: &[u8]| {
+            let mut message = token_test_message();
+            message.payload = payload.to_vec();
+            message.recipient = recipient.public_key.compress().to_bytes();
+            message
+                .headers
+                .insert("subject".to_string(), subject.to_string());
+            message.encrypt_split(&recipient.public_key).unwrap();
+            message
+        };
+        let invoice = split_message("Invoice", b"Pay 10");
+        let mut spliced = split_message("Greetings", b"Pay 1000");
+
+        // The header ciphertext of the invoice in front of the other body
+        let header_length = u32::from_le_bytes(invoice.payload[..4].try_into().unwrap()) as usize;
+        let body_start = 4 + u32::from_le_bytes(spliced.payload[..4].try_into().unwrap()) as usize;
+        spliced.payload = [
+            &invoice.payload[..4 + header_length],
+            &spliced.payload[body_start..],
+        ]
+        .concat();
+
+        assert!(matches!(
+            spliced.decrypt_body(&recipient.private_key),
+            Err(MessageError::Decryption(_))
+        ));
+    }
+
+    #[test]
+    fn test_public_json_omits_payload() {
+        let sender = KeyPair::generate().unwrap();
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message
+            .seal(&recipient.public_key, &sender.private_key)
+            .unwrap();
+
+        let json = message.to_public_json();
+        assert!(json.contains(&to_hex(&fingerprint(&sender.public_key))));
+        assert!(json.contains(&to_hex(&fingerprint(&recipient.public_key))));
+        assert!(json.contains(&format!("\"payload_length\":{}", message.payload.len())));
+        assert!(json.contains(&to_hex(&message.message_id())));
+        assert!(!json.contains(&BASE64_STANDARD.encode(&message.payload)));
+        assert!(!json.contains(&BASE64_STANDARD.encode(&message.payload[..16])));
+    }
+
+    fn merkle_batch(len: usize) -> Vec<Message> {
+        (0..len)
+            .map(|i| Message::new_broadcast(format!("Batch message {}", i).into_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merkle_root_is_stable() {
+        let batch = merkle_batch(5);
+        assert_eq!(merkle_root(&batch), merkle_root(&batch.clone()));
+
+        let mut reordered = batch.clone();
+        reordered.swap(0, 1);
+        assert_ne!(merkle_root(&reordered), merkle_root(&batch));
+        assert_ne!(merkle_root(&batch[..4]), merkle_root(&batch));
+    }
+
+    #[test]
+    fn test_merkle_proof_verifies_inclusion() {
+        for len in [1, 2, 5, 8] {
+            let batch = merkle_batch(len);
+            let root = merkle_root(&batch);
+            for (index, message) in batch.iter().enumerate() {
+                let proof = merkle_proof(&batch, index).unwrap();
+                assert!(
+                    verify_merkle_proof(&root, &proof, &message.message_id()),
+                    "Proof for message {} of {} should verify",
+                    index,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_merkle_proof_rejects_wrong_message() {
+        let batch = merkle_batch(5);
+        let root = merkle_root(&batch);
+        let proof = merkle_proof(&batch, 2).unwrap();
+
+        assert!(!verify_merkle_proof(&root, &proof, &batch[3].message_id()));
+        let outsider = Message::new_broadcast(b"Not in the batch".to_vec());
+        assert!(!verify_merkle_proof(&root, &proof, &outsider.message_id()));
+
+        let mut truncated = proof.clone();
+        truncated.siblings.pop();
+        assert!(!verify_merkle_proof(
+            &root,
+            &truncated,
+            &batch[2].message_id()
+        ));
+
+        assert_eq!(
+            merkle_proof(&batch, 5),
+            Err(MessageError::IndexOutOfRange { index: 5, len: 5 })
+        );
+    }
+
+    #[test]
+    fn test_zeroize_payload() {
+        let recipient = KeyPair::generate().unwrap();
+        let mut message = token_test_message();
+        message.recipient = recipient.public_key.compress().to_bytes();
+        message.encrypt(&recipient.public_key).unwrap();
+        message.decrypt(&recipient.private_key).unwrap();
+        let length = message.payload.len();
+
+        message.zeroize_payload();
+        assert_eq!(message.payload.len(), length);
+        assert!(message.payload.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn test_peek_version_reads_large_frames() {
+        let mut message = Message::new_broadcast(vec![0x5a; 1024 * 1024]);
+        message.format_version = 7;
+        message
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+
+        let formats = [
+            SerializationFormat::Json,
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack,
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor,
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode,
+        ];
+        for format in formats {
+            let mut frame = Vec::new();
+            crate::transport::send_message(&mut frame, &message, format).unwrap();
+            assert_eq!(
+                peek_version(&frame),
+                Ok(7),
+                "Wrong version for {:?}",
+                format
+            );
+            assert!(peek_version(&frame[..frame.len() - 1]).is_err());
+        }
+        assert!(peek_version(&[0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_archive_extracts_for_every_recipient() {
+        let recipients = [
+            KeyPair::generate().unwrap(),
+            KeyPair::generate().unwrap(),
+            KeyPair::generate().unwrap(),
+        ];
+        let public_keys: Vec<RistrettoPoint> = recipients
+            .iter()
+            .map(|keypair| keypair.public_key)
+            .collect();
+        let mut message = token_test_message();
+        message
+            .headers
+            .insert("subject".to_string(), "Backup".to_string());
+        message
+            .sign(&KeyPair::generate().unwrap().private_key)
+            .unwrap();
+
+        let archive = message.to_archive(&public_keys).unwrap();
+        assert_eq!(&archive[..4], crate::archive::ARCHIVE_MAGIC);
+        assert_eq!(u16::from_le_bytes([archive[6], archive[7]]), 3);
+
+        for recipient in &recipients {
+            let restored = Message::from_archive(&archive, &recipient.private_key).unwrap();
+            assert_eq!(restored.payload, message.payload);
+            assert_eq!(restored.headers, message.headers);
+            assert!(
+                restored.verify(),
+                "The archived signature should still verify"
+            );
+        }
+    }
+
+    #[test]
+    fn test_archive_rejects_outsiders_and_tampering() {
+        let recipient = KeyPair::generate().unwrap();
+        let archive = token_test_message()
+            .to_archive(&[recipient.public_key])
+            .unwrap();
+
+        assert_eq!(
+            Message::from_archive(&archive, &KeyPair::generate().unwrap().private_key).err(),
+            Some(MessageError::RecipientMismatch)
+        );
+
+        let mut tampered = archive.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert!(matches!(
+            Message::from_archive(&tampered, &recipient.private_key),
+            Err(MessageError::Decryption(_))
+        ));
+        assert!(matches!(
+            Message::from_archive(&archive[..20], &recipient.private_key),
+            Err(MessageError::MalformedCiphertext(_))
+        ));
+    }
+
+    #[test]
+    fn test_archive_authenticates_header_and_manifest() {
+        let recipient = KeyPair::generate().unwrap();
+        let other = KeyPair::generate().unwrap();
+        let archive = token_test_message()
+            .to_archive(&[recipient.public_key, other.public_key])
+            .unwrap();
+
+        // The format version, and the fingerprint in the other recipient's slot
+        let slot_start = 12 + 72;
+        for index in [5, slot_start] {
+            let mut tampered = archive.clone();
+            tampered[index] ^= 0x01;
+            assert!(
+                matches!(
+                    Message::from_archive(&tampered, &recipient.private_key),
+                    Err(MessageError::Decryption(_))
+                ),
+                "Byte {index}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_encryption_leaves_message_untouched() {
+        // Serialization can't fail for any constructible message, so this covers the
+        // reachable failures: a weak key and a message that is already encrypted
+        use curve25519_dalek::traits::Identity;
+
+        let recipient = KeyPair::from_seed(1);
+        let mut message = Message::new(
+            0,
+            b"Must survive failed encryption".to_vec(),
+            CompressedRistretto::default(),
+            recipient.public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+        message
+            .headers
+            .insert("subject".to_string(), "Untouched".to_string());
+        let before = serialize_message_to_bytes(&message).unwrap();
+
+        assert_eq!(
+            message.encrypt(&RistrettoPoint::identity()),
+            Err(MessageError::WeakKey)
+        );
+        assert_eq!(serialize_message_to_bytes(&message).unwrap(), before);
+
+        message.encrypt(&recipient.public_key).unwrap();
+        let encrypted = serialize_message_to_bytes(&message).unwrap();
+        assert_eq!(
+            message.encrypt(&recipient.public_key),
+            Err(MessageError::AlreadyEncrypted)
+        );
+        assert_eq!(serialize_message_to_bytes(&message).unwrap(), encrypted);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_events_leak_no_payload() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone)]
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = Capture(output.clone());
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let payload = b"Traced payload that must stay secret".to_vec();
+        let recipient = KeyPair::from_seed(2);
+        tracing::subscriber::with_default(subscriber, || {
+            let mut message = Message::new(
+                0,
+                payload.clone(),
+                CompressedRistretto::default(),
+                recipient.public_key.compress(),
+                SchnorrSignature::emty_signature(),
+            );
+            message.encrypt(&recipient.public_key).unwrap();
+            message.decrypt(&recipient.private_key).unwrap();
+        });
+
+        let logged = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(
+            logged.contains("operation=\"encrypt\""),
+            "Missing encrypt event: {}",
+            logged
+        );
+        assert!(
+            logged.contains("operation=\"decrypt\""),
+            "Missing decrypt event: {}",
+            logged
+        );
+        assert!(!logged.contains("Traced payload"));
+        assert!(!logged.contains(&to_hex(&payload)));
+        assert!(!logged.contains(&BASE64_STANDARD.encode(&payload)));
+    }
+
+    #[test]
+    fn test_timelock_roundtrip() {
+        let payload = b"Open after the deadline".to_vec();
+        let recipient = RistrettoPoint::random(&mut OsRng).compress();
+
+        let mut message = Message::new(
+            0,
+            payload.clone(),
+            CompressedRistretto::default(),
+            recipient,
+            SchnorrSignature::emty_signature(),
+        );
+        message.timelock_encrypt(100).unwrap();
+        assert_eq!(message.mode, EncryptionMode::Timelock);
+        assert!(!message
+            .payload
+            .windows(payload.len())
+            .any(|w| w == payload.as_slice()));
+
+        let mut received = message.clone();
+        received.timelock_decrypt().unwrap();
+        assert_eq!(received.payload, payload);
+        assert_eq!(received.state, MessageState::Plain);
+
+        assert_eq!(
+            message.decrypt_symmetric(&[0u8; AES_KEY_SIZE]),
+            Err(MessageError::WrongMode(EncryptionMode::Timelock))
+        );
+    }
+
+    #[test]
+    fn test_routing_headers_are_readable_and_authenticated() {
+        let recipient = KeyPair::from_seed(1);
+        let mut message = Message::new(
+            0,
+            b"Routed payload".to_vec(),
+            CompressedRistretto::default(),
+            recipient.public_key.compress(),
+            SchnorrSignature::emty_signature(),
+        );
+        message.set_routing_header("next-hop", "relay-7");
+        message.encrypt(&recipient.public_key).unwrap();
+
+        // A relay reads the header from the serialized envelope without any key
+        let json = String::from_utf8(serialize_message_to_bytes(&message).unwrap()).unwrap();
+        let relayed = Message::from_json(&json).unwrap();
+        assert_eq!(
+            relayed.routing_headers().get("next-hop").unwrap(),
+            "relay-7"
+        );
+
+        let mut decrypted = relayed.clone();
+        decrypted.decrypt(&recipient.private_key).unwrap();
+        assert_eq!(decrypted.payload, b"Routed payload");
+        assert_eq!(decrypted.routing_headers(), relayed.routing_headers());
+
+        let mut tampered = relayed;
+        tampered.set_routing_header("next-hop", "relay-8");
+        assert!(matches!(
+            tampered.decrypt(&recipient.private_key),
+            Err(MessageError::Decryption(_))
+        ));
+    }
+
+    #[test]
+    fn test_original_bytes_forward_exactly() {
+        let sender = KeyPair::from_seed(1);
+        let mut message = token_test_message();
+        message.sign(&sender.private_key).unwrap();
+        // Pretty-printed input, which `to_bytes` would write compactly
+        let input = serde_json::to_vec_pretty(&message).unwrap();
+
+        let mut parsed = Message::from_bytes(&input, SerializationFormat::Json).unwrap();
+        assert!(parsed.verify());
+        assert_eq!(parsed.original_bytes(), Some(input.as_slice()));
+        assert_ne!(parsed.to_bytes(SerializationFormat::Json).unwrap(), input);
+
+        parsed
+            .sign_force(&KeyPair::from_seed(2).private_key)
+            .unwrap();
+        assert_eq!(
+            parsed.original_bytes(),
+            None,
+            "Changed messages must be re-serialized"
+        );
+        assert_eq!(message.original_bytes(), None);
+    }
+
+    #[test]
+    fn test_migrate_aes_derivation() {
+        let bob = KeyPair::generate().unwrap();
+        let carol = KeyPair::generate().unwrap();
+
+        // Messages encrypted before the AES key went through HKDF
+        let legacy = |recipient: &KeyPair, body: &[u8]| {
+            let mut message = token_test_message();
+            message.payload = body.to_vec();
+            message.recipient = recipient.public_key.compress().to_bytes();
+            let serialized = serialize_message_to_bytes(&message).unwrap();
+            let ciphertext =
+                HybridCiphertext::encrypt_legacy(&serialized, &recipient.public_key).unwrap();
+            assert_eq!(ciphertext.key_derivation(), KeyDerivation::Raw);
+            message.payload = ciphertext.serialize();
+            message.state = MessageState::Encrypted;
+            message
+        };
+        let mut current = token_test_message();
+        current.recipient = bob.public_key.compress().to_bytes();
+        current.encrypt(&bob.public_key).unwrap();
+        assert_eq!(
+            HybridCiphertext::deserialize(&current.payload)
+                .unwrap()
+                .key_derivation(),
+            KeyDerivation::Hkdf
+        );
+        let mut signed = legacy(&bob, b"signed");
+        signed.sign(&carol.private_key).unwrap();
+        let mut messages = vec![
+            legacy(&bob, b"first"),
+            legacy(&carol, b"not for bob"),
+            legacy(&bob, b"second"),
+            current.clone(),
+            signed.clone(),
+        ];
+        let untouched = messages[1].clone();
+
+        let results = migrate_aes_derivation(&mut messages, &bob.private_key);
+        assert_eq!(results.len(), 5);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert_eq!(results[1], Err(MessageError::RecipientMismatch));
+        assert_eq!(results[3], Ok(()));
+        assert_eq!(results[4], Err(MessageError::SignatureWouldBeCleared));
+        assert_eq!(
+            messages[1].payload, untouched.payload,
+            "A failed migration changed it"
+        );
+        assert_eq!(
+            messages[3].payload, current.payload,
+            "Migrated messages are skipped"
+        );
+        assert!(
+            messages[4].verify(),
+            "A signed message must keep its signature"
+        );
+
+        for (index, body) in [(0, &b"first"[..]), (2, &b"second"[..])] {
+            let ciphertext = HybridCiphertext::deserialize(&messages[index].payload).unwrap();
+            assert_eq!(ciphertext.key_derivation(), KeyDerivation::Hkdf);
+            messages[index].decrypt(&bob.private_key).unwrap();
+            assert_eq!(messages[index].payload, body);
+        }
+    }
+
+    #[test]
+    fn test_deterministic_encryption_is_reproducible() {
+        let bob = KeyPair::from_seed(2);
+        let mut message = token_test_message();
+        message.recipient = bob.public_key.compress().to_bytes();
+
+        let encrypt = |ikm: &[u8]| {
+            let mut encrypted = message.clone();
+            encrypted
+                .encrypt_deterministic(&bob.public_key, ikm)
+                .unwrap();
+            serialize_message_to_bytes(&encrypted).unwrap()
+        };
+        assert_eq!(encrypt(b"dedup key"), encrypt(b"dedup key"));
+        assert_ne!(encrypt(b"dedup key"), encrypt(b"other key"));
+
+        let mut randomized = message.clone();
+        randomized.encrypt(&bob.public_key).unwrap();
+        assert_ne!(
+            serialize_message_to_bytes(&randomized).unwrap(),
+            encrypt(b"dedup key")
+        );
+
+        let mut encrypted = message.clone();
+        encrypted
+            .encrypt_deterministic(&bob.public_key, b"dedup key")
+            .unwrap();
+        encrypted.decrypt(&bob.private_key).unwrap();
+        assert_eq!(encrypted.payload, message.payload);
+    }
+
+    #[test]
+    fn test_encrypt_for_fingerprint() {
+        let bob = KeyPair::from_seed(2);
+        let carol = KeyPair::from_seed(3);
+        let bob_fingerprint = fingerprint(&bob.public_key);
+        let carol_fingerprint = fingerprint(&carol.public_key);
+        // Only knows Bob, and answers a lookup of a made-up fingerprint with Carol's key
+        let resolver = |fp: [u8; 8]| match fp {
+            fp if fp == bob_fingerprint => Some(bob.public_key),
+            [0x42, ..] => Some(carol.public_key),
+            _ => None,
+        };
+
+        let mut message = token_test_message();
+        let original = message.clone();
+        assert_eq!(
+            message.encrypt_for_fingerprint(carol_fingerprint, resolver),
+            Err(MessageError::UnknownRecipient(carol_fingerprint))
+        );
+        assert_eq!(
+            message.encrypt_for_fingerprint([0x42; 8], resolver),
+            Err(MessageError::UnknownRecipient([0x42; 8])),
+            "A key under the wrong fingerprint must not be used"
+        );
+        assert_eq!(message.state, MessageState::Plain);
+        assert_eq!(message.recipient, original.recipient);
+
+        message
+            .encrypt_for_fingerprint(bob_fingerprint, resolver)
+            .unwrap();
+        assert_eq!(message.recipient, bob.public_key.compress().to_bytes());
+        message.decrypt(&bob.private_key).unwrap();
+        assert_eq!(message.payload, original.payload);
+    }
+
+    #[test]
+    fn test_serialized_len_matches_serialization() {
+        let bob = KeyPair::from_seed(2);
+        let signer = KeyPair::from_seed(1);
+
+        let plain = token_test_message();
+        let mut sealed = token_test_message();
+        sealed
+            .headers
+            .insert("subject".to_string(), "Quarterly \"numbers\"\n".to_string());
+        sealed.add_attachment("report.bin", vec![0xab; 300]);
+        sealed.set_routing_header("queue", "inbound");
+        sealed.recipient = bob.public_key.compress().to_bytes();
+        sealed.seal(&bob.public_key, &signer.private_key).unwrap();
+        let mut compact = sealed.clone();
+        compact.signature_format = SignatureFormat::Array;
+        let mut empty = token_test_message();
+        empty.payload.clear();
+
+        let formats = [
+            SerializationFormat::Json,
+            #[cfg(feature = "msgpack")]
+            SerializationFormat::MessagePack,
+            #[cfg(feature = "cbor")]
+            SerializationFormat::Cbor,
+            #[cfg(feature = "bincode")]
+            SerializationFormat::Bincode,
+        ];
+        for message in [&plain, &sealed, &compact, &empty] {
+            for format in formats {
+                assert_eq!(
+                    message.serialized_len(format).unwrap(),
+                    message.to_bytes(format).unwrap().len(),
+                    "Length mismatch for {:?}",
+                    format
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_newer_format_version_is_rejected() {
+        let bob = KeyPair::from_seed(2);
+        let signer = KeyPair::from_seed(1);
+        let mut message = token_test_message();
+        message.recipient = bob.public_key.compress().to_bytes();
+        message.encrypt(&bob.public_key).unwrap();
+        message.format_version = FORMAT_VERSION + 1;
+        message.sign(&signer.private_key).unwrap();
+
+        let expected = MessageError::UnsupportedFormatVersion {
+            found: FORMAT_VERSION + 1,
+            max_supported: FORMAT_VERSION,
+        };
+        assert_eq!(message.recover_and_verify(), Err(expected.clone()));
+        assert!(!message.verify());
+        assert_eq!(message.clone().decrypt(&bob.private_key), Err(expected));
+
+        message.format_version = FORMAT_VERSION;
+        message.sign(&signer.private_key).unwrap();
+        assert!(message.verify());
+        message.decrypt(&bob.private_key).unwrap();
+    }
+
+    #[test]
+    fn test_decrypt_to_writer() {
+        let bob = KeyPair::from_seed(2);
+        let plaintext: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut message = token_test_message();
+        message.payload = plaintext.clone();
+        message.recipient = bob.public_key.compress().to_bytes();
+        message.encrypt(&bob.public_key).unwrap();
+
+        let mut sink = std::io::Cursor::new(Vec::new());
+        message
+            .decrypt_to_writer(&bob.private_key, &mut sink)
+            .unwrap();
+        assert!(sink.into_inner() == plaintext, "Streamed plaintext differs");
+        assert_eq!(message.state, MessageState::Encrypted);
+
+        let mut tampered = message.clone();
+        *tampered.payload.last_mut().unwrap() ^= 0x01;
+        let mut sink = std::io::Cursor::new(Vec::new());
+        assert!(matches!(
+            tampered.decrypt_to_writer(&bob.private_key, &mut sink),
+            Err(MessageError::Decryption(_))
+        ));
+        assert!(
+            sink.into_inner().is_empty(),
+            "Nothing may be written before authentication"
+        );
+    }
 }
@@ -1,12 +1,16 @@
 #![allow(non_snake_case)]
 
-use crate::keys::KeyPair;
+use crate::error::CryptoError;
+use crate::hash::HashAlgorithm;
+use crate::keys::{secure_rng, KeyPair};
 use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
 use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
 use curve25519_dalek::scalar::Scalar;
-use rand::rngs::OsRng;
+use curve25519_dalek::traits::{Identity, IsIdentity};
+use subtle::Choice;
 
 use sha2::{Digest, Sha512};
+use std::io::{self, Read};
 /// Struct to represent a Schnorr signature
 #[derive(Debug, PartialEq, Clone)]
 pub struct SchnorrSignature {
@@ -16,28 +20,54 @@ pub struct SchnorrSignature {
 
 impl SchnorrSignature {
     /// Generates a new KeyPair for signing
-    pub fn keygen() -> KeyPair {
+    pub fn keygen() -> Result<KeyPair, CryptoError> {
         KeyPair::generate()
     }
 
     /// Sign a message with a private key
-    pub fn sign(message: &[u8], signing_key: &Scalar) -> SchnorrSignature {
-        let mut rng = OsRng;
+    pub fn sign(message: &[u8], signing_key: &Scalar) -> Result<SchnorrSignature, CryptoError> {
+        SchnorrSignature::sign_with_hash(HashAlgorithm::Sha512, message, signing_key)
+    }
+
+    /// Like `sign`, with the challenge computed by `hash`. Verify with `verify_with_hash`
+    /// and the same hash.
+    pub fn sign_with_hash(
+        hash: HashAlgorithm,
+        message: &[u8],
+        signing_key: &Scalar,
+    ) -> Result<SchnorrSignature, CryptoError> {
+        let mut rng = secure_rng()?;
         let r = Scalar::random(&mut rng); // Generate random scalar r
 
         // Compute the commitment point R = g^r
-        let R = &r * &RISTRETTO_BASEPOINT_POINT;
+        let R = r * RISTRETTO_BASEPOINT_POINT;
 
-        // Recompute the challenge e = H(R || message)
-        let mut hasher = Sha512::new();
-        hasher.update(R.compress().as_bytes());
-        hasher.update(message);
-        let e = Scalar::from_hash(hasher);
+        // Compute the challenge e = H(R || message)
+        let e = hash.hash_to_scalar(&[R.compress().as_bytes(), message]);
 
         // Compute the response scalar s = r + e * private_key
         let s = r + e * signing_key;
 
-        SchnorrSignature { R, s }
+        Ok(SchnorrSignature { R, s })
+    }
+
+    /// Signs the SHA-512 digest of everything read from `reader`, the counterpart of
+    /// `StreamVerifier`
+    pub fn sign_reader<R: Read>(
+        mut reader: R,
+        signing_key: &Scalar,
+    ) -> io::Result<SchnorrSignature> {
+        let mut hasher = Sha512::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        let digest: [u8; 64] = hasher.finalize().into();
+        SchnorrSignature::sign(&digest, signing_key).map_err(io::Error::other)
     }
 
     /// Verify a Schnorr signature
@@ -46,18 +76,91 @@ impl SchnorrSignature {
         message: &[u8],
         public_key: &RistrettoPoint,
     ) -> bool {
+        SchnorrSignature::verify_with_hash(HashAlgorithm::Sha512, signature, message, public_key)
+    }
+
+    /// Verifies a signature made by `sign_with_hash` with the same hash
+    pub fn verify_with_hash(
+        hash: HashAlgorithm,
+        signature: &SchnorrSignature,
+        message: &[u8],
+        public_key: &RistrettoPoint,
+    ) -> bool {
+        // With the identity as key, the placeholder signature would verify for any message
+        if public_key.is_identity() {
+            return false;
+        }
+
         // Recompute the challenge e = H(R || message)
-        let mut hasher = Sha512::new();
-        hasher.update(signature.R.compress().as_bytes());
-        hasher.update(message);
-        let e = Scalar::from_hash(hasher);
+        let e = hash.hash_to_scalar(&[signature.R.compress().as_bytes(), message]);
         // Verify the equation: g^s == R + e * public_key
-        let lhs = &signature.s * &RISTRETTO_BASEPOINT_POINT; // g^s
+        let lhs = signature.s * RISTRETTO_BASEPOINT_POINT; // g^s
         let rhs = signature.R + e * public_key; // R + e * public_key
 
         lhs == rhs
     }
 
+    /// Verify a Schnorr signature without any heap allocation.
+    /// The challenge is hashed straight from the compressed commitment's byte array and
+    /// the check R == g^s - e * public_key is done with a single double-scalar multiplication.
+    pub fn verify_no_alloc(
+        signature: &SchnorrSignature,
+        message: &[u8],
+        public_key: &RistrettoPoint,
+    ) -> bool {
+        if public_key.is_identity() {
+            return false;
+        }
+        let R_bytes: [u8; 32] = signature.R.compress().to_bytes();
+
+        // Recompute the challenge e = H(R || message)
+        let e = HashAlgorithm::Sha512.hash_to_scalar(&[&R_bytes, message]);
+
+        // g^s - e * public_key should give back the commitment R
+        let expected_R =
+            RistrettoPoint::vartime_double_scalar_mul_basepoint(&-e, public_key, &signature.s);
+
+        expected_R == signature.R
+    }
+
+    /// Recovers the public key a signature was produced with: pk = (g^s - R) / e.
+    /// This works because the challenge e = H(R || message) doesn't depend on the key.
+    /// Note that every (R, s) pair "verifies" under the key recovered from it, so a recovered
+    /// key only authenticates a message once it is compared against a trusted key.
+    /// `Message` signatures hash the sender into the signed bytes, so there the sender can't be
+    /// dropped; this is meant for raw signatures over bytes the verifier already holds.
+    /// Returns None in the negligible case that e is zero.
+    pub fn recover_public_key(&self, message: &[u8]) -> Option<RistrettoPoint> {
+        let e = SchnorrSignature::compute_challenge(&self.R, message);
+
+        if e == Scalar::ZERO {
+            return None;
+        }
+
+        Some((self.s * RISTRETTO_BASEPOINT_POINT - self.R) * e.invert())
+    }
+
+    /// The challenge e = SHA-512(R || message) reduced modulo the group order, as used by
+    /// `sign` and `verify`. Exposed so other implementations can compare it byte for byte.
+    pub fn compute_challenge(R: &RistrettoPoint, message: &[u8]) -> Scalar {
+        HashAlgorithm::Sha512.hash_to_scalar(&[R.compress().as_bytes(), message])
+    }
+
+    /// Key-prefixed challenge e = SHA-512(R || pk || message) of schemes that bind the
+    /// signer's key into the challenge. `sign` doesn't use it, this is for interop tests with
+    /// such implementations.
+    pub fn compute_challenge_with_key(
+        R: &RistrettoPoint,
+        public_key: &RistrettoPoint,
+        message: &[u8],
+    ) -> Scalar {
+        HashAlgorithm::Sha512.hash_to_scalar(&[
+            R.compress().as_bytes(),
+            public_key.compress().as_bytes(),
+            message,
+        ])
+    }
+
     // Converts RistrettoPoint to a byte array
     pub fn point_to_bytes(point: &RistrettoPoint) -> Vec<u8> {
         point.compress().as_bytes().to_vec()
@@ -69,59 +172,115 @@ impl SchnorrSignature {
     }
 
     // Converts byte array back to RistrettoPoint
-    pub fn bytes_to_point(bytes: &[u8]) -> Result<RistrettoPoint, &'static str> {
-        if bytes.len() != 32 {
-            return Err("Invalid byte length for RistrettoPoint");
-        }
-
-        let mut array = [0u8; 32];
-        array.copy_from_slice(bytes); // Convert &[u8] to [u8; 32]
+    pub fn bytes_to_point(bytes: &[u8]) -> Result<RistrettoPoint, CryptoError> {
+        let array: [u8; 32] = bytes.try_into().map_err(|_| CryptoError::WrongLength {
+            expected: 32,
+            actual: bytes.len(),
+        })?;
 
-        let R_compressed = CompressedRistretto(array); // Compressed form
-        let R = R_compressed
+        CompressedRistretto(array)
             .decompress()
-            .ok_or("Failed to decompress RistrettoPoint")?; // Handle decompression failure
-
-        Ok(R)
+            .ok_or(CryptoError::InvalidPoint)
     }
 
+    /// Placeholder for "not signed yet": R is the identity and s is zero, so both encode as
+    /// all-zero bytes and can't be mistaken for a real signature.
+    /// `verify` rejects the identity as a public key, so the placeholder never verifies.
     pub fn emty_signature() -> SchnorrSignature {
         SchnorrSignature {
-            R: RISTRETTO_BASEPOINT_POINT,
+            R: RistrettoPoint::identity(),
             s: Scalar::ZERO,
         }
     }
 
-    // Converts byte array back to Scalar
-    pub fn bytes_to_scalar(bytes: &[u8]) -> Result<Scalar, &'static str> {
-        if bytes.len() != 32 {
-            return Err("Invalid byte length for Scalar");
+    // Converts byte array back to Scalar, rejecting values at or above the group order
+    pub fn bytes_to_scalar(bytes: &[u8]) -> Result<Scalar, CryptoError> {
+        let array: [u8; 32] = bytes.try_into().map_err(|_| CryptoError::WrongLength {
+            expected: 32,
+            actual: bytes.len(),
+        })?;
+
+        // Only the final accept/reject decision is branched on, never the scalar itself
+        let (scalar, is_canonical) = Self::bytes_to_scalar_ct(&array);
+        if bool::from(is_canonical) {
+            Ok(scalar)
+        } else {
+            Err(CryptoError::NonCanonicalScalar)
         }
+    }
 
-        let array: [u8; 32] = bytes.try_into().map_err(|_| "Invalid length")?;
-        let scalar = Scalar::from_canonical_bytes(array);
+    /// Constant-time canonical scalar parsing: returns the parsed scalar (zero if the bytes are
+    /// not canonical) together with a `Choice` telling whether they were, without branching on
+    /// the bytes. Use this when parsing secret scalars such as `s`.
+    pub fn bytes_to_scalar_ct(bytes: &[u8; 32]) -> (Scalar, Choice) {
+        let parsed = Scalar::from_canonical_bytes(*bytes);
+        let is_canonical = parsed.is_some();
+        (parsed.unwrap_or(Scalar::ZERO), is_canonical)
+    }
+}
 
-        // Handle CtOption<Scalar>
-        if scalar.is_some().into() {
-            Ok(scalar.unwrap())
-        } else {
-            Err("Invalid scalar")
+/// Verifies a signature over a SHA-512 prehash of data fed in chunks, so large inputs don't
+/// have to be buffered. Feeding a message's `signed_bytes` gives the same result as
+/// `Message::verify`, which signs `signing_digest`, the SHA-512 of those bytes.
+pub struct StreamVerifier {
+    hasher: Sha512,
+}
+
+impl StreamVerifier {
+    pub fn new() -> Self {
+        StreamVerifier {
+            hasher: Sha512::new(),
         }
     }
+
+    /// Feeds the next chunk of signed data
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+    }
+
+    /// Checks the signature over the SHA-512 digest of everything fed so far
+    pub fn finalize(self, signature: &SchnorrSignature, public_key: &RistrettoPoint) -> bool {
+        let digest: [u8; 64] = self.hasher.finalize().into();
+        SchnorrSignature::verify(signature, &digest, public_key)
+    }
+}
+
+impl Default for StreamVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detached signature over arbitrary bytes, without building a `Message`. `Message::sign`
+/// uses it over `signing_digest`. Nothing is prepended, so put a context string into
+/// `message` if the same key also signs other kinds of data.
+pub fn sign_bytes(message: &[u8], signing_key: &Scalar) -> Result<SchnorrSignature, CryptoError> {
+    SchnorrSignature::sign(message, signing_key)
+}
+
+/// Checks a detached signature from `sign_bytes`
+pub fn verify_bytes(
+    message: &[u8],
+    signature: &SchnorrSignature,
+    public_key: &RistrettoPoint,
+) -> bool {
+    SchnorrSignature::verify(signature, message, public_key)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::OsRng;
+    use subtle::ConditionallySelectable;
 
     #[test]
     fn test_valid_signature() {
         // Generate keypair
-        let keypair: KeyPair = SchnorrSignature::keygen();
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
 
         // Sign a message
         let message = b"Test message for Schnorr signature";
-        let signature = SchnorrSignature::sign(message, &keypair.private_key);
+        let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
 
         // Verify the signature
         let is_valid = SchnorrSignature::verify(&signature, message, &keypair.public_key);
@@ -134,10 +293,10 @@ mod tests {
     #[test]
     fn test_invalid_signature_message() {
         // Generate keypair
-        let keypair: KeyPair = SchnorrSignature::keygen();
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
         // Sign a message
         let message = b"Test message for Schnorr signature";
-        let signature = SchnorrSignature::sign(message, &keypair.private_key);
+        let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
 
         // Modify the message
         let modified_message = b"Modified test message";
@@ -153,10 +312,10 @@ mod tests {
     #[test]
     fn test_invalid_signature_signature() {
         // Generate keypair
-        let keypair: KeyPair = SchnorrSignature::keygen();
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
         // Sign a message
         let message = b"Test message for Schnorr signature";
-        let signature = SchnorrSignature::sign(message, &keypair.private_key);
+        let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
 
         // Alter the signature by modifying the `s` scalar
         let altered_signature = SchnorrSignature {
@@ -172,10 +331,10 @@ mod tests {
     #[test]
     fn test_edge_case_empty_message() {
         // Generate keypair
-        let keypair: KeyPair = SchnorrSignature::keygen();
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
         // Sign an empty message
         let empty_message = b"";
-        let signature = SchnorrSignature::sign(empty_message, &keypair.private_key);
+        let signature = SchnorrSignature::sign(empty_message, &keypair.private_key).unwrap();
 
         // Verify the signature for the empty message
         let is_valid = SchnorrSignature::verify(&signature, empty_message, &keypair.public_key);
@@ -188,12 +347,12 @@ mod tests {
     #[test]
     fn test_invalid_public_key() {
         // Generate keypairs
-        let keypair1: KeyPair = SchnorrSignature::keygen();
-        let keypair2: KeyPair = SchnorrSignature::keygen();
+        let keypair1: KeyPair = SchnorrSignature::keygen().unwrap();
+        let keypair2: KeyPair = SchnorrSignature::keygen().unwrap();
 
         // Sign a message with the first keypair
         let message = b"Test message for Schnorr signature";
-        let signature = SchnorrSignature::sign(message, &keypair1.private_key);
+        let signature = SchnorrSignature::sign(message, &keypair1.private_key).unwrap();
 
         // Try to verify with a different public key
         let is_valid = SchnorrSignature::verify(&signature, message, &keypair2.public_key);
@@ -203,15 +362,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_verify_no_alloc_matches_verify() {
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
+        let other_keypair: KeyPair = SchnorrSignature::keygen().unwrap();
+        let message = b"Test message for Schnorr signature";
+        let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
+        let altered_signature = SchnorrSignature {
+            R: signature.R,
+            s: signature.s + Scalar::ONE,
+        };
+
+        let cases = [
+            (&signature, &message[..], &keypair.public_key),
+            (
+                &signature,
+                &b"Modified test message"[..],
+                &keypair.public_key,
+            ),
+            (&signature, &message[..], &other_keypair.public_key),
+            (&altered_signature, &message[..], &keypair.public_key),
+        ];
+
+        for (sig, msg, pk) in cases {
+            assert_eq!(
+                SchnorrSignature::verify_no_alloc(sig, msg, pk),
+                SchnorrSignature::verify(sig, msg, pk),
+                "verify_no_alloc should agree with verify"
+            );
+        }
+        assert!(SchnorrSignature::verify_no_alloc(
+            &signature,
+            message,
+            &keypair.public_key
+        ));
+    }
+
     #[test]
     fn test_repeated_signing_different_signatures() {
         // Generate keypair
-        let keypair: KeyPair = SchnorrSignature::keygen();
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
 
         // Sign the same message twice
         let message = b"Test message for Schnorr signature";
-        let signature1 = SchnorrSignature::sign(message, &keypair.private_key);
-        let signature2 = SchnorrSignature::sign(message, &keypair.private_key);
+        let signature1 = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
+        let signature2 = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
 
         // The signatures should be different due to different random nonces
         assert_ne!(
@@ -223,4 +418,155 @@ mod tests {
             "Response scalars should be different due to randomness"
         );
     }
+
+    #[test]
+    fn test_recover_public_key() {
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
+        let message = b"Test message for Schnorr signature";
+        let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
+
+        let recovered = signature
+            .recover_public_key(message)
+            .expect("Recovery should succeed");
+        assert_eq!(
+            recovered, keypair.public_key,
+            "Recovered key should be the signer's"
+        );
+        assert!(SchnorrSignature::verify(&signature, message, &recovered));
+    }
+
+    #[test]
+    fn test_recovered_key_must_be_compared_to_a_trusted_key() {
+        let keypair: KeyPair = SchnorrSignature::keygen().unwrap();
+        let signature = SchnorrSignature::sign(b"Original message", &keypair.private_key).unwrap();
+
+        // Recovery "succeeds" for any message, but yields an unrelated key
+        let recovered = signature
+            .recover_public_key(b"Forged message")
+            .expect("Recovery should succeed");
+        assert_ne!(recovered, keypair.public_key);
+        assert!(SchnorrSignature::verify(
+            &signature,
+            b"Forged message",
+            &recovered
+        ));
+    }
+
+    #[test]
+    fn test_bytes_to_scalar_distinguishes_length_from_overflow() {
+        assert_eq!(
+            SchnorrSignature::bytes_to_scalar(&[0u8; 31]),
+            Err(CryptoError::WrongLength {
+                expected: 32,
+                actual: 31
+            })
+        );
+        // 2^256 - 1 is well above the group order
+        assert_eq!(
+            SchnorrSignature::bytes_to_scalar(&[0xff; 32]),
+            Err(CryptoError::NonCanonicalScalar)
+        );
+        assert_eq!(
+            SchnorrSignature::bytes_to_scalar(&Scalar::ONE.to_bytes()),
+            Ok(Scalar::ONE)
+        );
+    }
+
+    #[test]
+    fn test_constant_time_scalar_parsing_matches() {
+        let order_minus_one = (-Scalar::ONE).to_bytes();
+        let mut order = order_minus_one;
+        order[0] += 1;
+        let inputs = [
+            [0u8; 32],
+            Scalar::ONE.to_bytes(),
+            order_minus_one,
+            order,
+            [0xff; 32],
+            Scalar::random(&mut OsRng).to_bytes(),
+        ];
+
+        for bytes in inputs {
+            let expected = Option::<Scalar>::from(Scalar::from_canonical_bytes(bytes));
+            let (scalar, is_canonical) = SchnorrSignature::bytes_to_scalar_ct(&bytes);
+
+            assert_eq!(bool::from(is_canonical), expected.is_some());
+            assert_eq!(SchnorrSignature::bytes_to_scalar(&bytes).ok(), expected);
+            // Selecting on the Choice gives the parsed scalar or the fallback, without branching
+            let selected = Scalar::conditional_select(&Scalar::ONE, &scalar, is_canonical);
+            assert_eq!(selected, expected.unwrap_or(Scalar::ONE));
+        }
+    }
+
+    #[cfg(feature = "blake2")]
+    #[test]
+    fn test_blake2b_signatures_are_self_consistent() {
+        let keypair = KeyPair::generate().unwrap();
+        let message = b"Signed with BLAKE2b";
+
+        let signature =
+            SchnorrSignature::sign_with_hash(HashAlgorithm::Blake2b, message, &keypair.private_key)
+                .unwrap();
+        assert!(SchnorrSignature::verify_with_hash(
+            HashAlgorithm::Blake2b,
+            &signature,
+            message,
+            &keypair.public_key
+        ));
+        assert!(!SchnorrSignature::verify_with_hash(
+            HashAlgorithm::Blake2b,
+            &signature,
+            b"Another message",
+            &keypair.public_key
+        ));
+        // The challenge differs, so a SHA-512 verifier rejects it
+        assert!(!SchnorrSignature::verify(
+            &signature,
+            message,
+            &keypair.public_key
+        ));
+    }
+
+    #[test]
+    fn test_compute_challenge_matches_verification() {
+        let keypair = KeyPair::from_seed(7);
+        let message = b"Interop challenge";
+        let signature = SchnorrSignature::sign(message, &keypair.private_key).unwrap();
+
+        let e = SchnorrSignature::compute_challenge(&signature.R, message);
+        assert_eq!(
+            signature.s * RISTRETTO_BASEPOINT_POINT,
+            signature.R + e * keypair.public_key,
+            "The exposed challenge should satisfy the verification equation"
+        );
+        let mut hasher = Sha512::new();
+        hasher.update(signature.R.compress().as_bytes());
+        hasher.update(message);
+        assert_eq!(e, Scalar::from_hash(hasher));
+
+        let e_with_key = SchnorrSignature::compute_challenge_with_key(
+            &signature.R,
+            &keypair.public_key,
+            message,
+        );
+        assert_ne!(e_with_key, e);
+    }
+
+    #[test]
+    fn test_detached_signature_over_bytes() {
+        let keypair = KeyPair::generate().unwrap();
+        let data = b"Release manifest v1.2.3".to_vec();
+
+        let signature = sign_bytes(&data, &keypair.private_key).unwrap();
+        assert!(verify_bytes(&data, &signature, &keypair.public_key));
+
+        let mut altered = data.clone();
+        altered[0] ^= 0x01;
+        assert!(!verify_bytes(&altered, &signature, &keypair.public_key));
+        assert!(!verify_bytes(
+            &data,
+            &signature,
+            &KeyPair::generate().unwrap().public_key
+        ));
+    }
 }
@@ -0,0 +1,140 @@
+//! Start-up self-test: runs every primitive once on fresh keys, so a broken build or
+//! platform (miscompiled field arithmetic, a stuck RNG) shows up before real data is sealed.
+
+use crate::aes::{AESCiphertext, AES_KEY_SIZE};
+use crate::elgamal::ElGamalCiphertext;
+use crate::error::{CryptoError, MessageError};
+use crate::keys::{secure_rng, KeyPair};
+use crate::message::{Message, UNSET_SENDER};
+use crate::schnorr::SchnorrSignature;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+use rand::RngCore;
+
+const SELF_TEST_PLAINTEXT: &[u8] = b"secure-channel self-test";
+
+/// The steps run by `self_test`, reported in `CryptoError::SelfTestFailed`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestStep {
+    KeyGeneration,
+    Signature,
+    ElGamal,
+    Aes,
+    Message,
+}
+
+/// Runs a key generation, a signature, an ElGamal and an AES round trip and a full message
+/// seal and open, and fails with the first step that doesn't give the expected result
+pub fn self_test() -> Result<(), CryptoError> {
+    run_self_test(None)
+}
+
+/// Like `self_test`, but corrupts the result of `step`, to check that callers handle a
+/// failing self-test
+#[cfg(any(test, feature = "test-utils"))]
+pub fn self_test_with_fault(step: SelfTestStep) -> Result<(), CryptoError> {
+    run_self_test(Some(step))
+}
+
+fn run_self_test(fault: Option<SelfTestStep>) -> Result<(), CryptoError> {
+    // Flips the first byte of a step's output when that step is the injected fault
+    let output = |step: SelfTestStep, mut bytes: Vec<u8>| {
+        if fault == Some(step) {
+            bytes[0] ^= 0x01;
+        }
+        bytes
+    };
+    let check = |step: SelfTestStep, passed: bool| {
+        if passed {
+            Ok(())
+        } else {
+            Err(CryptoError::SelfTestFailed(step))
+        }
+    };
+
+    let keys = KeyPair::generate_checked()?;
+    let recomputed = keys.private_key * RISTRETTO_BASEPOINT_POINT;
+    check(
+        SelfTestStep::KeyGeneration,
+        output(
+            SelfTestStep::KeyGeneration,
+            recomputed.compress().to_bytes().to_vec(),
+        ) == keys.public_key.compress().to_bytes(),
+    )?;
+
+    let signature = SchnorrSignature::sign(SELF_TEST_PLAINTEXT, &keys.private_key)?;
+    let signed = output(SelfTestStep::Signature, SELF_TEST_PLAINTEXT.to_vec());
+    check(
+        SelfTestStep::Signature,
+        SchnorrSignature::verify(&signature, &signed, &keys.public_key)
+            && !SchnorrSignature::verify(&signature, b"other message", &keys.public_key),
+    )?;
+
+    let mut rng = secure_rng()?;
+    let scalar_key = Scalar::random(&mut rng);
+    let decrypted_key =
+        ElGamalCiphertext::encrypt(&scalar_key, &keys.public_key)?.decrypt(&keys.private_key);
+    check(
+        SelfTestStep::ElGamal,
+        output(SelfTestStep::ElGamal, decrypted_key.to_bytes().to_vec()) == scalar_key.to_bytes(),
+    )?;
+
+    let mut aes_key = [0u8; AES_KEY_SIZE];
+    rng.fill_bytes(&mut aes_key);
+    let aes_ciphertext = AESCiphertext::encrypt_with_key(&aes_key, SELF_TEST_PLAINTEXT)
+        .map_err(|_| CryptoError::SelfTestFailed(SelfTestStep::Aes))?;
+    let aes_plaintext = AESCiphertext::decrypt_with_key(&aes_key, &aes_ciphertext)
+        .map_err(|_| CryptoError::SelfTestFailed(SelfTestStep::Aes))?;
+    check(
+        SelfTestStep::Aes,
+        output(SelfTestStep::Aes, aes_plaintext) == SELF_TEST_PLAINTEXT,
+    )?;
+
+    let opened =
+        seal_and_open(&keys).map_err(|_| CryptoError::SelfTestFailed(SelfTestStep::Message))?;
+    check(
+        SelfTestStep::Message,
+        output(SelfTestStep::Message, opened) == SELF_TEST_PLAINTEXT,
+    )
+}
+
+/// Seals a message to and from `keys` and opens it again, returning the payload
+fn seal_and_open(keys: &KeyPair) -> Result<Vec<u8>, MessageError> {
+    let mut message = Message::new(
+        0,
+        SELF_TEST_PLAINTEXT.to_vec(),
+        CompressedRistretto(UNSET_SENDER),
+        keys.public_key.compress(),
+        SchnorrSignature::emty_signature(),
+    );
+    message.seal(&keys.public_key, &keys.private_key)?;
+    message.open(&keys.public_key, &keys.private_key)?;
+    Ok(message.payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_test_passes() {
+        assert_eq!(self_test(), Ok(()));
+    }
+
+    #[test]
+    fn test_self_test_reports_the_failing_step() {
+        for step in [
+            SelfTestStep::KeyGeneration,
+            SelfTestStep::Signature,
+            SelfTestStep::ElGamal,
+            SelfTestStep::Aes,
+            SelfTestStep::Message,
+        ] {
+            assert_eq!(
+                self_test_with_fault(step),
+                Err(CryptoError::SelfTestFailed(step))
+            );
+        }
+    }
+}
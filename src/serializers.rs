@@ -1,27 +1,304 @@
 #![allow(non_snake_case)]
+use crate::message::{message_from_json, Message, SignatureFormat, BROADCAST_RECIPIENT};
 use crate::schnorr::SchnorrSignature;
-use crate::message::Message;
+use base64::engine::GeneralPurpose;
 use base64::prelude::*;
 
-use serde::de::Error;
-use serde::{ser::SerializeMap, Serializer};
-use serde::{Deserialize, Deserializer};
+use serde::de::{Error, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeStruct, SerializeTuple};
+use serde::Serializer;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::cell::Cell;
+use std::fmt;
+
+/// Largest decoded size of a single byte field accepted by default
+pub const DEFAULT_MAX_DECODED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Limits applied while deserializing byte fields, see `with_decode_options`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Fields decoding to more bytes are rejected before decoding, so a huge Base64 string
+    /// can't exhaust memory
+    pub max_decoded_size: usize,
+    /// Strip whitespace and line breaks from Base64 strings before decoding, for
+    /// hand-edited JSON. Off by default, strict decoding rejects them.
+    pub lenient_whitespace: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            max_decoded_size: DEFAULT_MAX_DECODED_SIZE,
+            lenient_whitespace: false,
+        }
+    }
+}
+
+thread_local! {
+    static DECODE_OPTIONS: Cell<DecodeOptions> = Cell::new(DecodeOptions::default());
+}
+
+/// Runs `f` with `options` applied to every byte field deserialized on this thread,
+/// e.g. around `Message::from_json`. The previous options are restored afterwards.
+pub fn with_decode_options<T>(options: DecodeOptions, f: impl FnOnce() -> T) -> T {
+    struct Restore(DecodeOptions);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            DECODE_OPTIONS.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(DECODE_OPTIONS.with(|current| current.replace(options)));
+    f()
+}
+
+fn decode_options() -> DecodeOptions {
+    DECODE_OPTIONS.with(Cell::get)
+}
+
+/// Base64 alphabets for byte fields in human-readable formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Base64Alphabet {
+    /// `+` and `/`, with padding (the default)
+    #[default]
+    Standard,
+    /// `-` and `_`, with padding
+    UrlSafe,
+}
+
+impl Base64Alphabet {
+    fn engine(self) -> &'static GeneralPurpose {
+        match self {
+            Base64Alphabet::Standard => &BASE64_STANDARD,
+            Base64Alphabet::UrlSafe => &BASE64_URL_SAFE,
+        }
+    }
+}
+
+/// Base64 alphabet of each kind of byte field, for exchanging messages with systems that
+/// mix alphabets. Applies to writing and parsing, see `with_base64_config`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Base64Config {
+    pub payload: Base64Alphabet,   // The message payload
+    pub signature: Base64Alphabet, // R and s of signatures, also in certificates and timestamps
+    pub other: Base64Alphabet,     // Keys, attachments and every other byte field
+}
+
+/// Kinds of byte fields that `Base64Config` can give different alphabets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Base64Field {
+    Payload,
+    Signature,
+    Other,
+}
+
+impl Base64Config {
+    fn alphabet(&self, field: Base64Field) -> Base64Alphabet {
+        match field {
+            Base64Field::Payload => self.payload,
+            Base64Field::Signature => self.signature,
+            Base64Field::Other => self.other,
+        }
+    }
+}
+
+thread_local! {
+    static BASE64_CONFIG: Cell<Base64Config> = Cell::new(Base64Config::default());
+}
+
+/// Runs `f` with the alphabets of `config` used for every byte field serialized or
+/// deserialized on this thread, e.g. around `Message::from_json`. The previous
+/// configuration is restored afterwards.
+pub fn with_base64_config<T>(config: Base64Config, f: impl FnOnce() -> T) -> T {
+    struct Restore(Base64Config);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            BASE64_CONFIG.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(BASE64_CONFIG.with(|current| current.replace(config)));
+    f()
+}
+
+fn base64_engine(field: Base64Field) -> &'static GeneralPurpose {
+    BASE64_CONFIG.with(Cell::get).alphabet(field).engine()
+}
+
+/// Rejects a field whose decoded size is above the configured limit
+fn check_decoded_size<E: Error>(size: usize) -> Result<(), E> {
+    let limit = decode_options().max_decoded_size;
+    if size > limit {
+        return Err(E::custom(format!(
+            "Field of {} bytes exceeds the limit of {} bytes",
+            size, limit
+        )));
+    }
+    Ok(())
+}
+
+/// Serializes bytes as a Base64 string for human-readable formats like JSON,
+/// and as native binary for binary formats like MessagePack
+fn serialize_bytes_field<S>(
+    bytes: &[u8],
+    field: Base64Field,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&base64_engine(field).encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Counterpart of `serialize_bytes_field`
+fn deserialize_bytes_field<'de, D>(deserializer: D, field: Base64Field) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_str(Base64Visitor(field))
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+/// Decodes a Base64 string in the alphabet configured for the field, checking the decoded
+/// size before decoding
+struct Base64Visitor(Base64Field);
+
+impl Visitor<'_> for Base64Visitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a Base64 string")
+    }
+
+    fn visit_str<E: Error>(self, base64_str: &str) -> Result<Vec<u8>, E> {
+        if decode_options().lenient_whitespace
+            && base64_str.bytes().any(|b| b.is_ascii_whitespace())
+        {
+            let stripped: String = base64_str
+                .chars()
+                .filter(|c| !c.is_ascii_whitespace())
+                .collect();
+            return self.visit_str(&stripped);
+        }
+        let padding = base64_str.bytes().rev().take_while(|&b| b == b'=').count();
+        check_decoded_size((base64_str.len() / 4 * 3).saturating_sub(padding))?;
+        // Convert Base64 string back to bytes
+        base64_engine(self.0).decode(base64_str).map_err(E::custom)
+    }
+}
+
+/// Accepts binary data, or a sequence of bytes for formats without a binary type
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array")
+    }
+
+    fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        check_decoded_size(bytes.len())?;
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        check_decoded_size(bytes.len())?;
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let limit = decode_options().max_decoded_size;
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(limit));
+        while let Some(byte) = seq.next_element()? {
+            check_decoded_size(bytes.len() + 1)?;
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Wraps signature bytes so they can be serialized as a map value with `serialize_bytes_field`
+struct EncodedBytes<'a>(&'a [u8]);
+
+impl Serialize for EncodedBytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes_field(self.0, Base64Field::Signature, serializer)
+    }
+}
+
+/// Counterpart of `EncodedBytes`, decodes a value with `deserialize_bytes_field`
+struct DecodedBytes(Vec<u8>);
+
+impl<'de> Deserialize<'de> for DecodedBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_bytes_field(deserializer, Base64Field::Signature).map(DecodedBytes)
+    }
+}
 
 /// Serialize Vec<u8> as a Base64 string
-pub fn serialize_base64<S>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error>
+pub fn serialize_base64<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    let base64_str = BASE64_STANDARD.encode(bytes); // Convert bytes to Base64 string
-    serializer.serialize_str(&base64_str)
+    serialize_bytes_field(bytes, Base64Field::Other, serializer)
+}
+
+/// Like `serialize_base64`, but hands the encoder to the serializer with `collect_str`
+/// instead of building the whole Base64 string first. Serializers that write `collect_str`
+/// output straight to their sink, like serde_json, then encode the bytes in small chunks,
+/// which saves a copy of the field the size of 4/3 of its bytes. Serializers that keep the
+/// default `collect_str` buffer the string anyway, so it only helps for large fields
+/// written with serde_json, e.g. the payload in `Message::to_file`.
+pub fn serialize_base64_streaming<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_streaming_field(bytes, Base64Field::Other, serializer)
+}
+
+/// `serialize_base64_streaming` for the message payload, in the payload's alphabet
+pub fn serialize_payload_base64<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serialize_streaming_field(bytes, Base64Field::Payload, serializer)
+}
+
+fn serialize_streaming_field<S>(
+    bytes: &[u8],
+    field: Base64Field,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.collect_str(&base64::display::Base64Display::new(
+            bytes,
+            base64_engine(field),
+        ))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
 }
-// Base64 serialize function for [u8; 32]
-pub fn serialize_fixed_base64<S>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
+
+// Base64 serialize function for fixed-size arrays such as [u8; 32]
+pub fn serialize_fixed_base64<S, const N: usize>(
+    bytes: &[u8; N],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    let base64_str = BASE64_STANDARD.encode(bytes); // Convert to base64 string
-    serializer.serialize_str(&base64_str) // Serialize as string
+    serialize_bytes_field(bytes, Base64Field::Other, serializer)
 }
 
 /// Deserialize Base64 string back into Vec<u8>
@@ -29,32 +306,58 @@ pub fn deserialize_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let base64_str = String::deserialize(deserializer)?; // Deserialize as string
-    BASE64_STANDARD
-        .decode(&base64_str)
-        .map_err(serde::de::Error::custom) // Convert Base64 string back to bytes
+    deserialize_bytes_field(deserializer, Base64Field::Other)
+}
+
+/// Counterpart of `serialize_payload_base64`
+pub fn deserialize_payload_base64<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_bytes_field(deserializer, Base64Field::Payload)
 }
 
-/// Deserialize Base64 string back into [08;32]
-pub fn deserialize_fixed_base64<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
+/// Deserialize Base64 string back into a fixed-size array such as [u8; 32]
+pub fn deserialize_fixed_base64<'de, D, const N: usize>(
+    deserializer: D,
+) -> Result<[u8; N], D::Error>
 where
     D: Deserializer<'de>,
 {
-    let base64_str = String::deserialize(deserializer)?; // Deserialize as string
-    let bytes = BASE64_STANDARD
-        .decode(&base64_str)
-        .map_err(serde::de::Error::custom)?; // Convert Base64 string back to bytes
+    let bytes = deserialize_bytes_field(deserializer, Base64Field::Other)?;
 
-    // Ensure the length is exactly 32 bytes
-    if bytes.len() == 32 {
-        let mut array = [0u8; 32];
-        array.copy_from_slice(&bytes);
-        Ok(array)
+    // Ensure the length is exactly N bytes
+    bytes
+        .try_into()
+        .map_err(|_| serde::de::Error::custom("Invalid length for byte array"))
+}
+
+/// Serialize a recipient key as Base64, or as an empty string for broadcast messages
+pub fn serialize_recipient_base64<S>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if *bytes == BROADCAST_RECIPIENT {
+        serialize_bytes_field(&[], Base64Field::Other, serializer)
     } else {
-        Err(serde::de::Error::custom("Invalid length for byte array"))
+        serialize_fixed_base64(bytes, serializer)
     }
 }
 
+/// Deserialize a recipient key, mapping the empty string back to the broadcast recipient
+pub fn deserialize_recipient_base64<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bytes = deserialize_bytes_field(deserializer, Base64Field::Other)?;
+    if bytes.is_empty() {
+        return Ok(BROADCAST_RECIPIENT);
+    }
+    bytes
+        .try_into()
+        .map_err(|_| serde::de::Error::custom("Invalid length for byte array"))
+}
+
 // Serializer for `SchnorrSignature`
 pub fn serialize_schnorr_signature<S>(
     signature: &SchnorrSignature,
@@ -66,48 +369,173 @@ where
     let r_bytes = SchnorrSignature::point_to_bytes(&signature.R);
     let s_bytes = SchnorrSignature::scalar_to_bytes(&signature.s);
 
-    let mut map = serializer.serialize_map(Some(2))?;
-    map.serialize_entry("R", &BASE64_STANDARD.encode(&r_bytes))?;
-    map.serialize_entry("s", &BASE64_STANDARD.encode(&s_bytes))?;
+    // A struct is written as a map by the self-describing formats and positionally by bincode
+    let mut map = serializer.serialize_struct("SchnorrSignature", 2)?;
+    map.serialize_field("R", &EncodedBytes(&r_bytes))?;
+    map.serialize_field("s", &EncodedBytes(&s_bytes))?;
     map.end()
 }
 
-// Deserializer for `SchnorrSignature`
+/// Serializes a `SchnorrSignature` as a two-element array `[R, s]`, smaller than the map
+/// form and without depending on key names
+pub fn serialize_schnorr_signature_compact<S>(
+    signature: &SchnorrSignature,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let r_bytes = SchnorrSignature::point_to_bytes(&signature.R);
+    let s_bytes = SchnorrSignature::scalar_to_bytes(&signature.s);
+
+    let mut tuple = serializer.serialize_tuple(2)?;
+    tuple.serialize_element(&EncodedBytes(&r_bytes))?;
+    tuple.serialize_element(&EncodedBytes(&s_bytes))?;
+    tuple.end()
+}
+
+/// Parses the encoded R and s of a signature
+fn signature_from_parts<E: Error>(R: &[u8], s: &[u8]) -> Result<SchnorrSignature, E> {
+    let R = SchnorrSignature::bytes_to_point(R).map_err(E::custom)?;
+    let s = SchnorrSignature::bytes_to_scalar(s).map_err(E::custom)?;
+    Ok(SchnorrSignature { R, s })
+}
+
+/// Accepts a signature in either the map or the array form
+struct SchnorrSignatureVisitor;
+
+impl<'de> Visitor<'de> for SchnorrSignatureVisitor {
+    type Value = SchnorrSignature;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a signature map {R, s} or array [R, s]")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SchnorrSignature, A::Error> {
+        let (mut R, mut s) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            let field = match key.as_str() {
+                "R" => &mut R,
+                "s" => &mut s,
+                _ => return Err(A::Error::unknown_field(&key, &["R", "s"])),
+            };
+            if field.is_some() {
+                return Err(A::Error::custom(format!("duplicate field `{}`", key)));
+            }
+            *field = Some(map.next_value::<DecodedBytes>()?);
+        }
+        let R = R.ok_or_else(|| A::Error::missing_field("R"))?;
+        let s = s.ok_or_else(|| A::Error::missing_field("s"))?;
+        signature_from_parts(&R.0, &s.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SchnorrSignature, A::Error> {
+        let R: DecodedBytes = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let s: DecodedBytes = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+        if seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+            return Err(A::Error::invalid_length(3, &self));
+        }
+        signature_from_parts(&R.0, &s.0)
+    }
+}
+
+// Deserializer for `SchnorrSignature`, accepting both the map and the compact array form
 pub fn deserialize_schnorr_signature<'de, D>(deserializer: D) -> Result<SchnorrSignature, D::Error>
 where
     D: Deserializer<'de>,
 {
-    #[derive(Deserialize)]
-    struct SchnorrSignatureHelper {
-        R: String,
-        s: String,
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(SchnorrSignatureVisitor)
+    } else {
+        // Binary formats only ever carry the map form, and bincode can't decode `any`
+        deserializer.deserialize_struct("SchnorrSignature", &["R", "s"], SchnorrSignatureVisitor)
     }
+}
 
-    let helper = SchnorrSignatureHelper::deserialize(deserializer)?;
+/// Deserializer for signatures written by `serialize_schnorr_signature_compact`
+pub fn deserialize_schnorr_signature_compact<'de, D>(
+    deserializer: D,
+) -> Result<SchnorrSignature, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let (R, s) = <(DecodedBytes, DecodedBytes)>::deserialize(deserializer)?;
+    signature_from_parts(&R.0, &s.0)
+}
 
-    let r_bytes = BASE64_STANDARD
-        .decode(&helper.R)
-        .map_err(D::Error::custom)?;
-    let s_bytes = BASE64_STANDARD
-        .decode(&helper.s)
-        .map_err(D::Error::custom)?;
+/// Encodes bytes as a lowercase hex string, e.g. for message IDs in headers
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
-    let R = SchnorrSignature::bytes_to_point(&r_bytes).map_err(D::Error::custom)?;
-    let s = SchnorrSignature::bytes_to_scalar(&s_bytes).map_err(D::Error::custom)?;
+/// Serializes key fingerprints as a list of hex strings
+pub fn serialize_fingerprints<S>(fingerprints: &[[u8; 8]], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(fingerprints.iter().map(|fingerprint| to_hex(fingerprint)))
+}
 
-    Ok(SchnorrSignature { R, s })
+/// Deserializes a list of hex-encoded key fingerprints
+pub fn deserialize_fingerprints<'de, D>(deserializer: D) -> Result<Vec<[u8; 8]>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|hex| {
+            let bytes = (0..hex.len())
+                .step_by(2)
+                .map(|i| {
+                    hex.get(i..i + 2)
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| D::Error::custom("Invalid hex fingerprint"))?;
+            bytes
+                .try_into()
+                .map_err(|_| D::Error::custom("Invalid length for fingerprint"))
+        })
+        .collect()
+}
+
+/// Hex-encodes at most `max_bytes` bytes for display, marking truncated input with an
+/// ellipsis and its full length
+pub fn to_hex_truncated(bytes: &[u8], max_bytes: usize) -> String {
+    if bytes.len() <= max_bytes {
+        to_hex(bytes)
+    } else {
+        format!("{}... ({} bytes)", to_hex(&bytes[..max_bytes]), bytes.len())
+    }
 }
 
 /// Serializes the Message struct into a Vec<u8> (binary format).
 pub fn serialize_message_to_bytes(message: &Message) -> Result<Vec<u8>, String> {
     // Serialize the Message struct into a JSON string.
-    let json_string = serde_json::to_string(message)
-        .map_err(|e| format!("Failed to serialize message: {}", e))?;
+    let json_string = match message.signature_format {
+        SignatureFormat::Map => serde_json::to_string(message),
+        SignatureFormat::Array => {
+            message_to_json_value(message).and_then(|value| serde_json::to_string(&value))
+        }
+    }
+    .map_err(|e| format!("Failed to serialize message: {}", e))?;
 
     // Convert the JSON string into a Vec<u8>.
     Ok(json_string.into_bytes())
 }
 
+/// Converts the message to a JSON value with the signature in the compact array form
+pub(crate) fn message_to_json_value(message: &Message) -> serde_json::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(message)?;
+    value["signature"] =
+        serialize_schnorr_signature_compact(&message.signature, serde_json::value::Serializer)?;
+    Ok(value)
+}
+
 /// Deserializes the Message from a Vec<u8> back into the Message struct.
 pub fn deserialize_message_from_bytes(bytes: &[u8]) -> Result<Message, String> {
     // Convert the Vec<u8> back into a JSON string.
@@ -115,7 +543,7 @@ pub fn deserialize_message_from_bytes(bytes: &[u8]) -> Result<Message, String> {
         String::from_utf8(bytes.to_vec()).map_err(|e| format!("Invalid UTF-8 sequence: {}", e))?;
 
     // Deserialize the JSON string back into a Message struct.
-    serde_json::from_str(&json_string).map_err(|e| format!("Failed to deserialize message: {}", e))
+    message_from_json(&json_string).map_err(|e| format!("Failed to deserialize message: {}", e))
 }
 
 #[cfg(test)]
@@ -149,13 +577,153 @@ mod tests {
             .expect("Failed to deserialize bytes into message");
 
         // Verify that the original and deserialized messages match
-        assert_eq!(message.version, deserialized_message.version);
+        assert_eq!(message.format_version, deserialized_message.format_version);
         assert_eq!(message.payload, deserialized_message.payload);
         assert_eq!(message.recipient, deserialized_message.recipient);
         assert_eq!(message.sender, deserialized_message.sender);
         assert_eq!(message.signature, deserialized_message.signature);
 
-        // Display the deserialized message for visual verification
-        deserialized_message.display();
+        // The hex `Display` form shows the deserialized fields
+        assert_eq!(deserialized_message.to_string(), message.to_string());
+    }
+
+    #[test]
+    fn test_signature_map_and_array_forms_round_trip() {
+        let keypair = crate::keys::KeyPair::generate().unwrap();
+        let mut message = Message::new_broadcast(b"Signature formats".to_vec());
+        message.sign(&keypair.private_key).unwrap();
+
+        let map_form = serialize_message_to_bytes(&message).unwrap();
+        message.signature_format = SignatureFormat::Array;
+        let array_form = serialize_message_to_bytes(&message).unwrap();
+        assert!(
+            array_form.len() < map_form.len(),
+            "The array form should be smaller than the map form"
+        );
+
+        let value: serde_json::Value = serde_json::from_slice(&array_form).unwrap();
+        assert_eq!(value["signature"].as_array().map(Vec::len), Some(2));
+
+        for bytes in [map_form, array_form] {
+            let parsed = deserialize_message_from_bytes(&bytes).unwrap();
+            assert_eq!(parsed.signature, message.signature);
+            assert!(parsed.verify());
+        }
+
+        let compact =
+            serialize_schnorr_signature_compact(&message.signature, serde_json::value::Serializer)
+                .unwrap();
+        assert_eq!(
+            deserialize_schnorr_signature_compact(compact).unwrap(),
+            message.signature
+        );
+    }
+
+    #[test]
+    fn test_oversized_base64_is_rejected() {
+        let message = Message::new_broadcast(vec![0x42; 1024]);
+        let json = String::from_utf8(serialize_message_to_bytes(&message).unwrap()).unwrap();
+        let options = DecodeOptions {
+            max_decoded_size: 1023,
+            ..DecodeOptions::default()
+        };
+
+        let result =
+            with_decode_options(options, || deserialize_message_from_bytes(json.as_bytes()));
+        assert!(
+            result
+                .as_ref()
+                .is_err_and(|e| e.contains("exceeds the limit")),
+            "Expected a size error, got {:?}",
+            result.map(|m| m.payload.len())
+        );
+        assert_eq!(
+            decode_options(),
+            DecodeOptions::default(),
+            "Options should be restored"
+        );
+
+        let exact = DecodeOptions {
+            max_decoded_size: 1024,
+            ..DecodeOptions::default()
+        };
+        let parsed = with_decode_options(exact, || deserialize_message_from_bytes(json.as_bytes()));
+        assert_eq!(parsed.unwrap().payload.len(), 1024);
+    }
+
+    #[test]
+    fn test_lenient_decoding_strips_whitespace() {
+        let message = Message::new_broadcast(vec![0x42; 100]);
+        let json = String::from_utf8(serialize_message_to_bytes(&message).unwrap()).unwrap();
+        let encoded = BASE64_STANDARD.encode(&message.payload);
+        // JSON escapes for a line break with indentation and a CRLF
+        let wrapped = format!(
+            "{}\\n  {}\\r\\n{}",
+            &encoded[..40],
+            &encoded[40..80],
+            &encoded[80..]
+        );
+        let edited = json.replace(&encoded, &wrapped);
+        assert_ne!(edited, json);
+
+        assert!(
+            deserialize_message_from_bytes(edited.as_bytes()).is_err(),
+            "Strict decoding should reject whitespace"
+        );
+        let lenient = DecodeOptions {
+            lenient_whitespace: true,
+            ..DecodeOptions::default()
+        };
+        let parsed = with_decode_options(lenient, || {
+            deserialize_message_from_bytes(edited.as_bytes())
+        });
+        assert_eq!(parsed.unwrap().payload, message.payload);
+    }
+
+    #[test]
+    fn test_streaming_base64_matches_one_shot() {
+        // Odd length so the last chunk needs padding
+        let bytes: Vec<u8> = (0..1_000_003u32).map(|i| (i * 31 % 251) as u8).collect();
+
+        let mut streamed = Vec::new();
+        serialize_base64_streaming(&bytes, &mut serde_json::Serializer::new(&mut streamed))
+            .unwrap();
+        let one_shot = serde_json::to_vec(&BASE64_STANDARD.encode(&bytes)).unwrap();
+        assert_eq!(streamed, one_shot);
+    }
+
+    #[test]
+    fn test_mixed_base64_alphabets() {
+        // Encodes to "-_-_" in the URL-safe alphabet and "+/+/" in the standard one
+        let mut message = Message::new_broadcast([0xfb, 0xff, 0xbf].repeat(4));
+        message
+            .sign(&crate::keys::KeyPair::from_seed(1).private_key)
+            .unwrap();
+        let json = String::from_utf8(serialize_message_to_bytes(&message).unwrap()).unwrap();
+        let standard_payload = BASE64_STANDARD.encode(&message.payload);
+        let url_safe_payload = BASE64_URL_SAFE.encode(&message.payload);
+        let partner_json = json.replace(&standard_payload, &url_safe_payload);
+        assert_ne!(partner_json, json);
+
+        assert!(
+            deserialize_message_from_bytes(partner_json.as_bytes()).is_err(),
+            "The default alphabet should reject a URL-safe payload"
+        );
+        let config = Base64Config {
+            payload: Base64Alphabet::UrlSafe,
+            ..Base64Config::default()
+        };
+        let parsed = with_base64_config(config, || {
+            deserialize_message_from_bytes(partner_json.as_bytes())
+        })
+        .unwrap();
+        assert_eq!(parsed.payload, message.payload);
+        assert_eq!(parsed.signature, message.signature);
+        assert!(parsed.verify());
+
+        // Writing with the same configuration reproduces the partner's encoding
+        let written = with_base64_config(config, || serialize_message_to_bytes(&parsed)).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), partner_json);
+        assert_eq!(BASE64_CONFIG.with(Cell::get), Base64Config::default());
     }
 }
@@ -0,0 +1,144 @@
+//! Ordered messages under a shared session key. Every ciphertext authenticates the ID of
+//! the previous one as AAD, so the messages form a hash chain and a dropped, replayed or
+//! reordered message is detected on decryption.
+
+use crate::aes::{AESCiphertext, CipherContext, AES_KEY_SIZE};
+use crate::capabilities::AeadAlgorithm;
+use crate::error::MessageError;
+use sha2::{Digest, Sha256};
+
+/// Previous-message ID of the first message of a session
+pub const SESSION_START: [u8; 16] = [0u8; 16];
+
+/// One message of a session, with the ID of the message it follows
+pub struct SessionCiphertext {
+    pub previous_id: [u8; 16], // Also authenticated as AAD of `aes_ciphertext`
+    pub aes_ciphertext: AESCiphertext,
+}
+
+impl SessionCiphertext {
+    /// ID the next message of the session refers to: the first 16 bytes of SHA-256 over
+    /// the previous ID and the serialized ciphertext
+    pub fn id(&self) -> [u8; 16] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.previous_id);
+        hasher.update(self.aes_ciphertext.serialize());
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&hasher.finalize()[..16]);
+        id
+    }
+}
+
+/// Sending side of a session, chaining every message to the one sent before
+pub struct SessionEncryptor {
+    context: CipherContext,
+    previous_id: [u8; 16],
+}
+
+impl SessionEncryptor {
+    pub fn new(key: &[u8; AES_KEY_SIZE]) -> SessionEncryptor {
+        SessionEncryptor {
+            context: CipherContext::new(AeadAlgorithm::Aes256Gcm, key),
+            previous_id: SESSION_START,
+        }
+    }
+
+    pub fn encrypt(&mut self, message: &[u8]) -> Result<SessionCiphertext, MessageError> {
+        let aes_ciphertext = self
+            .context
+            .encrypt_with_aad(message, &self.previous_id)
+            .map_err(MessageError::Encryption)?;
+        let ciphertext = SessionCiphertext {
+            previous_id: self.previous_id,
+            aes_ciphertext,
+        };
+        self.previous_id = ciphertext.id();
+        Ok(ciphertext)
+    }
+}
+
+/// Receiving side of a session, accepting messages only in the order they were sent
+pub struct SessionDecryptor {
+    context: CipherContext,
+    expected_previous_id: [u8; 16],
+}
+
+impl SessionDecryptor {
+    pub fn new(key: &[u8; AES_KEY_SIZE]) -> SessionDecryptor {
+        SessionDecryptor {
+            context: CipherContext::new(AeadAlgorithm::Aes256Gcm, key),
+            expected_previous_id: SESSION_START,
+        }
+    }
+
+    /// Decrypts the next message. Fails with `SequenceBroken` if it doesn't follow the last
+    /// accepted message, in which case the expected position stays unchanged.
+    pub fn decrypt(&mut self, ciphertext: &SessionCiphertext) -> Result<Vec<u8>, MessageError> {
+        if ciphertext.previous_id != self.expected_previous_id {
+            return Err(MessageError::SequenceBroken);
+        }
+        let plaintext = self
+            .context
+            .decrypt_with_aad(&ciphertext.aes_ciphertext, &self.expected_previous_id)
+            .map_err(|e| MessageError::Decryption(e.to_string()))?;
+        self.expected_previous_id = ciphertext.id();
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; AES_KEY_SIZE] = [0x17; AES_KEY_SIZE];
+
+    fn session(messages: &[&[u8]]) -> Vec<SessionCiphertext> {
+        let mut encryptor = SessionEncryptor::new(&KEY);
+        messages
+            .iter()
+            .map(|message| encryptor.encrypt(message).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_in_order_sequence_decrypts() {
+        let messages: [&[u8]; 3] = [b"first", b"second", b"third"];
+        let ciphertexts = session(&messages);
+        let mut decryptor = SessionDecryptor::new(&KEY);
+
+        for (ciphertext, expected) in ciphertexts.iter().zip(messages) {
+            assert_eq!(decryptor.decrypt(ciphertext).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_dropped_or_reordered_message_breaks_sequence() {
+        let ciphertexts = session(&[b"first", b"second", b"third"]);
+
+        let mut decryptor = SessionDecryptor::new(&KEY);
+        decryptor.decrypt(&ciphertexts[0]).unwrap();
+        assert_eq!(
+            decryptor.decrypt(&ciphertexts[2]),
+            Err(MessageError::SequenceBroken),
+            "Dropping the second message should be detected"
+        );
+        assert_eq!(
+            decryptor.decrypt(&ciphertexts[0]),
+            Err(MessageError::SequenceBroken),
+            "Replaying the first message should be detected"
+        );
+        // The decryptor still waits for the second message
+        assert_eq!(decryptor.decrypt(&ciphertexts[1]).unwrap(), b"second");
+
+        // Relabeling the previous ID fails authentication
+        let mut relabeled = SessionDecryptor::new(&KEY);
+        let forged = SessionCiphertext {
+            previous_id: SESSION_START,
+            aes_ciphertext: ciphertexts[1].aes_ciphertext.clone(),
+        };
+        assert!(matches!(
+            relabeled.decrypt(&forged),
+            Err(MessageError::Decryption(_))
+        ));
+    }
+}
@@ -0,0 +1,159 @@
+//! Flips a byte in every envelope field of a signed and encrypted message and
+//! checks which layer catches it: the signature, the AEAD or the recipient check.
+
+use crate::error::MessageError;
+use crate::keys::KeyPair;
+use crate::message::{EncryptionMode, Message, MessageState};
+use crate::schnorr::SchnorrSignature;
+use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+use curve25519_dalek::ristretto::CompressedRistretto;
+use curve25519_dalek::scalar::Scalar;
+
+const PAYLOAD: &[u8] = b"Tamper test payload";
+
+// Offsets of the regions inside a serialized HybridCiphertext, after the 3-byte tagged
+// header marking the HKDF-derived AES key
+const C1_OFFSET: usize = 3;
+const C2_OFFSET: usize = 35;
+const NONCE_OFFSET: usize = 67;
+const AES_CIPHERTEXT_OFFSET: usize = 79;
+
+/// A named in-place modification of one envelope field
+type Mutation = (&'static str, fn(&mut Message));
+
+struct Parties {
+    sender: KeyPair,
+    recipient: KeyPair,
+    attacker: KeyPair,
+}
+
+/// Builds a message encrypted to the recipient and signed by the sender
+fn sealed_message() -> (Parties, Message) {
+    // Fixed keys keep failures reproducible
+    let parties = Parties {
+        sender: KeyPair::from_seed(1),
+        recipient: KeyPair::from_seed(2),
+        attacker: KeyPair::from_seed(3),
+    };
+
+    let mut message = Message::new(
+        0,
+        PAYLOAD.to_vec(),
+        CompressedRistretto::default(),
+        parties.recipient.public_key.compress(),
+        SchnorrSignature::emty_signature(),
+    );
+    message
+        .encrypt(&parties.recipient.public_key)
+        .expect("Encryption failed");
+    message.sign(&parties.sender.private_key).unwrap();
+    assert!(message.verify(), "Untampered message should verify");
+
+    (parties, message)
+}
+
+fn flip(bytes: &mut [u8], index: usize) {
+    bytes[index] ^= 0x01;
+}
+
+#[test]
+fn test_untampered_message_opens() {
+    let (parties, mut message) = sealed_message();
+
+    message
+        .decrypt(&parties.recipient.private_key)
+        .expect("Decryption failed");
+    assert_eq!(message.payload, PAYLOAD);
+}
+
+#[test]
+fn test_tampered_fields_break_signature() {
+    let mutations: Vec<Mutation> = vec![
+        ("format version", |m| m.format_version ^= 0x01),
+        ("state", |m| m.state = MessageState::Plain),
+        ("mode", |m| m.mode = EncryptionMode::Symmetric),
+        ("recipient", |m| flip(&mut m.recipient, 0)),
+        ("sender", |m| flip(&mut m.sender, 0)),
+        ("payload c1", |m| flip(&mut m.payload, C1_OFFSET)),
+        ("payload c2", |m| flip(&mut m.payload, C2_OFFSET)),
+        ("payload nonce", |m| flip(&mut m.payload, NONCE_OFFSET)),
+        ("payload ciphertext", |m| {
+            flip(&mut m.payload, AES_CIPHERTEXT_OFFSET)
+        }),
+        ("signature R", |m| {
+            m.signature.R += RISTRETTO_BASEPOINT_POINT
+        }),
+        ("signature s", |m| {
+            let mut s_bytes = m.signature.s.to_bytes();
+            flip(&mut s_bytes, 0);
+            m.signature.s = Scalar::from_bytes_mod_order(s_bytes);
+        }),
+    ];
+
+    for (field, mutate) in mutations {
+        let (_, mut message) = sealed_message();
+        mutate(&mut message);
+        assert!(
+            !message.verify(),
+            "Tampering with {} should invalidate the signature",
+            field
+        );
+    }
+}
+
+#[test]
+fn test_resigned_ciphertext_tampering_fails_authentication() {
+    // An attacker who re-signs the envelope gets past the signature check,
+    // so the AEAD has to catch modifications of the encrypted payload
+    for (region, index) in [
+        ("c2", C2_OFFSET),
+        ("nonce", NONCE_OFFSET),
+        ("ciphertext", AES_CIPHERTEXT_OFFSET),
+    ] {
+        let (parties, mut message) = sealed_message();
+        flip(&mut message.payload, index);
+        message.sign_force(&parties.attacker.private_key).unwrap();
+        assert!(message.verify(), "Re-signed message should verify");
+
+        let result = message.decrypt(&parties.recipient.private_key);
+        assert!(
+            matches!(result, Err(MessageError::Decryption(_))),
+            "Tampering with {} should fail authentication, got {:?}",
+            region,
+            result
+        );
+    }
+}
+
+#[test]
+fn test_resigned_recipient_tampering_is_rejected() {
+    let (parties, mut message) = sealed_message();
+
+    // Redirect the message to the attacker and re-sign it
+    message.recipient = parties.attacker.public_key.compress().to_bytes();
+    message.sign_force(&parties.attacker.private_key).unwrap();
+    assert!(message.verify(), "Re-signed message should verify");
+
+    assert_eq!(
+        message.clone().decrypt(&parties.recipient.private_key),
+        Err(MessageError::RecipientMismatch)
+    );
+    // Even the attacker can't read it, the ciphertext is still bound to the original recipient
+    assert!(matches!(
+        message.decrypt(&parties.attacker.private_key),
+        Err(MessageError::Decryption(_))
+    ));
+}
+
+#[test]
+fn test_resigned_mode_tampering_is_rejected() {
+    let (parties, mut message) = sealed_message();
+
+    message.mode = EncryptionMode::Symmetric;
+    message.sign_force(&parties.attacker.private_key).unwrap();
+
+    assert_eq!(
+        message.decrypt(&parties.recipient.private_key),
+        Err(MessageError::WrongMode(EncryptionMode::Symmetric))
+    );
+}
@@ -0,0 +1,226 @@
+//! Time-lock puzzles after Rivest, Shamir and Wagner: a key locked behind `difficulty`
+//! sequential squarings modulo an RSA modulus.
+//!
+//! The creator knows the factors of the modulus and locks a key in two exponentiations.
+//! Everyone else has to do the squarings one after the other, so more cores don't help.
+//! The difficulty maps only roughly to wall-clock time. A 2048-bit squaring takes about
+//! a microsecond on a current desktop CPU, so a difficulty of 1_000_000 takes about a second
+//! there. Faster hardware opens the puzzle sooner, so calibrate on the fastest machine an
+//! early reader could use.
+
+use crate::error::CryptoError;
+use crate::keys::secure_rng;
+use num_bigint::{BigUint, RandBigInt};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+/// Size of the RSA modulus. Factoring it skips the squarings, so it has to stay out of reach
+const MODULUS_BITS: u64 = 2048;
+
+/// Miller-Rabin rounds for the prime factors, each lets a composite through with chance <= 1/4
+const MILLER_RABIN_ROUNDS: usize = 40;
+
+/// Small primes ruling out most candidates before the Miller-Rabin test
+const SMALL_PRIMES: [u32; 24] = [
+    3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+/// Domain separator of the hash turning the puzzle solution into a key mask
+const MASK_DOMAIN: &[u8] = b"secure-channel/timelock-mask";
+
+/// Size of the fixed part of a serialized puzzle: difficulty, locked key and modulus length
+const HEADER_SIZE: usize = 8 + 32 + 2;
+
+/// Key locked behind a sequential-squaring puzzle, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimelockPuzzle {
+    pub difficulty: u64, // Number of sequential squarings needed to solve the puzzle
+    modulus: BigUint,
+    base: BigUint,
+    locked_key: [u8; 32], // The key XORed with a hash of the solution
+}
+
+impl TimelockPuzzle {
+    /// Locks `key` behind `difficulty` squarings modulo a fresh 2048-bit modulus
+    pub fn lock(key: &[u8; 32], difficulty: u64) -> Result<TimelockPuzzle, CryptoError> {
+        TimelockPuzzle::lock_with_modulus_bits(key, difficulty, MODULUS_BITS)
+    }
+
+    fn lock_with_modulus_bits(
+        key: &[u8; 32],
+        difficulty: u64,
+        modulus_bits: u64,
+    ) -> Result<TimelockPuzzle, CryptoError> {
+        let mut rng = secure_rng()?;
+        let p = random_prime(&mut rng, modulus_bits / 2);
+        let q = loop {
+            let q = random_prime(&mut rng, modulus_bits / 2);
+            if q != p {
+                break q;
+            }
+        };
+        let modulus = &p * &q;
+        let phi = (p - 1u32) * (q - 1u32);
+        let base = rng.gen_biguint_range(&BigUint::from(2u32), &modulus);
+
+        // The trapdoor: knowing phi(n), 2^difficulty reduces to an exponent below phi(n)
+        let exponent = BigUint::from(2u32).modpow(&BigUint::from(difficulty), &phi);
+        let solution = base.modpow(&exponent, &modulus);
+
+        Ok(TimelockPuzzle {
+            difficulty,
+            locked_key: xor_mask(key, &solution),
+            modulus,
+            base,
+        })
+    }
+
+    /// Recovers the key by squaring `difficulty` times, this is the slow part
+    pub fn solve(&self) -> [u8; 32] {
+        let mut value = self.base.clone();
+        for _ in 0..self.difficulty {
+            value = &value * &value % &self.modulus;
+        }
+        xor_mask(&self.locked_key, &value)
+    }
+
+    /// Serializes the puzzle as difficulty || locked key || modulus length || modulus || base
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let modulus = self.modulus.to_bytes_be();
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + 2 * modulus.len());
+        bytes.extend_from_slice(&self.difficulty.to_le_bytes());
+        bytes.extend_from_slice(&self.locked_key);
+        bytes.extend_from_slice(&(modulus.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&modulus);
+        bytes.extend_from_slice(&self.base.to_bytes_be());
+        bytes
+    }
+
+    /// Parses a puzzle written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<TimelockPuzzle, CryptoError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(CryptoError::Truncated {
+                minimum: HEADER_SIZE,
+                actual: bytes.len(),
+            });
+        }
+        let difficulty = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let locked_key: [u8; 32] = bytes[8..40].try_into().unwrap();
+        let modulus_length = u16::from_le_bytes([bytes[40], bytes[41]]) as usize;
+        let rest = &bytes[HEADER_SIZE..];
+        if rest.len() < modulus_length {
+            return Err(CryptoError::Truncated {
+                minimum: HEADER_SIZE + modulus_length,
+                actual: bytes.len(),
+            });
+        }
+
+        let modulus = BigUint::from_bytes_be(&rest[..modulus_length]);
+        let base = BigUint::from_bytes_be(&rest[modulus_length..]);
+        if modulus <= BigUint::from(2u32) || base >= modulus {
+            return Err(CryptoError::InvalidPuzzle);
+        }
+        Ok(TimelockPuzzle {
+            difficulty,
+            modulus,
+            base,
+            locked_key,
+        })
+    }
+}
+
+/// XORs `key` with a hash of the puzzle solution, locking or unlocking it
+fn xor_mask(key: &[u8; 32], solution: &BigUint) -> [u8; 32] {
+    let mask = Sha256::new()
+        .chain_update(MASK_DOMAIN)
+        .chain_update(solution.to_bytes_be())
+        .finalize();
+    let mut masked = *key;
+    for (byte, mask_byte) in masked.iter_mut().zip(mask) {
+        *byte ^= mask_byte;
+    }
+    masked
+}
+
+/// Random prime with exactly `bits` bits and the top two bits set, so the product
+/// of two such primes has exactly twice as many bits
+fn random_prime(rng: &mut OsRng, bits: u64) -> BigUint {
+    loop {
+        let mut candidate = rng.gen_biguint(bits);
+        candidate.set_bit(bits - 1, true);
+        candidate.set_bit(bits - 2, true);
+        candidate.set_bit(0, true);
+        if is_probable_prime(rng, &candidate) {
+            return candidate;
+        }
+    }
+}
+
+/// Miller-Rabin test with random bases, for odd candidates above the small primes
+fn is_probable_prime(rng: &mut OsRng, candidate: &BigUint) -> bool {
+    if SMALL_PRIMES
+        .iter()
+        .any(|&prime| (candidate % prime) == BigUint::ZERO)
+    {
+        return false;
+    }
+
+    let one = BigUint::from(1u32);
+    let candidate_minus_one = candidate - &one;
+    let shift = candidate_minus_one
+        .trailing_zeros()
+        .expect("Candidate is odd and above 1");
+    let odd_part = &candidate_minus_one >> shift;
+
+    'witness: for _ in 0..MILLER_RABIN_ROUNDS {
+        let witness = rng.gen_biguint_range(&BigUint::from(2u32), &candidate_minus_one);
+        let mut x = witness.modpow(&odd_part, candidate);
+        if x == one || x == candidate_minus_one {
+            continue;
+        }
+        for _ in 1..shift {
+            x = &x * &x % candidate;
+            if x == candidate_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solving_recovers_the_key() {
+        let key = [0x42u8; 32];
+        let puzzle = TimelockPuzzle::lock_with_modulus_bits(&key, 1_000, 512).unwrap();
+
+        assert_ne!(puzzle.locked_key, key);
+        assert_eq!(puzzle.solve(), key);
+
+        let parsed = TimelockPuzzle::from_bytes(&puzzle.to_bytes()).unwrap();
+        assert_eq!(parsed, puzzle);
+
+        // Skipping a single squaring yields garbage
+        let shortcut = TimelockPuzzle {
+            difficulty: 999,
+            ..puzzle
+        };
+        assert_ne!(shortcut.solve(), key);
+    }
+
+    #[test]
+    fn test_primality_test() {
+        let rng = &mut secure_rng().unwrap();
+        assert!(is_probable_prime(rng, &BigUint::from(1_000_000_007u32)));
+        assert!(!is_probable_prime(
+            rng,
+            &BigUint::from(1_000_000_007u64 * 998_244_353)
+        ));
+        // 561 = 3 * 11 * 17 is a Carmichael number
+        assert!(!is_probable_prime(rng, &BigUint::from(561u32)));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::error::{CryptoError, MessageError};
+use crate::schnorr::SchnorrSignature;
+use crate::serializers::*;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use curve25519_dalek::scalar::Scalar;
+use serde::{Deserialize, Serialize};
+
+/// Domain separator prepended to the bytes covered by a timestamp signature
+const TIMESTAMP_DOMAIN: &[u8] = b"secure-channel/timestamp";
+
+/// Statement by a timestamping authority that a message with `message_id` existed at `time`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimestampToken {
+    #[serde(
+        serialize_with = "serialize_fixed_base64",
+        deserialize_with = "deserialize_fixed_base64"
+    )]
+    pub message_id: [u8; 16], // `Message::message_id` of the timestamped message
+    pub time: u64, // Unix time in seconds attested by the authority
+    #[serde(
+        serialize_with = "serialize_schnorr_signature",
+        deserialize_with = "deserialize_schnorr_signature"
+    )]
+    pub signature: SchnorrSignature,
+}
+
+impl TimestampToken {
+    /// Issues a token for `message_id`, signed with the authority's private key
+    pub fn issue(
+        authority_key: &Scalar,
+        message_id: &[u8; 16],
+        time: u64,
+    ) -> Result<TimestampToken, CryptoError> {
+        let mut token = TimestampToken {
+            message_id: *message_id,
+            time,
+            signature: SchnorrSignature::emty_signature(),
+        };
+        token.signature = SchnorrSignature::sign(&token.signed_bytes(), authority_key)?;
+        Ok(token)
+    }
+
+    /// Bytes covered by the authority's signature
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(TIMESTAMP_DOMAIN.len() + 24);
+        buffer.extend_from_slice(TIMESTAMP_DOMAIN);
+        buffer.extend_from_slice(&self.message_id);
+        buffer.extend_from_slice(&self.time.to_le_bytes());
+        buffer
+    }
+
+    /// Checks that the token is signed by `authority` and covers `message_id`
+    pub fn verify(
+        &self,
+        authority: &RistrettoPoint,
+        message_id: &[u8; 16],
+    ) -> Result<(), MessageError> {
+        if self.message_id != *message_id
+            || !SchnorrSignature::verify(&self.signature, &self.signed_bytes(), authority)
+        {
+            return Err(MessageError::InvalidTimestamp);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyPair;
+
+    #[test]
+    fn test_timestamp_token_verification() {
+        let authority = KeyPair::from_seed(1);
+        let message_id = [0x42; 16];
+
+        let mut token = TimestampToken::issue(&authority.private_key, &message_id, 1_000).unwrap();
+        assert_eq!(token.verify(&authority.public_key, &message_id), Ok(()));
+        assert_eq!(
+            token.verify(&KeyPair::from_seed(2).public_key, &message_id),
+            Err(MessageError::InvalidTimestamp)
+        );
+
+        // Backdating invalidates the authority's signature
+        token.time = 500;
+        assert_eq!(
+            token.verify(&authority.public_key, &message_id),
+            Err(MessageError::InvalidTimestamp)
+        );
+    }
+}
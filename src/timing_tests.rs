@@ -0,0 +1,96 @@
+//! Coarse timing checks for the constant-time comparisons. Not a side-channel analysis:
+//! they only catch gross regressions such as an early return on the first differing byte.
+//! Wall-clock ratios are unreliable on loaded CI or under coverage and sanitizer builds, so
+//! the tests are ignored by default: run them with `cargo test timing -- --ignored`.
+
+use crate::keys::public_keys_equal;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+/// Timing samples taken per secret input, the median of them is compared
+const SAMPLES: usize = 101;
+
+/// Ratio between the slowest and the fastest secret input that counts as data-dependent.
+/// Loose on purpose, so scheduler noise on a busy machine doesn't fail the tests.
+const VARIANCE_THRESHOLD: f64 = 3.0;
+
+/// Runs `op` on each of `secrets` many times and returns the ratio of the slowest to the
+/// fastest median running time, 1.0 for an operation that doesn't depend on the secret.
+/// Rounds go over all secrets in turn, so a slow phase of the machine hits each of them.
+fn timing_variance<T>(secrets: &[T], op: impl Fn(&T)) -> f64 {
+    let mut timings: Vec<Vec<Duration>> = vec![Vec::with_capacity(SAMPLES); secrets.len()];
+    for _ in 0..SAMPLES {
+        for (secret, samples) in secrets.iter().zip(&mut timings) {
+            let start = Instant::now();
+            op(black_box(secret));
+            samples.push(start.elapsed());
+        }
+    }
+
+    let medians: Vec<f64> = timings
+        .iter_mut()
+        .map(|samples| {
+            samples.sort();
+            samples[SAMPLES / 2].as_nanos().max(1) as f64
+        })
+        .collect();
+    let slowest = medians.iter().cloned().fold(f64::MIN, f64::max);
+    let fastest = medians.iter().cloned().fold(f64::MAX, f64::min);
+    slowest / fastest
+}
+
+/// Long inputs differing in the first byte, in the last byte or not at all, so an
+/// early return shows up well above the timer resolution
+fn comparison_inputs() -> (Vec<u8>, Vec<Vec<u8>>) {
+    let reference = vec![0xa5u8; 64 * 1024];
+    let mut first_differs = reference.clone();
+    first_differs[0] ^= 0x01;
+    let mut last_differs = reference.clone();
+    *last_differs.last_mut().unwrap() ^= 0x01;
+    let candidates = vec![first_differs, last_differs, reference.clone()];
+    (reference, candidates)
+}
+
+#[test]
+#[ignore = "wall-clock timing, run on an idle machine"]
+fn test_ct_eq_timing_is_flat_while_eq_is_not() {
+    let (reference, candidates) = comparison_inputs();
+
+    let constant_time = timing_variance(&candidates, |candidate| {
+        black_box(bool::from(reference.ct_eq(candidate)));
+    });
+    let early_return = timing_variance(&candidates, |candidate| {
+        black_box(reference == *candidate);
+    });
+
+    assert!(
+        constant_time < VARIANCE_THRESHOLD,
+        "ct_eq timing depends on the input: ratio {:.2}",
+        constant_time
+    );
+    assert!(
+        early_return > VARIANCE_THRESHOLD,
+        "== should return early on the first difference: ratio {:.2}",
+        early_return
+    );
+}
+
+#[test]
+#[ignore = "wall-clock timing, run on an idle machine"]
+fn test_public_key_comparison_timing() {
+    let reference = [0x5au8; 32];
+    let mut first_differs = reference;
+    first_differs[0] ^= 0x01;
+    let mut last_differs = reference;
+    last_differs[31] ^= 0x01;
+
+    let variance = timing_variance(&[first_differs, last_differs, reference], |candidate| {
+        black_box(public_keys_equal(&reference, candidate));
+    });
+    assert!(
+        variance < VARIANCE_THRESHOLD,
+        "public_keys_equal timing depends on the input: ratio {:.2}",
+        variance
+    );
+}
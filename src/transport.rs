@@ -0,0 +1,157 @@
+//! Length-prefixed framing of messages over a byte stream. Every frame is a 4-byte
+//! big-endian length, a 1-byte `SerializationFormat` tag and the serialized message,
+//! so clients using different formats can talk to the same endpoint.
+
+use crate::error::MessageError;
+use crate::message::{Message, SerializationFormat};
+use std::io::{Read, Write};
+
+/// Largest frame body (tag and message) accepted by `recv_message`
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Writes the message as one frame in the given format
+pub fn send_message<W: Write>(
+    writer: &mut W,
+    message: &Message,
+    format: SerializationFormat,
+) -> Result<(), MessageError> {
+    let body = message.to_bytes(format)?;
+    let size = body.len() + 1;
+    if size > MAX_FRAME_SIZE {
+        return Err(MessageError::EnvelopeTooLarge {
+            size,
+            limit: MAX_FRAME_SIZE,
+        });
+    }
+
+    let mut frame = Vec::with_capacity(4 + size);
+    frame.extend_from_slice(&(size as u32).to_be_bytes());
+    frame.push(format.tag());
+    frame.extend_from_slice(&body);
+    writer
+        .write_all(&frame)
+        .and_then(|_| writer.flush())
+        .map_err(|e| MessageError::Io(e.kind()))
+}
+
+/// Reads one frame and decodes the message in the format named by its tag
+pub fn recv_message<R: Read>(reader: &mut R) -> Result<Message, MessageError> {
+    let mut length = [0u8; 4];
+    reader
+        .read_exact(&mut length)
+        .map_err(|e| MessageError::Io(e.kind()))?;
+    let size = u32::from_be_bytes(length) as usize;
+    if size == 0 {
+        return Err(MessageError::Serialization("Empty frame".to_string()));
+    }
+    if size > MAX_FRAME_SIZE {
+        return Err(MessageError::EnvelopeTooLarge {
+            size,
+            limit: MAX_FRAME_SIZE,
+        });
+    }
+
+    let mut frame = vec![0u8; size];
+    reader
+        .read_exact(&mut frame)
+        .map_err(|e| MessageError::Io(e.kind()))?;
+    Message::from_bytes(&frame[1..], frame_format(frame[0])?)
+}
+
+/// Splits a complete frame held in memory into its format and serialized message
+pub fn parse_frame(frame: &[u8]) -> Result<(SerializationFormat, &[u8]), MessageError> {
+    if frame.len() < 5 {
+        return Err(MessageError::Serialization("Truncated frame".to_string()));
+    }
+    let size = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+    if size != frame.len() - 4 {
+        return Err(MessageError::Serialization(format!(
+            "Frame length {} does not match the {} bytes after it",
+            size,
+            frame.len() - 4
+        )));
+    }
+    Ok((frame_format(frame[4])?, &frame[5..]))
+}
+
+fn frame_format(tag: u8) -> Result<SerializationFormat, MessageError> {
+    SerializationFormat::from_tag(tag).ok_or_else(|| {
+        MessageError::UnsupportedFormat(format!("Unknown serialization format tag {}", tag))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyPair;
+    use std::io::Cursor;
+
+    fn signed_message(payload: &[u8]) -> Message {
+        let mut message = Message::new_broadcast(payload.to_vec());
+        message.sign(&KeyPair::from_seed(1).private_key).unwrap();
+        message
+    }
+
+    #[test]
+    fn test_frames_carry_their_format() {
+        let mut stream = Vec::new();
+        send_message(
+            &mut stream,
+            &signed_message(b"JSON"),
+            SerializationFormat::Json,
+        )
+        .unwrap();
+        #[cfg(feature = "cbor")]
+        send_message(
+            &mut stream,
+            &signed_message(b"CBOR"),
+            SerializationFormat::Cbor,
+        )
+        .unwrap();
+        #[cfg(feature = "bincode")]
+        send_message(
+            &mut stream,
+            &signed_message(b"bincode"),
+            SerializationFormat::Bincode,
+        )
+        .unwrap();
+
+        let mut loopback = Cursor::new(stream);
+        let json = recv_message(&mut loopback).unwrap();
+        assert_eq!(json.payload, b"JSON");
+        assert!(json.verify());
+        #[cfg(feature = "cbor")]
+        {
+            let cbor = recv_message(&mut loopback).unwrap();
+            assert_eq!(cbor.payload, b"CBOR");
+            assert!(cbor.verify());
+        }
+        #[cfg(feature = "bincode")]
+        {
+            let bincode = recv_message(&mut loopback).unwrap();
+            assert_eq!(bincode.payload, b"bincode");
+            assert!(bincode.verify());
+        }
+        assert_eq!(
+            recv_message(&mut loopback).err(),
+            Some(MessageError::Io(std::io::ErrorKind::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_unknown_format_tag_is_rejected() {
+        let mut stream = Vec::new();
+        send_message(
+            &mut stream,
+            &signed_message(b"Tagged"),
+            SerializationFormat::Json,
+        )
+        .unwrap();
+        stream[4] = 0xee;
+
+        assert!(matches!(
+            recv_message(&mut Cursor::new(stream)),
+            Err(MessageError::UnsupportedFormat(e)) if e.contains("238")
+        ));
+    }
+}